@@ -1,43 +1,45 @@
+extern crate hydramq;
+
+use hydramq::message::message::{ListBuilder, MapBuilder, Message, MessageBuilder};
+use hydramq::message::message_set::MessageSet;
+use hydramq::topic::{FileSegment, Segment};
+
+const MESSAGES: u32 = 1_000_000;
+const BATCH_SIZE: u32 = 1_000;
+
 fn main() {
-    println!("Hello!");
+    let segment = FileSegment::with_directory("example");
+    for _ in 0..MESSAGES / BATCH_SIZE {
+        let mut builder = MessageSet::starting_at(u64::from(segment.size()));
+        for _ in 0..BATCH_SIZE {
+            builder = builder.append(example());
+        }
+        segment.append(&builder.build());
+    }
 }
 
-//extern crate hydramq;
-//
-//use hydramq::message::{Message, Map, List};
-//use hydramq::topic::{FileSegment, Segment};
-//
-//fn main() {
-//
-//    let segment = FileSegment::with_directory("example");
-//    let message = example();
-//    for _i in 0..1_000_000 {
-//        segment.write(&message);
-//    }
-//}
-//
-//fn example() -> Message {
-//    Message::new()
-//        .with_property("fname", "Jimmie")
-//        .with_property("lname", "Fulton")
-//        .with_property("age", 42)
-//        .with_property("temp", 98.6)
-//        .with_property("vehicles", List::new()
-//            .append("Aprilia")
-//            .append("Infiniti")
-//            .build()
-//        )
-//        .with_property("siblings",
-//                       Map::new()
-//                           .insert("brothers",
-//                                   List::new()
-//                                       .append("Jason").build()
-//                           )
-//                           .insert("sisters",
-//                                   List::new()
-//                                       .append("Laura")
-//                                       .append("Sariah")
-//                                       .build()
-//                           ).build()
-//        ).build()
-//}
\ No newline at end of file
+fn example<'a>() -> Message<'a> {
+    MessageBuilder::new()
+        .with_header("fname", "Jimmie")
+        .with_header("lname", "Fulton")
+        .with_header("age", 42)
+        .with_header("temp", 98.6)
+        .with_header("vehicles", ListBuilder::new()
+            .push("Aprilia")
+            .push("Infiniti")
+            .build()
+        )
+        .with_header("siblings",
+                     MapBuilder::new()
+                         .insert("brothers",
+                                 ListBuilder::new()
+                                     .push("Jason").build()
+                         )
+                         .insert("sisters",
+                                 ListBuilder::new()
+                                     .push("Laura")
+                                     .push("Sariah")
+                                     .build()
+                         ).build()
+        ).build()
+}
@@ -1,57 +1,63 @@
 extern crate hydramq;
 extern crate bytes;
 
-//use bytes::{IntoBuf};
+use bytes::BytesMut;
 
-use hydramq::message::{Message, List, Map};
+use hydramq::codec::Codec;
+use hydramq::codec::compact::CompactMessageCodec;
+use hydramq::codec::message_codec::BinaryMessageCodec;
+use hydramq::codec::message_pack::MessagePackCodec;
+use hydramq::message::message::{Message, MessageBuilder, ListBuilder, MapBuilder};
+
+use std::time::Instant;
+
+const ITERATIONS: u32 = 1_000_000;
 
 fn main() {
+    let message = example();
+
+    run("binary", &BinaryMessageCodec::new(), &message);
+    run("compact", &CompactMessageCodec::new(), &message);
+    run("msgpack", &MessagePackCodec::new(), &message);
+}
 
-//    let message = example();
-//
-//    for _ in 0..1_000_000 {
-//        let _ = encode_decode(&message);
-//    }
+fn run<C: Codec>(name: &str, codec: &C, message: &Message) {
+    let now = Instant::now();
+    for _ in 0..ITERATIONS {
+        let _ = encode_decode(codec, message);
+    }
+    println!("{}: {} bytes, {} round trips in {:?}",
+             name, codec.encoded_len(message), ITERATIONS, now.elapsed());
 }
 
-//fn encode_decode(message: &Message) -> Message {
-//    let buffer = encode(message);
-//    decode(buffer)
-//}
-//
-//fn encode(message: &Message) -> bytes::BytesMut {
-//    let mut buffer = bytes::BytesMut::with_capacity(300);
-//    hydramq::codec::encode_message(&message, &mut buffer);
-//    buffer
-//}
-//
-//fn decode(buffer: bytes::BytesMut) -> Message {
-//    let mut bytes = buffer.freeze().into_buf();
-//    hydramq::codec::decode_message(&mut bytes)
-//}
-//
-//fn example() -> Message {
-//    Message::new()
-//        .with_property("fname", "Jimmie")
-//        .with_property("lname", "Fulton")
-//        .with_property("age", 42)
-//        .with_property("temp", 96.8)
-//        .with_property("vehicles", List::new()
-//            .append("Aprilia")
-//            .append("Infiniti")
-//            .build()
-//        )
-//        .with_property("siblings",
-//                       Map::new()
-//                           .insert("brothers",
-//                                   List::new()
-//                                       .append("Jason").build()
-//                           )
-//                           .insert("sisters",
-//                                   List::new()
-//                                       .append("Laura")
-//                                       .append("Sariah")
-//                                       .build()
-//                           ).build()
-//        ).build()
-//}
\ No newline at end of file
+fn encode_decode<'a, C: Codec>(codec: &C, message: &Message) -> Message<'a> {
+    let mut buffer = BytesMut::with_capacity(codec.encoded_len(message));
+    codec.encode(message, &mut buffer);
+    codec.decode(&buffer).expect("Error decoding message")
+}
+
+fn example() -> Message<'static> {
+    MessageBuilder::new()
+        .with_header("fname", "Jimmie")
+        .with_header("lname", "Fulton")
+        .with_header("age", 42)
+        .with_header("temp", 96.8)
+        .with_header("vehicles", ListBuilder::new()
+            .push("Aprilia")
+            .push("Infiniti")
+            .build()
+        )
+        .with_header("siblings",
+                     MapBuilder::new()
+                         .insert("brothers",
+                                 ListBuilder::new()
+                                     .push("Jason").build()
+                         )
+                         .insert("sisters",
+                                 ListBuilder::new()
+                                     .push("Laura")
+                                     .push("Sariah")
+                                     .build()
+                         ).build()
+        ).build()
+}
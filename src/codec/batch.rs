@@ -0,0 +1,567 @@
+//! Binary encoding of message batches with a shared dictionary of header keys.
+//!
+//! Messages in a batch usually repeat the same header keys.  A batch writes every distinct
+//! string header key once, up front, and each message then refers to its keys by index:
+//!
+//! | contents                                                  |
+//! |-----------------------------------------------------------|
+//! | dictionary length, `i32`                                  |
+//! | each key as an `i32` length followed by its UTF-8 bytes   |
+//! | message count, `i32`                                      |
+//! | each message                                              |
+//!
+//! Messages use the binary format, except that a header key in the dictionary is written as
+//! key tag `3` followed by its index as a varint.  Keys of nested maps are written as usual.
+//! Batch messages are never compressed.
+//!
+//! A `MessageSet` is encoded the same way, preceded by its offsets and timestamps:
+//!
+//! | contents                                                  |
+//! |-----------------------------------------------------------|
+//! | base offset, `u64`                                        |
+//! | base timestamp, as in the binary format                   |
+//! | message count, `i32`                                      |
+//! | the key dictionary                                        |
+//! | each message, preceded by its offset delta as a varint    |
+//!
+//! Offset deltas count up from zero, as the offsets of a set are consecutive.  The base
+//! timestamp is that of the first message with one, or the epoch if none has a timestamp.  A
+//! message's timestamp is written relative to it, as a zigzag varint of the difference in
+//! seconds followed by a varint of the nanoseconds.
+//!
+//! Both formats are for the wire.  Storage does not use them: `Segment::append` writes each
+//! message of a set on its own, with the segment's codec, so that every message can still be
+//! read by its offset.
+
+use bytes::{BufMut, BytesMut};
+use chrono::{TimeZone, UTC};
+use codec::buf::{DecodeBuf, SliceBuf};
+use codec::error::DecodeError;
+use codec::limits::{DecodeLimits, Limit};
+use codec::message_codec::{BinaryMessageCodec, MessageDecoder, MessageEncoder};
+use codec::util;
+use codec::varint::{decode_varint, encode_varint, zigzag_decode_i64, zigzag_encode_i64};
+use message::message::{Key, Message, Timestamp};
+use message::message_set::MessageSet;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Cursor;
+
+/// Key tag of a header key that refers to the batch dictionary.
+pub const DICTIONARY_KEY_TAG: u8 = 3;
+
+pub fn encode_batch(messages: &[Message]) -> BytesMut {
+    let mut buffer = Vec::new();
+    let codec = BatchCodec::new();
+    codec.encode_batch(messages, &mut buffer);
+    BytesMut::from(buffer)
+}
+
+pub fn decode_batch<'a, T: AsRef<[u8]>>(buffer: T) -> Result<Vec<Message<'a>>, DecodeError> {
+    let codec = BatchCodec::new();
+    codec.decode_batch(&mut Cursor::new(buffer))
+}
+
+/// Decodes a batch whose strings, bytes and string keys borrow from `buffer` rather than
+/// being copied out of it.
+pub fn decode_batch_borrowed<'a>(buffer: &'a [u8]) -> Result<Vec<Message<'a>>, DecodeError> {
+    let codec = BatchCodec::new();
+    codec.decode_batch(&mut SliceBuf::new(buffer))
+}
+
+pub fn encode_message_set(message_set: &MessageSet) -> BytesMut {
+    let mut buffer = Vec::new();
+    let codec = BatchCodec::new();
+    codec.encode_message_set(message_set, &mut buffer);
+    BytesMut::from(buffer)
+}
+
+pub fn decode_message_set<'a, T: AsRef<[u8]>>(buffer: T) -> Result<MessageSet<'a>, DecodeError> {
+    let codec = BatchCodec::new();
+    codec.decode_message_set(&mut Cursor::new(buffer))
+}
+
+pub fn decode_message_set_borrowed<'a>(buffer: &'a [u8]) -> Result<MessageSet<'a>, DecodeError> {
+    let codec = BatchCodec::new();
+    codec.decode_message_set(&mut SliceBuf::new(buffer))
+}
+
+/// The string header keys of a batch, in order of first appearance.
+#[derive(Debug, Clone, Default)]
+pub struct KeyDictionary<'m> {
+    keys: Vec<&'m str>,
+    indexes: HashMap<&'m str, u32>,
+}
+
+impl<'m> KeyDictionary<'m> {
+    pub fn new() -> KeyDictionary<'m> {
+        KeyDictionary {
+            keys: Vec::new(),
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Builds the dictionary of every string header key in `messages`.
+    pub fn from_messages<'a: 'm>(messages: &'m [Message<'a>]) -> KeyDictionary<'m> {
+        let mut dictionary = KeyDictionary::new();
+        for message in messages {
+            for (key, _) in message.headers().iter() {
+                if let Key::Str(ref key) = key {
+                    dictionary.insert(key);
+                }
+            }
+        }
+        dictionary
+    }
+
+    /// Adds `key` if it is not already present, returning its index.
+    pub fn insert(&mut self, key: &'m str) -> u32 {
+        if let Some(&index) = self.indexes.get(key) {
+            return index;
+        }
+        let index = self.keys.len() as u32;
+        self.keys.push(key);
+        self.indexes.insert(key, index);
+        index
+    }
+
+    pub fn index_of(&self, key: &str) -> Option<u32> {
+        self.indexes.get(key).cloned()
+    }
+
+    pub fn keys(&self) -> &[&'m str] {
+        &self.keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchCodec {
+    codec: BinaryMessageCodec,
+}
+
+impl BatchCodec {
+    pub fn new() -> BatchCodec {
+        BatchCodec {
+            codec: BinaryMessageCodec::new(),
+        }
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> BatchCodec {
+        BatchCodec {
+            codec: BinaryMessageCodec::with_limits(limits),
+        }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        self.codec.limits()
+    }
+
+    pub fn encode_batch<B: BufMut>(&self, messages: &[Message], buffer: &mut B) {
+        let dictionary = KeyDictionary::from_messages(messages);
+        self.encode_dictionary(&dictionary, buffer);
+        buffer.put_i32_be(messages.len() as i32);
+        for message in messages {
+            self.encode_message(&dictionary, None, message, buffer);
+        }
+    }
+
+    pub fn decode_batch<'a, B>(&self, buffer: &mut B) -> Result<Vec<Message<'a>>, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let dictionary = self.decode_dictionary(buffer)?;
+        let count = self.codec.decode_len(buffer, Limit::CollectionLength)?;
+        // The count comes from the input, and each message is far larger decoded than encoded,
+        // so messages are not preallocated.
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            messages.push(self.decode_message(&dictionary, None, buffer)?);
+        }
+        Ok(messages)
+    }
+
+    pub fn encode_message_set<B: BufMut>(&self, message_set: &MessageSet, buffer: &mut B) {
+        let messages = message_set.messages();
+        let base_timestamp = messages.iter()
+            .filter_map(|message| message.timestamp())
+            .next()
+            .unwrap_or_else(|| UTC.timestamp(0, 0));
+        buffer.put_u64_be(message_set.index());
+        self.codec.encode_timestamp(base_timestamp, buffer);
+        buffer.put_i32_be(messages.len() as i32);
+
+        let dictionary = KeyDictionary::from_messages(messages);
+        self.encode_dictionary(&dictionary, buffer);
+        for (delta, message) in messages.iter().enumerate() {
+            encode_varint(delta as u64, buffer);
+            self.encode_message(&dictionary, Some(base_timestamp), message, buffer);
+        }
+    }
+
+    pub fn decode_message_set<'a, B>(&self, buffer: &mut B) -> Result<MessageSet<'a>, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        buffer.require(8)?;
+        let index = buffer.get_u64_be();
+        let base_timestamp = self.codec.decode_timestamp(buffer)?;
+        let count = self.codec.decode_len(buffer, Limit::CollectionLength)?;
+
+        let dictionary = self.decode_dictionary(buffer)?;
+        let mut messages = Vec::new();
+        for expected in 0..count as u64 {
+            let offset = buffer.position();
+            let delta = decode_varint(buffer)?;
+            if delta != expected {
+                return Err(DecodeError::UnexpectedOffsetDelta { offset, expected, actual: delta });
+            }
+            messages.push(self.decode_message(&dictionary, Some(base_timestamp), buffer)?);
+        }
+        Ok(MessageSet::new(index, messages))
+    }
+
+    pub(crate) fn encode_dictionary<B: BufMut>(&self, dictionary: &KeyDictionary, buffer: &mut B) {
+        buffer.put_i32_be(dictionary.len() as i32);
+        for key in dictionary.keys() {
+            buffer.put_i32_be(key.len() as i32);
+            buffer.put_slice(key.as_bytes());
+        }
+    }
+
+    pub(crate) fn decode_dictionary<'a, B>(
+        &self,
+        buffer: &mut B,
+    ) -> Result<Vec<Cow<'a, str>>, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let count = self.codec.decode_len(buffer, Limit::CollectionLength)?;
+        let mut keys = Vec::new();
+        for _ in 0..count {
+            let len = self.codec.decode_len(buffer, Limit::BytesLength)?;
+            keys.push(buffer.take_str(len)?);
+        }
+        Ok(keys)
+    }
+
+    /// Encodes a single message of a batch, writing header keys found in `dictionary` by index,
+    /// and its timestamp relative to `base_timestamp` if given.
+    pub(crate) fn encode_message<B: BufMut>(
+        &self,
+        dictionary: &KeyDictionary,
+        base_timestamp: Option<Timestamp>,
+        message: &Message,
+        buffer: &mut B,
+    ) {
+        let codec = &self.codec;
+        let mut flags = util::Flags::empty();
+        flags.set(util::Flags::HAS_TIMESTAMP, message.timestamp().is_some());
+        flags.set(util::Flags::HAS_EXPIRATION, message.expiration().is_some());
+        flags.set(util::Flags::HAS_CORRELATION_ID, message.correlation_id().is_some());
+        flags.set(util::Flags::HAS_HEADERS, !message.headers().is_empty());
+        flags.set(util::Flags::HAS_BODY, message.body().is_some());
+        codec.encode_i32(flags.bits(), buffer);
+
+        match (message.timestamp(), base_timestamp) {
+            (Some(timestamp), Some(base)) => {
+                let delta = timestamp.timestamp().wrapping_sub(base.timestamp());
+                encode_varint(zigzag_encode_i64(delta), buffer);
+                encode_varint(u64::from(timestamp.timestamp_subsec_nanos()), buffer);
+            }
+            (Some(timestamp), None) => codec.encode_timestamp(timestamp, buffer),
+            (None, _) => (),
+        }
+
+        if let Some(expiration) = message.expiration() {
+            codec.encode_timestamp(expiration, buffer);
+        }
+
+        if let Some(correlation_id) = message.correlation_id() {
+            codec.encode_uuid(correlation_id, buffer);
+        }
+
+        if !message.headers().is_empty() {
+            codec.encode_i32(message.headers().len() as i32, buffer);
+            for (key, value) in message.headers().iter() {
+                let index = match key {
+                    Key::Str(ref key) => dictionary.index_of(key),
+                    Key::I32(_) => None,
+                };
+                match index {
+                    Some(index) => {
+                        buffer.put_u8(DICTIONARY_KEY_TAG);
+                        encode_varint(u64::from(index), buffer);
+                    }
+                    None => codec.encode_key(key, buffer),
+                }
+                codec.encode_value(value, buffer);
+            }
+        }
+
+        if let Some(body) = message.body() {
+            codec.encode_value(body, buffer);
+        }
+    }
+
+    /// Decodes a single message of a batch, resolving dictionary keys against `dictionary`, and
+    /// its timestamp against `base_timestamp` if given.
+    pub(crate) fn decode_message<'a, B>(
+        &self,
+        dictionary: &[Cow<'a, str>],
+        base_timestamp: Option<Timestamp>,
+        buffer: &mut B,
+    ) -> Result<Message<'a>, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let codec = &self.codec;
+        let mut message = Message::new();
+
+        let flags_offset = buffer.position();
+        let bits = codec.decode_i32(buffer)?;
+        let compressed = util::Flags::HEADERS_COMPRESSED | util::Flags::BODY_COMPRESSED;
+        let flags = util::Flags::from_bits(bits)
+            .filter(|flags| !flags.intersects(compressed))
+            .ok_or(DecodeError::InvalidFlags {
+                offset: flags_offset,
+                bits,
+            })?;
+
+        if flags.contains(util::Flags::HAS_TIMESTAMP) {
+            let timestamp = match base_timestamp {
+                Some(base) => self.decode_timestamp_delta(base, buffer)?,
+                None => codec.decode_timestamp(buffer)?,
+            };
+            message.set_timestamp(Some(timestamp));
+        }
+
+        if flags.contains(util::Flags::HAS_EXPIRATION) {
+            message.set_expiration(Some(codec.decode_timestamp(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_CORRELATION_ID) {
+            message.set_correlation_id(Some(codec.decode_uuid(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_HEADERS) {
+            codec.limits().check(Limit::Depth, 1, buffer.position())?;
+            let count = codec.decode_len(buffer, Limit::CollectionLength)?;
+            for _ in 0..count {
+                let key = if buffer.bytes().first() == Some(&DICTIONARY_KEY_TAG) {
+                    buffer.advance(1);
+                    let offset = buffer.position();
+                    let index = decode_varint(buffer)?;
+                    let key = dictionary.get(index as usize);
+                    let key = key.ok_or(DecodeError::UnknownKeyIndex { offset, index })?;
+                    Key::Str(key.clone())
+                } else {
+                    codec.decode_key(buffer)?
+                };
+                let value = codec.decode_value(buffer, 1)?;
+                message.headers_mut().insert(key, value);
+            }
+        }
+
+        if flags.contains(util::Flags::HAS_BODY) {
+            message.set_body(Some(codec.decode_value(buffer, 0)?));
+        }
+
+        Ok(message)
+    }
+
+    fn decode_timestamp_delta<'a, B>(
+        &self,
+        base: Timestamp,
+        buffer: &mut B,
+    ) -> Result<Timestamp, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let offset = buffer.position();
+        let seconds = base.timestamp().wrapping_add(zigzag_decode_i64(decode_varint(buffer)?));
+        let nanos = decode_varint(buffer)?;
+        let invalid = DecodeError::InvalidTimestamp {
+            offset,
+            seconds,
+            nanos: nanos as i32,
+        };
+        if nanos > u64::from(u32::MAX) {
+            return Err(invalid);
+        }
+        UTC.timestamp_opt(seconds, nanos as u32).single().ok_or(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::message_codec::encode_message;
+    use message::message::{MapBuilder, MessageBuilder};
+    use uuid::Uuid;
+
+    fn batch<'a>() -> Vec<Message<'a>> {
+        (0..10)
+            .map(|index| {
+                MessageBuilder::new()
+                    .with_timestamp(UTC.timestamp(1_500_000_000 + index, 0))
+                    .with_correlation_id(Uuid::nil())
+                    .with_header("content-type", "application/json")
+                    .with_header("tenant", "acme")
+                    .with_header(7, index)
+                    .with_header("nested", MapBuilder::new().insert("tenant", "inner").build())
+                    .with_body(format!("body {}", index))
+                    .build()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dictionary_keys() {
+        let messages = batch();
+        let dictionary = KeyDictionary::from_messages(&messages);
+        assert_eq!(dictionary.keys(), &["content-type", "tenant", "nested"]);
+        assert_eq!(dictionary.index_of("tenant"), Some(1));
+        assert_eq!(dictionary.index_of("missing"), None);
+    }
+
+    #[test]
+    fn batch_round_trip() {
+        let bytes = encode_batch(&batch());
+        assert_eq!(decode_batch(&bytes), Ok(batch()));
+        assert_eq!(decode_batch_borrowed(&bytes), Ok(batch()));
+    }
+
+    #[test]
+    fn empty_batch() {
+        let bytes = encode_batch(&[]);
+        assert_eq!(&bytes[..], &[0, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert_eq!(decode_batch(&bytes), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn smaller_than_individual_messages() {
+        let messages = batch();
+        let individual: usize = messages.iter().map(|message| encode_message(message).len()).sum();
+        let dictionary = 4 + (4 + 12) + (4 + 6) + (4 + 6);
+        let saved_per_message = (4 + 12 - 1) + (4 + 6 - 1) + (4 + 6 - 1);
+        let bytes = encode_batch(&messages);
+        assert_eq!(bytes.len(), individual + dictionary + 4 - 10 * saved_per_message);
+    }
+
+    #[test]
+    fn decode_unknown_key_index() {
+        let message = MessageBuilder::new().with_header("key", 1).build();
+        let mut bytes = encode_batch(&[message]);
+        // Dictionary of one key, message count, flags, header count, then the key index.
+        let index_offset = 4 + 4 + 3 + 4 + 4 + 4 + 1;
+        assert_eq!(bytes[index_offset - 1], DICTIONARY_KEY_TAG);
+        bytes[index_offset] = 5;
+        assert_eq!(
+            decode_batch(&bytes),
+            Err(DecodeError::UnknownKeyIndex { offset: index_offset, index: 5 })
+        );
+    }
+
+    #[test]
+    fn decode_count_beyond_buffer() {
+        let codec = BatchCodec::with_limits(DecodeLimits::unlimited());
+        let huge_dictionary = [0x7f, 0xff, 0xff, 0xff];
+        let huge_batch = [0, 0, 0, 0, 0x7f, 0xff, 0xff, 0xff];
+        for bytes in [&huge_dictionary[..], &huge_batch[..]].iter() {
+            match codec.decode_batch(&mut SliceBuf::new(bytes)) {
+                Err(DecodeError::Truncated { .. }) => (),
+                other => panic!("Expected truncation, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_truncated_batch() {
+        let bytes = encode_batch(&batch());
+        for len in 0..bytes.len() {
+            match decode_batch(&bytes[..len]) {
+                Err(DecodeError::Truncated { .. }) => (),
+                other => panic!("Expected truncation at length {}, got {:?}", len, other),
+            }
+        }
+    }
+
+    fn message_set<'a>() -> MessageSet<'a> {
+        let mut builder = MessageSet::starting_at(1_000);
+        for message in batch() {
+            builder = builder.append(message);
+        }
+        builder
+            .append(MessageBuilder::new().with_body("no timestamp").build())
+            .append(
+                MessageBuilder::new()
+                    .with_timestamp(UTC.timestamp(1_400_000_000, 999_999_999))
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn message_set_round_trip() {
+        let bytes = encode_message_set(&message_set());
+        assert_eq!(decode_message_set(&bytes), Ok(message_set()));
+        assert_eq!(decode_message_set_borrowed(&bytes), Ok(message_set()));
+
+        let empty = MessageSet::starting_at(7).build();
+        let bytes = encode_message_set(&empty);
+        assert_eq!(bytes.len(), 8 + 12 + 4 + 4);
+        assert_eq!(decode_message_set(&bytes), Ok(empty));
+    }
+
+    #[test]
+    fn message_set_layout() {
+        let message = MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 5))
+            .build();
+        let bytes = encode_message_set(&MessageSet::starting_at(2).append(message).build());
+        let mut expected = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        expected.extend_from_slice(&[0, 0, 0, 0, 0x59, 0x68, 0x2f, 0x00, 0, 0, 0, 5]);
+        expected.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+        // Offset delta, flags, then seconds and nanoseconds relative to the base timestamp.
+        expected.extend_from_slice(&[0, 0, 0, 0, 1, 0, 5]);
+        assert_eq!(&bytes[..], &expected[..]);
+    }
+
+    #[test]
+    fn decode_unexpected_offset_delta() {
+        let message_set = MessageSet::starting_at(0).append(Message::new()).build();
+        let mut bytes = encode_message_set(&message_set);
+        let delta_offset = 8 + 12 + 4 + 4;
+        bytes[delta_offset] = 3;
+        assert_eq!(
+            decode_message_set(&bytes),
+            Err(DecodeError::UnexpectedOffsetDelta { offset: delta_offset, expected: 0, actual: 3 })
+        );
+    }
+
+    #[test]
+    fn decode_message_set_count_beyond_buffer() {
+        let codec = BatchCodec::with_limits(DecodeLimits::unlimited());
+        let mut bytes = encode_message_set(&MessageSet::starting_at(0).build()).to_vec();
+        bytes[20..24].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff]);
+        let decoded = codec.decode_message_set(&mut SliceBuf::new(&bytes));
+        match decoded {
+            Err(DecodeError::Truncated { .. }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_truncated_message_set() {
+        let bytes = encode_message_set(&message_set());
+        for len in 0..bytes.len() {
+            match decode_message_set(&bytes[..len]) {
+                Err(DecodeError::Truncated { .. }) => (),
+                other => panic!("Expected truncation at length {}, got {:?}", len, other),
+            }
+        }
+    }
+}
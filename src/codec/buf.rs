@@ -0,0 +1,141 @@
+use bytes::Buf;
+use codec::error::DecodeError;
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::str;
+
+/// A buffer the decoder can read from.
+///
+/// Besides the usual `Buf` accessors, a `DecodeBuf` knows its position relative to the start
+/// of the encoded message and decides whether variable length values (strings and bytes) are
+/// copied out of the buffer or borrowed from it.
+pub trait DecodeBuf<'a>: Buf {
+    fn position(&self) -> usize;
+
+    fn take_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>, DecodeError>;
+
+    fn require(&self, needed: usize) -> Result<(), DecodeError> {
+        let remaining = self.remaining();
+        if remaining < needed {
+            return Err(DecodeError::Truncated {
+                offset: self.position(),
+                needed,
+                remaining,
+            });
+        }
+        Ok(())
+    }
+
+    fn take_str(&mut self, len: usize) -> Result<Cow<'a, str>, DecodeError> {
+        let offset = self.position();
+        match self.take_bytes(len)? {
+            Cow::Borrowed(bytes) => str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|_| DecodeError::InvalidUtf8 { offset }),
+            Cow::Owned(bytes) => String::from_utf8(bytes)
+                .map(Cow::Owned)
+                .map_err(|_| DecodeError::InvalidUtf8 { offset }),
+        }
+    }
+}
+
+/// Copies strings and bytes out of the underlying buffer, so decoded values own their data.
+impl<'a, T> DecodeBuf<'a> for Cursor<T>
+where
+    T: AsRef<[u8]>,
+{
+    fn position(&self) -> usize {
+        Cursor::position(self) as usize
+    }
+
+    fn take_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>, DecodeError> {
+        self.require(len)?;
+        let mut bytes = vec![0u8; len];
+        self.copy_to_slice(&mut bytes);
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+/// A buffer over a borrowed slice.  Strings and bytes decoded from a `SliceBuf` borrow
+/// directly from the slice instead of allocating.
+pub struct SliceBuf<'a> {
+    slice: &'a [u8],
+    position: usize,
+}
+
+impl<'a> SliceBuf<'a> {
+    pub fn new(slice: &'a [u8]) -> SliceBuf<'a> {
+        SliceBuf { slice, position: 0 }
+    }
+}
+
+impl<'a> Buf for SliceBuf<'a> {
+    fn remaining(&self) -> usize {
+        self.slice.len() - self.position
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.slice[self.position..]
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining(), "cannot advance past the end of the slice");
+        self.position += cnt;
+    }
+}
+
+impl<'a> DecodeBuf<'a> for SliceBuf<'a> {
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn take_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>, DecodeError> {
+        self.require(len)?;
+        let bytes = &self.slice[self.position..self.position + len];
+        self.position += len;
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+/// The first `len` bytes of another buffer, which is advanced as they are read.  Positions
+/// stay relative to the start of the underlying buffer.
+pub(crate) struct BoundedBuf<'b, B: 'b> {
+    inner: &'b mut B,
+    remaining: usize,
+}
+
+impl<'b, B: Buf> BoundedBuf<'b, B> {
+    pub(crate) fn new(inner: &'b mut B, len: usize) -> BoundedBuf<'b, B> {
+        assert!(len <= inner.remaining(), "cannot bound a buffer beyond its end");
+        BoundedBuf { inner, remaining: len }
+    }
+}
+
+impl<'b, B: Buf> Buf for BoundedBuf<'b, B> {
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn bytes(&self) -> &[u8] {
+        let bytes = self.inner.bytes();
+        &bytes[..bytes.len().min(self.remaining)]
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining, "cannot advance past the end of the bound");
+        self.inner.advance(cnt);
+        self.remaining -= cnt;
+    }
+}
+
+impl<'a, 'b, B: DecodeBuf<'a>> DecodeBuf<'a> for BoundedBuf<'b, B> {
+    fn position(&self) -> usize {
+        self.inner.position()
+    }
+
+    fn take_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>, DecodeError> {
+        self.require(len)?;
+        self.remaining -= len;
+        self.inner.take_bytes(len)
+    }
+}
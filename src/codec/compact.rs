@@ -0,0 +1,391 @@
+//! Compact binary encoding of `Message`.
+//!
+//! The layout follows `BinaryMessageCodec`, field for field and with the same type tags, but
+//! replaces the fixed width integers that dominate small messages:
+//!
+//! * The flags word and every string, bytes, list and map length or count is an unsigned
+//!   LEB128 varint.
+//! * `I32` and `I64` values, the elements of an `I64Array` and `I32` keys are zigzag encoded
+//!   varints.
+//! * A `Duration` is a varint of its seconds followed by a varint of its nanoseconds.
+//!
+//! Floats, UUIDs, timestamps and decimals keep their fixed width big endian form.
+//!
+//! Only those encodings are defined here; the traversal of keys, values and collections is the
+//! one `MessageDecoder` and `MessageEncoder` provide to both formats.
+
+use bytes::{BufMut, BytesMut};
+use codec::buf::{DecodeBuf, SliceBuf};
+use codec::error::DecodeError;
+use codec::limits::{DecodeLimits, Limit};
+use codec::message_codec::{duration, MessageDecoder, MessageEncoder};
+use codec::size_calculator::calculate_compact_message_size;
+use codec::util;
+use codec::Codec;
+use codec::varint::{decode_varint, encode_varint};
+use codec::varint::{zigzag_decode_i32, zigzag_decode_i64, zigzag_encode_i32, zigzag_encode_i64};
+use message::message::Message;
+use std::io::Cursor;
+use std::time::Duration;
+
+pub fn encode_message(message: &Message) -> BytesMut {
+    let size = calculate_compact_message_size(message);
+    let mut buffer = BytesMut::with_capacity(size as usize);
+    let codec = CompactMessageCodec::new();
+    codec.encode_message(message, &mut buffer);
+    buffer
+}
+
+pub fn decode_message<'a, T: AsRef<[u8]>>(buffer: T) -> Result<Message<'a>, DecodeError> {
+    let codec = CompactMessageCodec::new();
+    codec.decode_message(&mut Cursor::new(buffer))
+}
+
+/// Decodes a message whose strings, bytes and string keys borrow from `buffer` rather than
+/// being copied out of it.
+pub fn decode_message_borrowed<'a>(buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+    let codec = CompactMessageCodec::new();
+    codec.decode_message(&mut SliceBuf::new(buffer))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompactMessageCodec {
+    limits: DecodeLimits,
+}
+
+impl CompactMessageCodec {
+    pub fn new() -> CompactMessageCodec {
+        CompactMessageCodec {
+            limits: DecodeLimits::new(),
+        }
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> CompactMessageCodec {
+        CompactMessageCodec { limits }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+}
+
+impl Codec for CompactMessageCodec {
+    fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        buffer.reserve(self.encoded_len(message));
+        self.encode_message(message, buffer);
+    }
+
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut Cursor::new(buffer))
+    }
+
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut SliceBuf::new(buffer))
+    }
+
+    fn encoded_len(&self, message: &Message) -> usize {
+        calculate_compact_message_size(message) as usize
+    }
+}
+
+impl<'a, B> MessageDecoder<'a, B> for CompactMessageCodec
+    where B: DecodeBuf<'a>
+{
+    const MIN_I64_LEN: usize = 1;
+
+    fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    fn decode_message(&self, buffer: &mut B) -> Result<Message<'a>, DecodeError> {
+        let mut message = Message::new();
+
+        let flags_offset = buffer.position();
+        let bits = decode_varint(buffer)?;
+        // Compression is only supported by the fixed width binary format.
+        let compressed = util::Flags::HEADERS_COMPRESSED | util::Flags::BODY_COMPRESSED;
+        let flags = if bits <= i32::MAX as u64 {
+            util::Flags::from_bits(bits as i32).filter(|flags| !flags.intersects(compressed))
+        } else {
+            None
+        };
+        let flags = flags.ok_or(DecodeError::InvalidFlags {
+            offset: flags_offset,
+            bits: bits as i32,
+        })?;
+
+        if flags.contains(util::Flags::HAS_TIMESTAMP) {
+            message.set_timestamp(Some(self.decode_timestamp(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_EXPIRATION) {
+            message.set_expiration(Some(self.decode_timestamp(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_CORRELATION_ID) {
+            message.set_correlation_id(Some(self.decode_uuid(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_HEADERS) {
+            self.limits.check(Limit::Depth, 1, buffer.position())?;
+            let count = self.decode_len(buffer, Limit::CollectionLength)?;
+            for _ in 0..count {
+                let key = self.decode_key(buffer)?;
+                let value = self.decode_value(buffer, 1)?;
+                message.headers_mut().insert(key, value);
+            }
+        }
+
+        if flags.contains(util::Flags::HAS_BODY) {
+            message.set_body(Some(self.decode_value(buffer, 0)?));
+        }
+
+        // The buffer may hold more than this message, so the size is known once it is read.
+        self.limits.check(Limit::MessageSize, buffer.position() - flags_offset, flags_offset)?;
+
+        Ok(message)
+    }
+
+    fn decode_len(&self, buffer: &mut B, limit: Limit) -> Result<usize, DecodeError> {
+        let offset = buffer.position();
+        let length = decode_varint(buffer)?;
+        if length > usize::MAX as u64 {
+            return Err(DecodeError::IntegerOverflow { offset });
+        }
+        self.limits.check(limit, length as usize, offset)?;
+        Ok(length as usize)
+    }
+
+    fn decode_duration(&self, buffer: &mut B) -> Result<Duration, DecodeError> {
+        let offset = buffer.position();
+        let seconds = decode_varint(buffer)?;
+        let nanos = decode_varint(buffer)?;
+        duration(offset, seconds, nanos)
+    }
+
+    fn decode_i32(&self, buffer: &mut B) -> Result<i32, DecodeError> {
+        let offset = buffer.position();
+        let value = decode_varint(buffer)?;
+        if value > u64::from(u32::MAX) {
+            return Err(DecodeError::IntegerOverflow { offset });
+        }
+        Ok(zigzag_decode_i32(value as u32))
+    }
+
+    fn decode_i64(&self, buffer: &mut B) -> Result<i64, DecodeError> {
+        Ok(zigzag_decode_i64(decode_varint(buffer)?))
+    }
+}
+
+impl<'a, B> MessageEncoder<'a, B> for CompactMessageCodec
+    where B: BufMut
+{
+    fn encode_message(&self, message: &Message<'a>, buffer: &mut B) {
+        let mut flags = util::Flags::empty();
+
+        if message.timestamp().is_some() {
+            flags.insert(util::Flags::HAS_TIMESTAMP);
+        }
+
+        if message.expiration().is_some() {
+            flags.insert(util::Flags::HAS_EXPIRATION);
+        }
+
+        if message.correlation_id().is_some() {
+            flags.insert(util::Flags::HAS_CORRELATION_ID);
+        }
+
+        if !message.headers().is_empty() {
+            flags.insert(util::Flags::HAS_HEADERS);
+        }
+
+        if message.body().is_some() {
+            flags.insert(util::Flags::HAS_BODY);
+        }
+
+        encode_varint(flags.bits() as u64, buffer);
+
+        if let Some(timestamp) = message.timestamp() {
+            self.encode_timestamp(timestamp, buffer);
+        }
+
+        if let Some(expiration) = message.expiration() {
+            self.encode_timestamp(expiration, buffer);
+        }
+
+        if let Some(correlation_id) = message.correlation_id() {
+            self.encode_uuid(correlation_id, buffer);
+        }
+
+        if !message.headers().is_empty() {
+            self.encode_map(message.headers(), buffer);
+        }
+
+        if let Some(body) = message.body() {
+            self.encode_value(body, buffer);
+        }
+    }
+
+    fn encode_len(&self, len: usize, buffer: &mut B) {
+        encode_varint(len as u64, buffer);
+    }
+
+    fn encode_duration(&self, value: Duration, buffer: &mut B) {
+        encode_varint(value.as_secs(), buffer);
+        encode_varint(u64::from(value.subsec_nanos()), buffer);
+    }
+
+    fn encode_i32(&self, value: i32, buffer: &mut B) {
+        encode_varint(u64::from(zigzag_encode_i32(value)), buffer);
+    }
+
+    fn encode_i64(&self, value: i64, buffer: &mut B) {
+        encode_varint(zigzag_encode_i64(value), buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use codec::message_codec;
+    use codec::size_calculator::calculate_message_size;
+    use message::message::{Decimal, ListBuilder, MapBuilder, MessageBuilder, Value};
+    use std::borrow::Cow;
+    use uuid::Uuid;
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 123))
+            .with_expiration(UTC.timestamp(1_500_000_060, 0))
+            .with_correlation_id(Uuid::nil())
+            .with_header("key", "value")
+            .with_header(-7, i64::MIN)
+            .with_header(
+                "map",
+                MapBuilder::new()
+                    .insert("age", 43)
+                    .insert("temp", 98.6)
+                    .insert("big", i32::MAX)
+                    .insert("when", UTC.timestamp(0, 0))
+                    .build(),
+            )
+            .with_header(
+                "list",
+                ListBuilder::new()
+                    .push(Value::Null)
+                    .push(true)
+                    .push(1.5f32)
+                    .push(Value::Bytes(Cow::Borrowed(&[1u8, 2, 3][..])))
+                    .push(Value::Uuid(Uuid::nil()))
+                    .push(Decimal::new(-12345, 2))
+                    .push(Duration::new(90, 500))
+                    .push(vec![-1i64, i64::MAX])
+                    .push(vec![0.5f64])
+                    .build(),
+            )
+            .with_body("x".repeat(300))
+            .build()
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let bytes = encode_message(&example());
+        assert_eq!(decode_message(&bytes), Ok(example()));
+        assert_eq!(decode_message_borrowed(&bytes), Ok(example()));
+    }
+
+    #[test]
+    fn size_matches_encoding() {
+        let messages = vec![Message::new(), MessageBuilder::new().with_body(-1).build(), example()];
+        for message in messages {
+            let bytes = encode_message(&message);
+            assert_eq!(bytes.len(), calculate_compact_message_size(&message) as usize);
+        }
+    }
+
+    #[test]
+    fn smaller_than_binary_format() {
+        let message = MessageBuilder::new()
+            .with_header("key", "value")
+            .with_header("attempt", 1)
+            .with_body("body")
+            .build();
+        let bytes = encode_message(&message);
+        assert_eq!(bytes.len(), 1 + 1 + 1 + 4 + 1 + 6 + 1 + 8 + 1 + 1 + 1 + 5);
+        assert!(bytes.len() < calculate_message_size(&message) as usize);
+        assert!(bytes.len() < message_codec::encode_message(&message).len());
+    }
+
+    #[test]
+    fn decode_truncated_message() {
+        let bytes = encode_message(&example());
+        for len in 0..bytes.len() {
+            match decode_message(&bytes[..len]) {
+                Err(DecodeError::Truncated { offset, .. }) => assert!(offset <= len),
+                other => panic!("Expected truncation at length {}, got {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_invalid_flags() {
+        assert_eq!(
+            decode_message([0x40]),
+            Err(DecodeError::InvalidFlags { offset: 0, bits: 0x40 })
+        );
+    }
+
+    #[test]
+    fn decode_overlong_length() {
+        let mut bytes = vec![util::Flags::HAS_BODY.bits() as u8, 1];
+        bytes.extend_from_slice(&[0xff; 10]);
+        bytes.push(0x01);
+        assert_eq!(decode_message(&bytes[..]), Err(DecodeError::InvalidVarint { offset: 2 }));
+    }
+
+    #[test]
+    fn decode_i32_overflow() {
+        let bytes = [util::Flags::HAS_BODY.bits() as u8, 2, 0x80, 0x80, 0x80, 0x80, 0x10];
+        assert_eq!(decode_message(&bytes[..]), Err(DecodeError::IntegerOverflow { offset: 2 }));
+    }
+
+    #[test]
+    fn decode_invalid_duration() {
+        let bytes = [util::Flags::HAS_BODY.bits() as u8, 13, 1, 0x80, 0x94, 0xeb, 0xdc, 0x03];
+        assert_eq!(
+            decode_message(&bytes[..]),
+            Err(DecodeError::InvalidDuration { offset: 2, seconds: 1, nanos: 1_000_000_000 })
+        );
+    }
+
+    #[test]
+    fn decode_array_count_beyond_buffer() {
+        let codec = CompactMessageCodec::with_limits(DecodeLimits::unlimited());
+        for &(tag, needed) in [(14u8, 1), (15, 8)].iter() {
+            let mut bytes = vec![util::Flags::HAS_BODY.bits() as u8, tag];
+            encode_varint(u64::from(u32::MAX), &mut bytes);
+            assert_eq!(
+                codec.decode(&bytes),
+                Err(DecodeError::Truncated { offset: 7, needed, remaining: 0 })
+            );
+        }
+    }
+
+    #[test]
+    fn decode_exceeding_collection_limit() {
+        let limits = DecodeLimits::new().with_max_collection_len(1);
+        let codec = CompactMessageCodec::with_limits(limits);
+        let message = MessageBuilder::new().with_header("a", 1).with_header("b", 2).build();
+        let bytes = encode_message(&message);
+        assert_eq!(
+            MessageDecoder::decode_message(&codec, &mut Cursor::new(&bytes)),
+            Err(DecodeError::LimitExceeded {
+                offset: 1,
+                limit: Limit::CollectionLength,
+                actual: 2,
+                max: 1,
+            })
+        );
+    }
+}
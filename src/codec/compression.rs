@@ -0,0 +1,199 @@
+//! Compression of message sections in the binary format.
+//!
+//! When enabled, the encoded headers and body are each compressed if their encoding reaches
+//! the configured threshold and compression actually makes them smaller.  A compressed section
+//! is flagged with `Flags::HEADERS_COMPRESSED` or `Flags::BODY_COMPRESSED` and replaced by a
+//! block:
+//!
+//! | bytes | contents                                   |
+//! |-------|--------------------------------------------|
+//! | 1     | `Algorithm` id                             |
+//! | 4     | length of the uncompressed section, `i32`  |
+//! | 4     | length of the compressed data, `i32`       |
+//! | n     | compressed data                            |
+//!
+//! Decode errors inside a compressed section report offsets from the start of the uncompressed
+//! section.
+
+use flate2;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use lz4_flex::block as lz4;
+use std::io::{Read, Write};
+
+/// A compression algorithm and its id on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// LZ4 block format.  Fast, with a moderate ratio.
+    Lz4,
+    /// Raw deflate.  Slower, with a better ratio.
+    Deflate,
+}
+
+impl Algorithm {
+    pub fn from_u8(value: u8) -> Option<Algorithm> {
+        match value {
+            1 => Some(Algorithm::Lz4),
+            2 => Some(Algorithm::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Algorithm::Lz4 => 1,
+            Algorithm::Deflate => 2,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Lz4 => lz4::compress(data),
+            Algorithm::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+                encoder.write_all(data).expect("Error writing deflate stream to buffer");
+                encoder.finish().expect("Error writing deflate stream to buffer")
+            }
+        }
+    }
+
+    /// Decompresses `data`, returning `None` if it is corrupt or does not decompress to exactly
+    /// `len` bytes.  `len` comes from the wire, so memory is only committed to output the data
+    /// can actually produce.
+    pub fn decompress(self, data: &[u8], len: usize) -> Option<Vec<u8>> {
+        let mut output = match self {
+            Algorithm::Lz4 => {
+                // The block format has no streaming decoder, but it cannot expand its input
+                // by more than `LZ4_MAX_RATIO`.
+                if len > data.len().saturating_mul(LZ4_MAX_RATIO) {
+                    return None;
+                }
+                let mut output = vec![0u8; len];
+                let written = lz4::decompress_into(data, &mut output).ok()?;
+                output.truncate(written);
+                output
+            }
+            Algorithm::Deflate => {
+                let mut output = Vec::new();
+                DeflateDecoder::new(data)
+                    .take(len as u64 + 1)
+                    .read_to_end(&mut output)
+                    .ok()?;
+                output
+            }
+        };
+        if output.len() != len {
+            return None;
+        }
+        output.shrink_to_fit();
+        Some(output)
+    }
+}
+
+/// Upper bound on how many bytes of output a byte of LZ4 block data decodes to.  A match length
+/// grows by at most 255 per byte of the sequence describing it.
+const LZ4_MAX_RATIO: usize = 255;
+
+/// Opt-in compression settings for `BinaryMessageCodec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    algorithm: Algorithm,
+    threshold: usize,
+    headers: bool,
+}
+
+impl Compression {
+    /// Compresses bodies of at least 512 encoded bytes, leaving headers uncompressed.
+    pub fn new(algorithm: Algorithm) -> Compression {
+        Compression {
+            algorithm,
+            threshold: 512,
+            headers: false,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Minimum encoded size of a section before compression is attempted.
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn with_threshold(mut self, threshold: usize) -> Compression {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Whether headers are compressed as well as the body.
+    pub fn headers(&self) -> bool {
+        self.headers
+    }
+
+    pub fn with_headers(mut self, headers: bool) -> Compression {
+        self.headers = headers;
+        self
+    }
+
+    /// Compresses `section` if it reaches the threshold and compression makes it smaller.
+    pub(crate) fn compress(&self, section: &[u8]) -> Option<Vec<u8>> {
+        if section.len() < self.threshold {
+            return None;
+        }
+        let compressed = self.algorithm.compress(section);
+        if compressed.len() + BLOCK_HEADER_LEN < section.len() {
+            Some(compressed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Length of the algorithm id and lengths preceding compressed data.
+pub const BLOCK_HEADER_LEN: usize = 9;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = b"{\"name\": \"value\"}".repeat(100);
+        for &algorithm in [Algorithm::Lz4, Algorithm::Deflate].iter() {
+            let compressed = algorithm.compress(&data);
+            assert!(compressed.len() < data.len());
+            assert_eq!(algorithm.decompress(&compressed, data.len()), Some(data.clone()));
+            assert_eq!(algorithm.decompress(&compressed, data.len() - 1), None);
+            assert_eq!(algorithm.decompress(&compressed, data.len() + 1), None);
+            assert_eq!(Algorithm::from_u8(algorithm.to_u8()), Some(algorithm));
+        }
+    }
+
+    #[test]
+    fn decompress_beyond_data() {
+        let data = vec![0u8; 1 << 16];
+        for &algorithm in [Algorithm::Lz4, Algorithm::Deflate].iter() {
+            let compressed = algorithm.compress(&data);
+            assert_eq!(algorithm.decompress(&compressed, data.len()), Some(data.clone()));
+            assert_eq!(algorithm.decompress(&compressed, 64 << 20), None);
+            assert_eq!(algorithm.decompress(&[], 64 << 20), None);
+        }
+    }
+
+    #[test]
+    fn corrupt_data() {
+        for &algorithm in [Algorithm::Lz4, Algorithm::Deflate].iter() {
+            assert_eq!(algorithm.decompress(&[0xff; 16], 100), None);
+        }
+    }
+
+    #[test]
+    fn threshold() {
+        let compression = Compression::new(Algorithm::Lz4).with_threshold(64);
+        assert_eq!(compression.compress(&[0u8; 63]), None);
+        assert!(compression.compress(&[0u8; 64]).is_some());
+        let random: Vec<u8> = (0..256u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert_eq!(compression.compress(&random), None);
+    }
+}
@@ -0,0 +1,29 @@
+//! CRC32C (Castagnoli), the checksum used by iSCSI, ext4 and most log-structured stores.
+
+/// Reflected polynomial `0x82f63b78`, processed four bits at a time.
+const TABLE: [u32; 16] = [
+    0x00000000, 0x105ec76f, 0x20bd8ede, 0x30e349b1, 0x417b1dbc, 0x5125dad3, 0x61c69362, 0x7198540d,
+    0x82f63b78, 0x92a8fc17, 0xa24bb5a6, 0xb21572c9, 0xc38d26c4, 0xd3d3e1ab, 0xe330a81a, 0xf36e6f75,
+];
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[((crc ^ u32::from(byte)) & 0x0f) as usize] ^ (crc >> 4);
+        crc = TABLE[((crc ^ u32::from(byte >> 4)) & 0x0f) as usize] ^ (crc >> 4);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        assert_eq!(crc32c(b""), 0);
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8a9136aa);
+        assert_eq!(crc32c(&[0xffu8; 32]), 0x62a8ab43);
+    }
+}
@@ -0,0 +1,612 @@
+//! Authenticated encryption of message bodies and selected headers.
+//!
+//! `Encryption::encrypt` replaces the body, and any headers chosen with `with_header`, by a
+//! `Value::Bytes` holding the sealed binary encoding of the original value, and records how to
+//! reverse it in two headers.  A message without a body gets a sealed body with an empty
+//! plaintext, so every encrypted message carries at least one sealed value.
+//!
+//! | header                     | value                                                  |
+//! |----------------------------|--------------------------------------------------------|
+//! | `KEY_ID_HEADER`            | id of the key the message was encrypted with, a `Str`  |
+//! | `ENCRYPTED_HEADERS_HEADER` | keys of the encrypted headers, a `List` of `Str`/`I32` |
+//!
+//! The second header is only present when headers were encrypted.  The timestamp, expiration,
+//! correlation id and other headers stay in the clear.  Each sealed value is laid out as:
+//!
+//! | bytes | contents                                                    |
+//! |-------|-------------------------------------------------------------|
+//! | 12    | random nonce                                                |
+//! | n     | ChaCha20-Poly1305 ciphertext of the value's binary encoding |
+//! | 16    | authentication tag                                          |
+//!
+//! The associated data is the key id, the binary encoding of the list of encrypted headers (an
+//! empty list if there are none) and, for a header, the binary encoding of its key.  A sealed
+//! value cannot be moved to another header or to the body, and no entry of the list can be
+//! added or dropped, without failing authentication.  The sealed body also means neither
+//! marker header can be removed unnoticed.
+//!
+//! An encrypted message is an ordinary message and can be written with any codec.  Consumers
+//! without the key see the sealed values as opaque bytes, while `EncryptedCodec` wraps a codec
+//! to encrypt on encode and decrypt on decode.  `EncryptedCodec` rejects messages that are not
+//! encrypted or are encrypted under a key it does not hold, unless it is built
+//! `with_required(false)`.
+//!
+//! Decryption happens after the message is decoded, so `DecodeError::DecryptionFailed` reports
+//! offset 0, and errors decoding a decrypted value report offsets from the start of its
+//! plaintext.
+
+use bytes::{Buf, BufMut, BytesMut};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use codec::error::DecodeError;
+use codec::message_codec::{BinaryMessageCodec, MessageDecoder, MessageEncoder};
+use codec::size_calculator::calculate_value_size;
+use codec::Codec;
+use message::message::{Key, List, Map, Message, Value};
+use std::fmt;
+use std::io::Cursor;
+
+/// Header holding the id of the key a message was encrypted with.
+pub const KEY_ID_HEADER: &str = "hydramq.key_id";
+
+/// Header listing the keys of the encrypted headers.
+pub const ENCRYPTED_HEADERS_HEADER: &str = "hydramq.encrypted_headers";
+
+/// Length of an `EncryptionKey`.
+pub const KEY_LEN: usize = 32;
+
+/// Length of the nonce preceding each sealed value.
+pub const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag following each sealed value.
+pub const TAG_LEN: usize = 16;
+
+/// A ChaCha20-Poly1305 key and the id consumers look it up by.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey {
+    id: String,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    pub fn new<S: Into<String>>(id: S, key: [u8; KEY_LEN]) -> EncryptionKey {
+        EncryptionKey { id: id.into(), key }
+    }
+
+    /// Generates a random key.
+    pub fn generate<S: Into<String>>(id: S) -> EncryptionKey {
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&ChaCha20Poly1305::generate_key(&mut OsRng));
+        EncryptionKey::new(id, key)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Seals the binary encoding of `value`, or an empty plaintext for an absent body.
+    fn seal<'a>(&self, value: Option<&Value>, aad: &[u8]) -> Value<'a> {
+        let mut plaintext = Vec::new();
+        if let Some(value) = value {
+            MessageEncoder::encode_value(&BinaryMessageCodec::new(), value, &mut plaintext);
+        }
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &plaintext,
+            aad,
+        };
+        let ciphertext = self.cipher().encrypt(&nonce, payload).expect("Error encrypting value");
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Value::Bytes(sealed.into())
+    }
+
+    /// Reverses `seal`, returning `None` for an empty plaintext.
+    fn open<'a>(&self, value: &Value, aad: &[u8]) -> Result<Option<Value<'a>>, DecodeError> {
+        let sealed = match *value {
+            Value::Bytes(ref sealed) if sealed.len() >= NONCE_LEN + TAG_LEN => sealed,
+            _ => return Err(DecodeError::DecryptionFailed { offset: 0 }),
+        };
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let plaintext = self
+            .cipher()
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| DecodeError::DecryptionFailed { offset: 0 })?;
+        if plaintext.is_empty() {
+            return Ok(None);
+        }
+        let codec = BinaryMessageCodec::new();
+        let mut plaintext = Cursor::new(plaintext);
+        let value = MessageDecoder::decode_value(&codec, &mut plaintext, 0)?;
+        if plaintext.has_remaining() {
+            return Err(DecodeError::DecryptionFailed { offset: 0 });
+        }
+        Ok(Some(value))
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new((&self.key).into())
+    }
+}
+
+/// The associated data of a sealed value, given the encoding of the list of encrypted headers
+/// from `encode_header_list`.
+fn associated_data(id: &str, header_list: &[u8], header: Option<&Key>) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + id.len() + header_list.len());
+    aad.put_u32_be(id.len() as u32);
+    aad.put_slice(id.as_bytes());
+    aad.put_slice(header_list);
+    if let Some(header) = header {
+        MessageEncoder::encode_key(&BinaryMessageCodec::new(), header, &mut aad);
+    }
+    aad
+}
+
+fn encode_header_list(headers: &List) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    MessageEncoder::encode_list(&BinaryMessageCodec::new(), headers, &mut bytes);
+    bytes
+}
+
+/// Shows the key id only, so keys do not end up in logs.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionKey").field("id", &self.id).finish()
+    }
+}
+
+/// Encryption settings: the key to encrypt with, older keys still accepted for decryption, and
+/// the headers to encrypt along with the body.
+#[derive(Debug, Clone)]
+pub struct Encryption {
+    key: EncryptionKey,
+    decryption_keys: Vec<EncryptionKey>,
+    headers: Vec<Key<'static>>,
+}
+
+impl Encryption {
+    /// Encrypts bodies with `key`, leaving headers in the clear.
+    pub fn new(key: EncryptionKey) -> Encryption {
+        Encryption {
+            key,
+            decryption_keys: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn key(&self) -> &EncryptionKey {
+        &self.key
+    }
+
+    /// Keys accepted for decryption besides `key`.
+    pub fn decryption_keys(&self) -> &[EncryptionKey] {
+        &self.decryption_keys
+    }
+
+    /// Also decrypts messages encrypted with `key`, such as one that is being rotated out.
+    pub fn with_decryption_key(mut self, key: EncryptionKey) -> Encryption {
+        self.decryption_keys.push(key);
+        self
+    }
+
+    /// Headers encrypted along with the body.
+    pub fn headers(&self) -> &[Key<'static>] {
+        &self.headers
+    }
+
+    pub fn with_header<K: Into<Key<'static>>>(mut self, key: K) -> Encryption {
+        self.headers.push(key.into());
+        self
+    }
+
+    /// Returns a copy of `message` with its body and selected headers sealed.
+    pub fn encrypt<'a>(&self, message: &Message<'a>) -> Message<'a> {
+        self.replace_sealed(message, |value, header, header_list| {
+            let aad = associated_data(&self.key.id, header_list, header);
+            self.key.seal(value, &aad)
+        })
+    }
+
+    /// Returns a copy of `message` laid out as `encrypt` lays it out, with every sealed value
+    /// replaced by zeros of the same length.
+    fn placeholder<'a>(&self, message: &Message<'a>) -> Message<'a> {
+        self.replace_sealed(message, |value, _, _| {
+            let len = value.map_or(0, |value| calculate_value_size(value) as usize);
+            Value::Bytes(vec![0u8; NONCE_LEN + len + TAG_LEN].into())
+        })
+    }
+
+    /// Copies `message`, replacing the body and the selected headers by the result of `seal`,
+    /// which is given the value (`None` for an absent body), the header key (`None` for the
+    /// body) and the encoding of the list of encrypted headers.
+    fn replace_sealed<'a, F>(&self, message: &Message<'a>, mut seal: F) -> Message<'a>
+    where
+        F: FnMut(Option<&Value<'a>>, Option<&Key<'a>>, &[u8]) -> Value<'a>,
+    {
+        let encrypted_headers: List = message
+            .headers()
+            .iter()
+            .filter(|&(key, _)| self.headers.contains(key))
+            .map(|(key, _)| match *key {
+                Key::Str(ref key) => Value::Str(key.clone()),
+                Key::I32(key) => Value::I32(key),
+            })
+            .collect();
+        let header_list = encode_header_list(&encrypted_headers);
+
+        let mut headers = Map::new();
+        for (key, value) in message.headers().iter() {
+            if self.headers.contains(key) {
+                headers.insert(key.clone(), seal(Some(value), Some(key), &header_list));
+            } else {
+                headers.insert(key.clone(), value.clone());
+            }
+        }
+        headers.insert(KEY_ID_HEADER, self.key.id.clone());
+        if !encrypted_headers.is_empty() {
+            headers.insert(ENCRYPTED_HEADERS_HEADER, encrypted_headers);
+        }
+
+        let mut encrypted = copy_properties(message);
+        *encrypted.headers_mut() = headers;
+        encrypted.set_body(Some(seal(message.body(), None, &header_list)));
+        encrypted
+    }
+
+    /// Reverses `encrypt`.  Messages that are not encrypted, or are encrypted with a key this
+    /// `Encryption` does not hold, are returned unchanged; use `decrypt_required` to reject
+    /// them instead.
+    pub fn decrypt<'a>(&self, message: Message<'a>) -> Result<Message<'a>, DecodeError> {
+        self.decrypt_message(message, false)
+    }
+
+    /// Reverses `encrypt`, failing with `DecodeError::NotEncrypted` or
+    /// `DecodeError::UnknownEncryptionKey` if the message is not encrypted with a key this
+    /// `Encryption` holds.
+    pub fn decrypt_required<'a>(&self, message: Message<'a>) -> Result<Message<'a>, DecodeError> {
+        self.decrypt_message(message, true)
+    }
+
+    fn decrypt_message<'a>(
+        &self,
+        message: Message<'a>,
+        required: bool,
+    ) -> Result<Message<'a>, DecodeError> {
+        let key = match message.headers().get(&Key::from(KEY_ID_HEADER)) {
+            Some(Value::Str(ref id)) => match self.find_key(id) {
+                Some(key) => key,
+                None if required => {
+                    return Err(DecodeError::UnknownEncryptionKey {
+                        offset: 0,
+                        id: id.to_string(),
+                    })
+                }
+                None => return Ok(message),
+            },
+            _ if required => return Err(DecodeError::NotEncrypted { offset: 0 }),
+            _ => return Ok(message),
+        };
+        let empty = List::new();
+        let header_list = match message.headers().get(&Key::from(ENCRYPTED_HEADERS_HEADER)) {
+            Some(Value::List(ref keys)) => keys,
+            Some(_) => return Err(DecodeError::DecryptionFailed { offset: 0 }),
+            None => &empty,
+        };
+        let encrypted_headers = header_list
+            .iter()
+            .map(|key| match *key {
+                Value::Str(ref key) => Ok(Key::Str(key.clone())),
+                Value::I32(key) => Ok(Key::I32(key)),
+                _ => Err(DecodeError::DecryptionFailed { offset: 0 }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if encrypted_headers.iter().any(|header| !message.headers().contains_key(header)) {
+            return Err(DecodeError::DecryptionFailed { offset: 0 });
+        }
+        let header_list = encode_header_list(header_list);
+
+        let markers = [Key::from(KEY_ID_HEADER), Key::from(ENCRYPTED_HEADERS_HEADER)];
+        let mut headers = Map::new();
+        for (header, value) in message.headers().iter() {
+            if markers.contains(header) {
+                continue;
+            }
+            if encrypted_headers.contains(header) {
+                let aad = associated_data(&key.id, &header_list, Some(header));
+                let value = key.open(value, &aad)?;
+                let value = value.ok_or(DecodeError::DecryptionFailed { offset: 0 })?;
+                headers.insert(header.clone(), value);
+            } else {
+                headers.insert(header.clone(), value.clone());
+            }
+        }
+
+        let body = message.body().ok_or(DecodeError::DecryptionFailed { offset: 0 })?;
+        let aad = associated_data(&key.id, &header_list, None);
+        let body = key.open(body, &aad)?;
+
+        let mut decrypted = copy_properties(&message);
+        *decrypted.headers_mut() = headers;
+        decrypted.set_body(body);
+        Ok(decrypted)
+    }
+
+    fn find_key(&self, id: &str) -> Option<&EncryptionKey> {
+        Some(&self.key)
+            .into_iter()
+            .chain(self.decryption_keys.iter())
+            .find(|key| key.id == id)
+    }
+}
+
+fn copy_properties<'a>(message: &Message<'a>) -> Message<'a> {
+    let mut copy = Message::new();
+    copy.set_timestamp(message.timestamp());
+    copy.set_expiration(message.expiration());
+    copy.set_correlation_id(message.correlation_id());
+    copy
+}
+
+/// Wraps a codec to encrypt messages as they are encoded and decrypt them as they are decoded.
+#[derive(Debug, Clone)]
+pub struct EncryptedCodec<C> {
+    codec: C,
+    encryption: Encryption,
+    required: bool,
+}
+
+impl<C: Codec> EncryptedCodec<C> {
+    /// Rejects messages that are not encrypted with a key `encryption` holds.
+    pub fn new(codec: C, encryption: Encryption) -> EncryptedCodec<C> {
+        EncryptedCodec {
+            codec,
+            encryption,
+            required: true,
+        }
+    }
+
+    /// Whether decoding rejects messages that are not encrypted with a known key.  Turning
+    /// this off passes them through unchanged, which lets anyone who can write messages
+    /// substitute plaintext for encrypted values.
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    pub fn with_required(mut self, required: bool) -> EncryptedCodec<C> {
+        self.required = required;
+        self
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn encryption(&self) -> &Encryption {
+        &self.encryption
+    }
+}
+
+impl<C: Codec> Codec for EncryptedCodec<C> {
+    fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        self.codec.encode(&self.encryption.encrypt(message), buffer)
+    }
+
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError> {
+        self.encryption.decrypt_message(self.codec.decode(buffer)?, self.required)
+    }
+
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        self.encryption.decrypt_message(self.codec.decode_borrowed(buffer)?, self.required)
+    }
+
+    /// Sealing adds `NONCE_LEN + TAG_LEN` to the binary encoding of each value, so the message
+    /// is measured with zeroed placeholders of the sealed lengths instead of being encrypted.
+    /// If the wrapped codec compresses, placeholders compress where ciphertext does not, and
+    /// the length is a lower bound rather than exact.
+    fn encoded_len(&self, message: &Message) -> usize {
+        self.codec.encoded_len(&self.encryption.placeholder(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use codec::compact::CompactMessageCodec;
+    use codec::message_codec::BinaryMessageCodec;
+    use codec::message_pack::MessagePackCodec;
+    use message::message::{MapBuilder, MessageBuilder};
+    use uuid::Uuid;
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+            .with_correlation_id(Uuid::nil())
+            .with_header("tenant", "acme")
+            .with_header("card", "4111 1111 1111 1111")
+            .with_header(7, MapBuilder::new().insert("ssn", "078-05-1120").build())
+            .with_body("secret")
+            .build()
+    }
+
+    fn encryption() -> Encryption {
+        Encryption::new(EncryptionKey::new("k1", [1u8; KEY_LEN]))
+            .with_header("card")
+            .with_header(7)
+    }
+
+    fn header<'m, 'a>(message: &'m Message<'a>, key: &'static str) -> Option<&'m Value<'a>> {
+        message.headers().get(&Key::from(key))
+    }
+
+    fn round_trip<C: Codec>(codec: C) {
+        let codec = EncryptedCodec::new(codec, encryption());
+        let mut bytes = BytesMut::new();
+        codec.encode(&example(), &mut bytes);
+        assert_eq!(bytes.len(), codec.encoded_len(&example()));
+        assert_eq!(codec.decode(&bytes), Ok(example()));
+        assert_eq!(codec.decode_borrowed(&bytes), Ok(example()));
+    }
+
+    #[test]
+    fn encoded_len_without_encrypting() {
+        let codec = EncryptedCodec::new(CompactMessageCodec::new(), encryption());
+        let messages = [
+            example(),
+            Message::new(),
+            MessageBuilder::new().with_header("card", vec![7u8; 300]).build(),
+        ];
+        for message in messages.iter() {
+            let mut bytes = BytesMut::new();
+            codec.encode(message, &mut bytes);
+            assert_eq!(codec.encoded_len(message), bytes.len());
+        }
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        round_trip(BinaryMessageCodec::new());
+        round_trip(CompactMessageCodec::new());
+        round_trip(MessagePackCodec::new());
+    }
+
+    #[test]
+    fn opaque_without_key() {
+        let codec = EncryptedCodec::new(BinaryMessageCodec::new(), encryption());
+        let mut bytes = BytesMut::new();
+        codec.encode(&example(), &mut bytes);
+        let message = BinaryMessageCodec::new().decode(&bytes).unwrap();
+
+        assert_eq!(message.timestamp(), example().timestamp());
+        assert_eq!(header(&message, "tenant"), Some(&Value::from("acme")));
+        assert_eq!(header(&message, KEY_ID_HEADER), Some(&Value::from("k1")));
+        let encrypted_headers = header(&message, ENCRYPTED_HEADERS_HEADER).unwrap();
+        assert_eq!(encrypted_headers, &Value::from(List::builder().push("card").push(7).build()));
+        match header(&message, "card") {
+            Some(Value::Bytes(sealed)) => assert_eq!(sealed.len(), NONCE_LEN + 24 + TAG_LEN),
+            other => panic!("Expected sealed header, got {:?}", other),
+        }
+        match message.body() {
+            Some(Value::Bytes(sealed)) => assert_eq!(sealed.len(), NONCE_LEN + 11 + TAG_LEN),
+            other => panic!("Expected sealed body, got {:?}", other),
+        }
+
+        let other = Encryption::new(EncryptionKey::new("k2", [2u8; KEY_LEN]));
+        assert_eq!(other.decrypt(message.clone()), Ok(message));
+    }
+
+    #[test]
+    fn nonces_differ() {
+        let encryption = encryption();
+        assert_ne!(encryption.encrypt(&example()), encryption.encrypt(&example()));
+    }
+
+    #[test]
+    fn key_rotation() {
+        let old = encryption();
+        let new = Encryption::new(EncryptionKey::generate("k2"))
+            .with_decryption_key(old.key().clone());
+        let encrypted = old.encrypt(&example());
+        assert_eq!(new.decrypt(encrypted), Ok(example()));
+        assert_eq!(header(&new.encrypt(&example()), KEY_ID_HEADER), Some(&Value::from("k2")));
+    }
+
+    #[test]
+    fn tampered_body() {
+        let mut encrypted = encryption().encrypt(&example());
+        let mut sealed = match encrypted.body() {
+            Some(Value::Bytes(sealed)) => sealed.to_vec(),
+            other => panic!("Expected sealed body, got {:?}", other),
+        };
+        sealed[NONCE_LEN] ^= 0x01;
+        encrypted.set_body(Some(Value::Bytes(sealed.into())));
+        assert_eq!(
+            encryption().decrypt(encrypted),
+            Err(DecodeError::DecryptionFailed { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn moved_header() {
+        let encrypted = encryption().encrypt(&example());
+        let mut moved = encrypted.clone();
+        moved.set_body(header(&encrypted, "card").cloned());
+        assert_eq!(encryption().decrypt(moved), Err(DecodeError::DecryptionFailed { offset: 0 }));
+
+        let mut wrong_key = encrypted.clone();
+        wrong_key.headers_mut().insert(KEY_ID_HEADER, "k2");
+        let other = Encryption::new(EncryptionKey::new("k2", [1u8; KEY_LEN]));
+        assert_eq!(other.decrypt(wrong_key), Err(DecodeError::DecryptionFailed { offset: 0 }));
+    }
+
+    #[test]
+    fn required_by_codec() {
+        let plain = MessageBuilder::new().with_header("card", "forged").with_body("forged").build();
+        let mut bytes = BytesMut::new();
+        BinaryMessageCodec::new().encode(&plain, &mut bytes);
+        let codec = EncryptedCodec::new(BinaryMessageCodec::new(), encryption());
+        assert!(codec.required());
+        assert_eq!(codec.decode(&bytes), Err(DecodeError::NotEncrypted { offset: 0 }));
+        assert_eq!(codec.with_required(false).decode(&bytes), Ok(plain.clone()));
+
+        let other = Encryption::new(EncryptionKey::new("k2", [2u8; KEY_LEN]));
+        let encrypted = other.encrypt(&example());
+        assert_eq!(
+            encryption().decrypt_required(encrypted.clone()),
+            Err(DecodeError::UnknownEncryptionKey { offset: 0, id: "k2".to_string() })
+        );
+        assert_eq!(encryption().decrypt(encrypted.clone()), Ok(encrypted));
+        assert_eq!(
+            encryption().decrypt_required(plain),
+            Err(DecodeError::NotEncrypted { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn header_list_is_authenticated() {
+        let failed = Err(DecodeError::DecryptionFailed { offset: 0 });
+        let encrypted = encryption().encrypt(&example());
+
+        let mut dropped = encrypted.clone();
+        dropped.headers_mut().remove(&Key::from(ENCRYPTED_HEADERS_HEADER));
+        assert_eq!(encryption().decrypt(dropped), failed);
+
+        let mut shortened = encrypted.clone();
+        shortened.headers_mut().insert(ENCRYPTED_HEADERS_HEADER, List::builder().push(7).build());
+        assert_eq!(encryption().decrypt(shortened), failed);
+
+        let mut missing_header = encrypted.clone();
+        missing_header.headers_mut().remove(&Key::from("card"));
+        assert_eq!(encryption().decrypt(missing_header), failed);
+
+        let mut missing_body = encrypted.clone();
+        missing_body.set_body(None::<Value>);
+        assert_eq!(encryption().decrypt(missing_body), failed);
+    }
+
+    #[test]
+    fn without_body() {
+        let message = MessageBuilder::new().with_header("card", "4111").build();
+        let encrypted = encryption().encrypt(&message);
+        match encrypted.body() {
+            Some(Value::Bytes(sealed)) => assert_eq!(sealed.len(), NONCE_LEN + TAG_LEN),
+            other => panic!("Expected sealed body, got {:?}", other),
+        }
+        assert_eq!(encryption().decrypt(encrypted.clone()), Ok(message));
+
+        let mut dropped = encrypted;
+        dropped.headers_mut().remove(&Key::from(ENCRYPTED_HEADERS_HEADER));
+        assert_eq!(encryption().decrypt(dropped), Err(DecodeError::DecryptionFailed { offset: 0 }));
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let debug = format!("{:?}", EncryptionKey::new("k1", [0xab; KEY_LEN]));
+        assert_eq!(debug, "EncryptionKey { id: \"k1\" }");
+    }
+}
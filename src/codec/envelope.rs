@@ -0,0 +1,253 @@
+//! Self describing envelope around an encoded message.
+//!
+//! An enveloped message starts with three bytes, followed by the message in the named format:
+//!
+//! | byte | contents                          |
+//! |------|-----------------------------------|
+//! | 0    | `MAGIC`                           |
+//! | 1    | format version, `FORMAT_VERSION`  |
+//! | 2    | `CodecId` of the encoded message  |
+//!
+//! The envelope is optional.  A binary v1 message always starts with a zero byte, since its
+//! flags word is a small big endian integer, so `decode_any` reads anything that does not start
+//! with `MAGIC` as a bare binary v1 message.  Segments written before the envelope existed can
+//! therefore be read alongside newer ones.
+//!
+//! Errors from `decode_any` report offsets from the start of the envelope.
+//!
+//! `EnvelopeCodec` offers the same as a `Codec`, for code that stores or transmits messages
+//! through one.
+
+use bytes::{Buf, BufMut, BytesMut};
+use codec::buf::{DecodeBuf, SliceBuf};
+use codec::compact::CompactMessageCodec;
+use codec::error::DecodeError;
+use codec::message_codec::{BinaryMessageCodec, MessageDecoder};
+use codec::message_pack::MessagePackCodec;
+use codec::Codec;
+use message::message::Message;
+use std::io::Cursor;
+
+/// First byte of every envelope.  `0xc1` is never used in MessagePack and cannot start a binary
+/// v1 message, so it does not collide with the bare formats.
+pub const MAGIC: u8 = 0xc1;
+
+/// The envelope layout written by this version.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Length of the envelope header preceding the message.
+pub const HEADER_LEN: usize = 3;
+
+/// Identifies the format of the message inside an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodecId {
+    /// `message_codec::BinaryMessageCodec`.
+    Binary,
+    /// `compact::CompactMessageCodec`.
+    Compact,
+    /// `message_pack::MessagePackCodec`.
+    MessagePack,
+}
+
+impl CodecId {
+    pub fn from_u8(value: u8) -> Option<CodecId> {
+        match value {
+            1 => Some(CodecId::Binary),
+            2 => Some(CodecId::Compact),
+            3 => Some(CodecId::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CodecId::Binary => 1,
+            CodecId::Compact => 2,
+            CodecId::MessagePack => 3,
+        }
+    }
+}
+
+/// Encodes `message` with the given codec and wraps it in an envelope.
+pub fn encode_message(message: &Message, codec: CodecId) -> BytesMut {
+    let mut buffer = BytesMut::new();
+    EnvelopeCodec::new(codec).encode(message, &mut buffer);
+    buffer
+}
+
+/// Returns the codec an encoded message was written with, reading bare messages as binary v1.
+pub fn detect_codec(buffer: &[u8]) -> Result<CodecId, DecodeError> {
+    read_header(&mut SliceBuf::new(buffer))
+}
+
+/// Decodes an enveloped message with the codec named in its envelope, or a bare binary v1
+/// message.
+pub fn decode_any<'a, T: AsRef<[u8]>>(buffer: T) -> Result<Message<'a>, DecodeError> {
+    decode_from(&mut Cursor::new(buffer))
+}
+
+/// Like `decode_any`, but strings, bytes and string keys borrow from `buffer` rather than
+/// being copied out of it.
+pub fn decode_any_borrowed<'a>(buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+    decode_from(&mut SliceBuf::new(buffer))
+}
+
+fn decode_from<'a, B: DecodeBuf<'a>>(buffer: &mut B) -> Result<Message<'a>, DecodeError> {
+    match read_header(buffer)? {
+        CodecId::Binary => BinaryMessageCodec::new().decode_message(buffer),
+        CodecId::Compact => CompactMessageCodec::new().decode_message(buffer),
+        CodecId::MessagePack => MessagePackCodec::new().decode_message(buffer),
+    }
+}
+
+/// Writes messages in an envelope naming `codec`, and reads enveloped messages of any codec as
+/// well as bare binary v1 messages, like `decode_any`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvelopeCodec {
+    codec: CodecId,
+}
+
+impl EnvelopeCodec {
+    pub fn new(codec: CodecId) -> EnvelopeCodec {
+        EnvelopeCodec { codec }
+    }
+
+    /// The codec messages are written with.
+    pub fn codec(&self) -> CodecId {
+        self.codec
+    }
+}
+
+impl Codec for EnvelopeCodec {
+    fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        buffer.reserve(HEADER_LEN);
+        put_header(self.codec, buffer);
+        match self.codec {
+            CodecId::Binary => BinaryMessageCodec::new().encode(message, buffer),
+            CodecId::Compact => CompactMessageCodec::new().encode(message, buffer),
+            CodecId::MessagePack => MessagePackCodec::new().encode(message, buffer),
+        }
+    }
+
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError> {
+        decode_any(buffer)
+    }
+
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        decode_any_borrowed(buffer)
+    }
+
+    fn encoded_len(&self, message: &Message) -> usize {
+        HEADER_LEN + match self.codec {
+            CodecId::Binary => BinaryMessageCodec::new().encoded_len(message),
+            CodecId::Compact => CompactMessageCodec::new().encoded_len(message),
+            CodecId::MessagePack => MessagePackCodec::new().encoded_len(message),
+        }
+    }
+}
+
+fn put_header<B: BufMut>(codec: CodecId, buffer: &mut B) {
+    buffer.put_u8(MAGIC);
+    buffer.put_u8(FORMAT_VERSION);
+    buffer.put_u8(codec.to_u8());
+}
+
+/// Consumes the envelope header if there is one, leaving `buffer` at the start of the message.
+fn read_header<'a, B: DecodeBuf<'a>>(buffer: &mut B) -> Result<CodecId, DecodeError> {
+    if buffer.remaining() == 0 || buffer.bytes()[0] != MAGIC {
+        return Ok(CodecId::Binary);
+    }
+    buffer.require(HEADER_LEN)?;
+    buffer.advance(1);
+    let version_offset = buffer.position();
+    let version = buffer.get_u8();
+    if version != FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion { offset: version_offset, version });
+    }
+    let codec_offset = buffer.position();
+    let codec = buffer.get_u8();
+    CodecId::from_u8(codec).ok_or(DecodeError::UnknownCodec { offset: codec_offset, codec })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use codec::message_codec;
+    use message::message::{MapBuilder, MessageBuilder};
+    use uuid::Uuid;
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+            .with_correlation_id(Uuid::nil())
+            .with_header("key", "value")
+            .with_header(7, MapBuilder::new().insert("nested", 1i64).build())
+            .with_body("body")
+            .build()
+    }
+
+    #[test]
+    fn round_trip_each_codec() {
+        for &codec in [CodecId::Binary, CodecId::Compact, CodecId::MessagePack].iter() {
+            let bytes = encode_message(&example(), codec);
+            assert_eq!(&bytes[..HEADER_LEN], &[MAGIC, FORMAT_VERSION, codec.to_u8()][..]);
+            assert_eq!(detect_codec(&bytes), Ok(codec));
+            assert_eq!(decode_any(&bytes), Ok(example()));
+            assert_eq!(decode_any_borrowed(&bytes), Ok(example()));
+        }
+    }
+
+    #[test]
+    fn codec() {
+        let codec = EnvelopeCodec::new(CodecId::Compact);
+        let mut bytes = BytesMut::new();
+        codec.encode(&example(), &mut bytes);
+        assert_eq!(bytes, encode_message(&example(), CodecId::Compact));
+        assert_eq!(bytes.len(), codec.encoded_len(&example()));
+        assert_eq!(codec.decode(&bytes), Ok(example()));
+
+        let bare = message_codec::encode_message(&example());
+        assert_eq!(codec.decode_borrowed(&bare), Ok(example()));
+    }
+
+    #[test]
+    fn decode_bare_binary_message() {
+        let bytes = message_codec::encode_message(&example());
+        assert_eq!(detect_codec(&bytes), Ok(CodecId::Binary));
+        assert_eq!(decode_any(&bytes), Ok(example()));
+    }
+
+    #[test]
+    fn decode_unsupported_version() {
+        let mut bytes = encode_message(&example(), CodecId::Compact);
+        bytes[1] = 9;
+        assert_eq!(
+            decode_any(&bytes),
+            Err(DecodeError::UnsupportedVersion { offset: 1, version: 9 })
+        );
+    }
+
+    #[test]
+    fn decode_unknown_codec() {
+        let mut bytes = encode_message(&example(), CodecId::Compact);
+        bytes[2] = 42;
+        assert_eq!(decode_any(&bytes), Err(DecodeError::UnknownCodec { offset: 2, codec: 42 }));
+    }
+
+    #[test]
+    fn decode_truncated_header() {
+        match decode_any(&[MAGIC, FORMAT_VERSION][..]) {
+            Err(DecodeError::Truncated { offset: 0, needed: 3, remaining: 2 }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_report_offsets_from_envelope_start() {
+        let mut bytes = encode_message(&example(), CodecId::Binary);
+        let last = bytes.len() - 1;
+        bytes[last] = 0xff;
+        assert_eq!(decode_any(&bytes), Err(DecodeError::InvalidUtf8 { offset: last - 3 }));
+    }
+}
@@ -0,0 +1,169 @@
+use codec::limits::Limit;
+use std::error::Error;
+use std::fmt;
+
+/// Error produced when an encoded message cannot be decoded.
+///
+/// Every variant carries the byte offset, relative to the start of the encoded message,
+/// at which decoding failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Truncated { offset: usize, needed: usize, remaining: usize },
+    UnknownKeyType { offset: usize, tag: u8 },
+    UnknownValueType { offset: usize, tag: u8 },
+    InvalidFlags { offset: usize, bits: i32 },
+    InvalidUtf8 { offset: usize },
+    InvalidUuid { offset: usize },
+    InvalidTimestamp { offset: usize, seconds: i64, nanos: i32 },
+    NegativeLength { offset: usize, length: i32 },
+    LimitExceeded { offset: usize, limit: Limit, actual: usize, max: usize },
+    UnexpectedType { offset: usize, tag: u8, expected: &'static str },
+    UnknownField { offset: usize, name: String },
+    UnknownExtType { offset: usize, ext_type: i8 },
+    IntegerOverflow { offset: usize },
+    InvalidVarint { offset: usize },
+    UnsupportedVersion { offset: usize, version: u8 },
+    UnknownCodec { offset: usize, codec: u8 },
+    ChecksumMismatch { offset: usize, expected: u32, actual: u32 },
+    TrailingBytes { offset: usize, len: usize },
+    UnknownCompression { offset: usize, algorithm: u8 },
+    InvalidCompressedData { offset: usize },
+    UnknownKeyIndex { offset: usize, index: u64 },
+    UnexpectedOffsetDelta { offset: usize, expected: u64, actual: u64 },
+    InvalidDuration { offset: usize, seconds: u64, nanos: u64 },
+    DecryptionFailed { offset: usize },
+    NotEncrypted { offset: usize },
+    UnknownEncryptionKey { offset: usize, id: String },
+}
+
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            DecodeError::Truncated { offset, .. } => offset,
+            DecodeError::UnknownKeyType { offset, .. } => offset,
+            DecodeError::UnknownValueType { offset, .. } => offset,
+            DecodeError::InvalidFlags { offset, .. } => offset,
+            DecodeError::InvalidUtf8 { offset } => offset,
+            DecodeError::InvalidUuid { offset } => offset,
+            DecodeError::InvalidTimestamp { offset, .. } => offset,
+            DecodeError::NegativeLength { offset, .. } => offset,
+            DecodeError::LimitExceeded { offset, .. } => offset,
+            DecodeError::UnexpectedType { offset, .. } => offset,
+            DecodeError::UnknownField { offset, .. } => offset,
+            DecodeError::UnknownExtType { offset, .. } => offset,
+            DecodeError::IntegerOverflow { offset } => offset,
+            DecodeError::InvalidVarint { offset } => offset,
+            DecodeError::UnsupportedVersion { offset, .. } => offset,
+            DecodeError::UnknownCodec { offset, .. } => offset,
+            DecodeError::ChecksumMismatch { offset, .. } => offset,
+            DecodeError::TrailingBytes { offset, .. } => offset,
+            DecodeError::UnknownCompression { offset, .. } => offset,
+            DecodeError::InvalidCompressedData { offset } => offset,
+            DecodeError::UnknownKeyIndex { offset, .. } => offset,
+            DecodeError::UnexpectedOffsetDelta { offset, .. } => offset,
+            DecodeError::InvalidDuration { offset, .. } => offset,
+            DecodeError::DecryptionFailed { offset } => offset,
+            DecodeError::NotEncrypted { offset } => offset,
+            DecodeError::UnknownEncryptionKey { offset, .. } => offset,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated { offset, needed, remaining } => write!(
+                f,
+                "Truncated message at offset {}: needed {} bytes, {} remaining",
+                offset, needed, remaining
+            ),
+            DecodeError::UnknownKeyType { offset, tag } => {
+                write!(f, "Unsupported key type '{}' at offset {}", tag, offset)
+            }
+            DecodeError::UnknownValueType { offset, tag } => {
+                write!(f, "Unsupported value type '{}' at offset {}", tag, offset)
+            }
+            DecodeError::InvalidFlags { offset, bits } => {
+                write!(f, "Invalid flags '{:#x}' at offset {}", bits, offset)
+            }
+            DecodeError::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 string at offset {}", offset)
+            }
+            DecodeError::InvalidUuid { offset } => write!(f, "Invalid UUID at offset {}", offset),
+            DecodeError::InvalidTimestamp { offset, seconds, nanos } => write!(
+                f,
+                "Invalid timestamp '{}s {}ns' at offset {}",
+                seconds, nanos, offset
+            ),
+            DecodeError::NegativeLength { offset, length } => {
+                write!(f, "Negative length '{}' at offset {}", length, offset)
+            }
+            DecodeError::LimitExceeded { offset, limit, actual, max } => write!(
+                f,
+                "Maximum {} of {} exceeded at offset {}: {}",
+                limit, max, offset, actual
+            ),
+            DecodeError::UnexpectedType { offset, tag, expected } => write!(
+                f,
+                "Expected {} but found type '{:#x}' at offset {}",
+                expected, tag, offset
+            ),
+            DecodeError::UnknownField { offset, ref name } => {
+                write!(f, "Unknown field '{}' at offset {}", name, offset)
+            }
+            DecodeError::UnknownExtType { offset, ext_type } => {
+                write!(f, "Unsupported extension type '{}' at offset {}", ext_type, offset)
+            }
+            DecodeError::IntegerOverflow { offset } => {
+                write!(f, "Integer out of range at offset {}", offset)
+            }
+            DecodeError::InvalidVarint { offset } => {
+                write!(f, "Invalid varint at offset {}", offset)
+            }
+            DecodeError::UnsupportedVersion { offset, version } => {
+                write!(f, "Unsupported format version '{}' at offset {}", version, offset)
+            }
+            DecodeError::UnknownCodec { offset, codec } => {
+                write!(f, "Unknown codec id '{}' at offset {}", codec, offset)
+            }
+            DecodeError::ChecksumMismatch { offset, expected, actual } => write!(
+                f,
+                "Checksum mismatch at offset {}: expected {:#010x}, computed {:#010x}",
+                offset, expected, actual
+            ),
+            DecodeError::TrailingBytes { offset, len } => {
+                write!(f, "Unexpected {} bytes after the message at offset {}", len, offset)
+            }
+            DecodeError::UnknownCompression { offset, algorithm } => {
+                write!(f, "Unsupported compression '{}' at offset {}", algorithm, offset)
+            }
+            DecodeError::InvalidCompressedData { offset } => {
+                write!(f, "Invalid compressed data at offset {}", offset)
+            }
+            DecodeError::UnknownKeyIndex { offset, index } => {
+                write!(f, "Unknown dictionary key index '{}' at offset {}", index, offset)
+            }
+            DecodeError::UnexpectedOffsetDelta { offset, expected, actual } => write!(
+                f,
+                "Expected offset delta {} but found {} at offset {}",
+                expected, actual, offset
+            ),
+            DecodeError::InvalidDuration { offset, seconds, nanos } => write!(
+                f,
+                "Invalid duration '{}s {}ns' at offset {}",
+                seconds, nanos, offset
+            ),
+            DecodeError::DecryptionFailed { offset } => {
+                write!(f, "Could not decrypt value at offset {}", offset)
+            }
+            DecodeError::NotEncrypted { offset } => {
+                write!(f, "Expected an encrypted message at offset {}", offset)
+            }
+            DecodeError::UnknownEncryptionKey { offset, ref id } => {
+                write!(f, "Unknown encryption key '{}' at offset {}", id, offset)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
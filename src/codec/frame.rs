@@ -0,0 +1,239 @@
+//! Length prefixed framing of messages over a byte stream.
+//!
+//! Each frame is a big endian `i32` holding the length of the encoded message, followed by the
+//! message itself in the format of the `Codec` in use, binary by default.
+
+use bytes::{Buf, BufMut, BytesMut};
+use codec::error::DecodeError;
+use codec::limits::{DecodeLimits, Limit};
+use codec::message_codec::BinaryMessageCodec;
+use codec::Codec;
+use message::message::Message;
+use std::io::Cursor;
+
+/// Length of the prefix preceding each message.
+pub const LENGTH_PREFIX_LEN: usize = 4;
+
+/// Writes messages as length prefixed frames.
+#[derive(Debug, Clone, Default)]
+pub struct FrameEncoder<C = BinaryMessageCodec> {
+    codec: C,
+}
+
+impl FrameEncoder {
+    pub fn new() -> FrameEncoder {
+        FrameEncoder::with_codec(BinaryMessageCodec::new())
+    }
+}
+
+impl<C: Codec> FrameEncoder<C> {
+    pub fn with_codec(codec: C) -> FrameEncoder<C> {
+        FrameEncoder { codec }
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Appends a frame holding `message` to `buffer`, growing it as needed.
+    pub fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        // The length is filled in once the message is encoded, as measuring it up front would
+        // encode a compressed message twice.
+        let start = buffer.len();
+        buffer.reserve(LENGTH_PREFIX_LEN);
+        buffer.put_i32_be(0);
+        self.codec.encode(message, buffer);
+        let size = (buffer.len() - start - LENGTH_PREFIX_LEN) as i32;
+        buffer[start..start + LENGTH_PREFIX_LEN].copy_from_slice(&size.to_be_bytes());
+    }
+}
+
+/// Reassembles messages from a stream of length prefixed frames delivered in arbitrary chunks.
+///
+/// Bytes are added with `extend` as they arrive, and `decode` yields each message once its
+/// frame is complete.  A frame that fails to decode is discarded, so decoding can continue with
+/// the next one.  A frame whose length prefix is negative or over the message size limit leaves
+/// the stream unreadable, as there is no way to find the start of the next frame.
+#[derive(Debug, Clone, Default)]
+pub struct FrameDecoder<C = BinaryMessageCodec> {
+    buffer: BytesMut,
+    codec: C,
+    limits: DecodeLimits,
+}
+
+impl FrameDecoder {
+    pub fn new() -> FrameDecoder {
+        FrameDecoder::with_limits(DecodeLimits::new())
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> FrameDecoder {
+        FrameDecoder::with_codec(BinaryMessageCodec::with_limits(limits), limits)
+    }
+}
+
+impl<C: Codec> FrameDecoder<C> {
+    /// Decodes frames with `codec`.  Frame lengths are checked against the message size limit
+    /// of `limits` before a frame is buffered.
+    pub fn with_codec(codec: C, limits: DecodeLimits) -> FrameDecoder<C> {
+        FrameDecoder {
+            buffer: BytesMut::new(),
+            codec,
+            limits,
+        }
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Buffers a chunk of bytes read from the stream.
+    pub fn extend(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    /// Number of bytes buffered but not yet decoded.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Decodes the next message, or returns `None` until its frame has been fully buffered.
+    pub fn decode(&mut self) -> Result<Option<Message<'static>>, DecodeError> {
+        if self.buffer.len() < LENGTH_PREFIX_LEN {
+            return Ok(None);
+        }
+        let length = Cursor::new(&self.buffer[..LENGTH_PREFIX_LEN]).get_i32_be();
+        if length < 0 {
+            return Err(DecodeError::NegativeLength { offset: 0, length });
+        }
+        self.limits.check(Limit::MessageSize, length as usize, 0)?;
+
+        let frame_len = LENGTH_PREFIX_LEN + length as usize;
+        if self.buffer.len() < frame_len {
+            return Ok(None);
+        }
+        let frame = self.buffer.split_to(frame_len);
+        let message = self.codec.decode(&frame[LENGTH_PREFIX_LEN..])?;
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use codec::compact::CompactMessageCodec;
+    use codec::message_codec::encode_message;
+    use codec::message_pack::MessagePackCodec;
+    use message::message::{MapBuilder, MessageBuilder};
+
+    fn example<'a>(index: i32) -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+            .with_header("index", index)
+            .with_header("map", MapBuilder::new().insert("key", "value").build())
+            .with_body("body")
+            .build()
+    }
+
+    fn encode_frames(count: i32) -> BytesMut {
+        let encoder = FrameEncoder::new();
+        let mut buffer = BytesMut::new();
+        for index in 0..count {
+            encoder.encode(&example(index), &mut buffer);
+        }
+        buffer
+    }
+
+    #[test]
+    fn frame_layout() {
+        let bytes = encode_frames(1);
+        let message = encode_message(&example(0));
+        assert_eq!(bytes.len(), LENGTH_PREFIX_LEN + message.len());
+        assert_eq!(&bytes[..LENGTH_PREFIX_LEN], &[0, 0, 0, message.len() as u8][..]);
+        assert_eq!(&bytes[LENGTH_PREFIX_LEN..], &message[..]);
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        let bytes = encode_frames(5);
+        for &chunk_size in [1, 3, 7, 64, bytes.len()].iter() {
+            let mut decoder = FrameDecoder::new();
+            let mut output = Vec::new();
+            for chunk in bytes.chunks(chunk_size) {
+                decoder.extend(chunk);
+                while let Some(message) = decoder.decode().unwrap() {
+                    output.push(message);
+                }
+            }
+            let expected: Vec<Message> = (0..5).map(example).collect();
+            assert_eq!(output, expected, "Chunk size {}", chunk_size);
+            assert_eq!(decoder.buffered_len(), 0);
+        }
+    }
+
+    #[test]
+    fn partial_frame() {
+        let bytes = encode_frames(1);
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&bytes[..bytes.len() - 1]);
+        assert_eq!(decoder.decode(), Ok(None));
+        assert_eq!(decoder.buffered_len(), bytes.len() - 1);
+        decoder.extend(&bytes[bytes.len() - 1..]);
+        assert_eq!(decoder.decode(), Ok(Some(example(0))));
+    }
+
+    #[test]
+    fn invalid_frame_is_skipped() {
+        let mut bytes = encode_frames(2);
+        bytes[LENGTH_PREFIX_LEN] = 0x80;
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&bytes);
+        match decoder.decode() {
+            Err(DecodeError::InvalidFlags { offset: 0, .. }) => (),
+            other => panic!("Expected invalid flags, got {:?}", other),
+        }
+        assert_eq!(decoder.decode(), Ok(Some(example(1))));
+    }
+
+    #[test]
+    fn frames_with_other_codecs() {
+        fn round_trip<C: Codec + Clone>(codec: C) {
+            let encoder = FrameEncoder::with_codec(codec.clone());
+            let mut bytes = BytesMut::new();
+            encoder.encode(&example(0), &mut bytes);
+            encoder.encode(&example(1), &mut bytes);
+
+            let mut decoder = FrameDecoder::with_codec(codec, DecodeLimits::new());
+            decoder.extend(&bytes);
+            assert_eq!(decoder.decode(), Ok(Some(example(0))));
+            assert_eq!(decoder.decode(), Ok(Some(example(1))));
+            assert_eq!(decoder.decode(), Ok(None));
+        }
+        round_trip(CompactMessageCodec::new());
+        round_trip(MessagePackCodec::new());
+        round_trip(BinaryMessageCodec::new().with_checksum(true));
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut decoder = FrameDecoder::with_limits(DecodeLimits::new().with_max_message_size(16));
+        decoder.extend(&[0, 0, 1, 0]);
+        assert_eq!(
+            decoder.decode(),
+            Err(DecodeError::LimitExceeded {
+                offset: 0,
+                limit: Limit::MessageSize,
+                actual: 256,
+                max: 16,
+            })
+        );
+
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(&[0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(decoder.decode(), Err(DecodeError::NegativeLength { offset: 0, length: -1 }));
+    }
+}
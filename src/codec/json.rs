@@ -0,0 +1,568 @@
+//! JSON representation of `Message`.
+//!
+//! A message is a JSON object with the optional fields `timestamp`, `expiration`,
+//! `correlation_id`, `headers` and `body`.  Timestamps are RFC 3339 strings with nanosecond
+//! precision, or `[seconds, nanoseconds]` since the Unix epoch outside the years 0000 to 9999,
+//! which RFC 3339 cannot represent.  The correlation id is a hyphenated UUID string.
+//!
+//! Values that JSON can represent unambiguously use plain JSON:
+//!
+//! * `Null`, `Bool` and `Str` are `null`, booleans and strings.
+//! * `I32` is an integer and a finite `F64` a number with a fraction or exponent.
+//! * `List` is an array.
+//! * `Map` is an object when every key is a `Key::Str` that does not start with `$`.
+//!
+//! Everything else is an object with a single `$`-prefixed tag:
+//!
+//! ```text
+//! {"$i64": 42}
+//! {"$f32": 1.5}                 non-finite floats use "NaN", "Infinity" or "-Infinity"
+//! {"$f64": "NaN"}
+//! {"$bytes": "AQID"}            standard base64
+//! {"$uuid": "c6a1ff35-7a3b-4b0c-a4f3-8bba0f5b4b1e"}
+//! {"$timestamp": "2017-07-14T02:40:00.123456789+00:00"}
+//! {"$timestamp": [253402300800, 0]}   outside the years 0000 to 9999
+//! {"$decimal": "-12.340"}
+//! {"$duration": [90, 500000000]}   seconds and nanoseconds
+//! {"$i64_array": [1, 2, 3]}
+//! {"$f64_array": [1.5, "NaN"]}
+//! {"$map": [["name", "value"], [7, "integer key"]]}
+//! ```
+//!
+//! When decoding, plain integers that do not fit in an `I32` become an `I64` and plain floats
+//! become an `F64`, so hand-written JSON does not need tags for ordinary numbers.
+
+use base64;
+use chrono::{DateTime, Datelike, TimeZone, UTC};
+use codec::message_codec::duration;
+use message::message::{Key, List, Map, Message, Timestamp, Value};
+use serde_json::{self, Map as JsonMap, Number, Value as Json};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+const TIMESTAMP: &str = "timestamp";
+const EXPIRATION: &str = "expiration";
+const CORRELATION_ID: &str = "correlation_id";
+const HEADERS: &str = "headers";
+const BODY: &str = "body";
+
+const TAG_I64: &str = "$i64";
+const TAG_F32: &str = "$f32";
+const TAG_F64: &str = "$f64";
+const TAG_BYTES: &str = "$bytes";
+const TAG_UUID: &str = "$uuid";
+const TAG_TIMESTAMP: &str = "$timestamp";
+const TAG_DECIMAL: &str = "$decimal";
+const TAG_DURATION: &str = "$duration";
+const TAG_I64_ARRAY: &str = "$i64_array";
+const TAG_F64_ARRAY: &str = "$f64_array";
+const TAG_MAP: &str = "$map";
+
+/// Error produced when JSON cannot be read as a message.
+///
+/// `path` is a JSON Pointer to the offending element.
+#[derive(Debug)]
+pub enum JsonError {
+    Syntax(serde_json::Error),
+    Invalid { path: String, reason: String },
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonError::Syntax(ref error) => write!(f, "Invalid JSON: {}", error),
+            JsonError::Invalid { ref path, ref reason } => {
+                write!(f, "Invalid message JSON at '{}': {}", path, reason)
+            }
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(error: serde_json::Error) -> Self {
+        JsonError::Syntax(error)
+    }
+}
+
+pub fn encode_message(message: &Message) -> String {
+    message_to_json(message).to_string()
+}
+
+pub fn encode_message_pretty(message: &Message) -> String {
+    serde_json::to_string_pretty(&message_to_json(message)).expect("Error writing JSON")
+}
+
+pub fn decode_message<'a>(json: &str) -> Result<Message<'a>, JsonError> {
+    let json: Json = serde_json::from_str(json)?;
+    message_from_json(&json)
+}
+
+pub fn message_to_json(message: &Message) -> Json {
+    let mut object = JsonMap::new();
+    if let Some(timestamp) = message.timestamp() {
+        object.insert(TIMESTAMP.to_owned(), timestamp_to_json(timestamp));
+    }
+    if let Some(expiration) = message.expiration() {
+        object.insert(EXPIRATION.to_owned(), timestamp_to_json(expiration));
+    }
+    if let Some(correlation_id) = message.correlation_id() {
+        object.insert(
+            CORRELATION_ID.to_owned(),
+            Json::String(correlation_id.hyphenated().to_string()),
+        );
+    }
+    if !message.headers().is_empty() {
+        object.insert(HEADERS.to_owned(), map_to_json(message.headers()));
+    }
+    if let Some(body) = message.body() {
+        object.insert(BODY.to_owned(), value_to_json(body));
+    }
+    Json::Object(object)
+}
+
+pub fn message_from_json<'a>(json: &Json) -> Result<Message<'a>, JsonError> {
+    let object = match *json {
+        Json::Object(ref object) => object,
+        _ => return Err(invalid("", "expected a message object")),
+    };
+    let mut message = Message::new();
+    for (name, value) in object.iter() {
+        let path = format!("/{}", escape(name));
+        match name.as_ref() {
+            TIMESTAMP => message.set_timestamp(Some(timestamp_from_json(&path, value)?)),
+            EXPIRATION => message.set_expiration(Some(timestamp_from_json(&path, value)?)),
+            CORRELATION_ID => message.set_correlation_id(Some(uuid_from_json(&path, value)?)),
+            HEADERS => match value_from_json_at(&path, value)? {
+                Value::Map(headers) => *message.headers_mut() = headers,
+                _ => return Err(invalid(&path, "expected a map")),
+            },
+            BODY => message.set_body(Some(value_from_json_at(&path, value)?)),
+            _ => return Err(invalid(&path, "unknown message field")),
+        }
+    }
+    Ok(message)
+}
+
+pub fn value_to_json(value: &Value) -> Json {
+    match value {
+        Value::Null => Json::Null,
+        Value::Str(ref value) => Json::String(value.to_string()),
+        Value::I32(value) => Json::from(*value),
+        Value::I64(value) => tagged(TAG_I64, Json::from(*value)),
+        Value::F32(value) => tagged(TAG_F32, float_to_json(f64::from(*value))),
+        Value::F64(value) => match Number::from_f64(*value) {
+            Some(number) => Json::Number(number),
+            None => tagged(TAG_F64, float_to_json(*value)),
+        },
+        Value::Bool(value) => Json::Bool(*value),
+        Value::Bytes(ref value) => tagged(TAG_BYTES, Json::String(base64::encode(value))),
+        Value::List(ref value) => Json::Array(value.iter().map(value_to_json).collect()),
+        Value::Map(ref value) => map_to_json(value),
+        Value::Uuid(value) => tagged(TAG_UUID, Json::String(value.hyphenated().to_string())),
+        Value::Timestamp(value) => tagged(TAG_TIMESTAMP, timestamp_to_json(*value)),
+        Value::Decimal(value) => tagged(TAG_DECIMAL, Json::String(value.to_string())),
+        Value::Duration(value) => {
+            let parts = vec![Json::from(value.as_secs()), Json::from(value.subsec_nanos())];
+            tagged(TAG_DURATION, Json::Array(parts))
+        }
+        Value::I64Array(ref values) => {
+            tagged(TAG_I64_ARRAY, Json::Array(values.iter().map(|v| Json::from(*v)).collect()))
+        }
+        Value::F64Array(ref values) => {
+            tagged(TAG_F64_ARRAY, Json::Array(values.iter().map(|v| float_to_json(*v)).collect()))
+        }
+    }
+}
+
+pub fn value_from_json<'a>(json: &Json) -> Result<Value<'a>, JsonError> {
+    value_from_json_at("", json)
+}
+
+fn map_to_json(map: &Map) -> Json {
+    let plain = map.iter().all(|(key, _)| match key {
+        Key::Str(ref key) => !key.starts_with('$'),
+        Key::I32(_) => false,
+    });
+    if plain {
+        let mut object = JsonMap::new();
+        for (key, value) in map.iter() {
+            if let Key::Str(ref key) = key {
+                object.insert(key.to_string(), value_to_json(value));
+            }
+        }
+        Json::Object(object)
+    } else {
+        let entries = map
+            .iter()
+            .map(|(key, value)| {
+                let key = match key {
+                    Key::Str(ref key) => Json::String(key.to_string()),
+                    Key::I32(key) => Json::from(*key),
+                };
+                Json::Array(vec![key, value_to_json(value)])
+            })
+            .collect();
+        tagged(TAG_MAP, Json::Array(entries))
+    }
+}
+
+fn float_to_json(value: f64) -> Json {
+    if value.is_nan() {
+        Json::String("NaN".to_owned())
+    } else if value.is_infinite() && value > 0.0 {
+        Json::String("Infinity".to_owned())
+    } else if value.is_infinite() {
+        Json::String("-Infinity".to_owned())
+    } else {
+        Number::from_f64(value).map(Json::Number).unwrap_or(Json::Null)
+    }
+}
+
+fn timestamp_to_json(timestamp: Timestamp) -> Json {
+    if (0..=9999).contains(&timestamp.year()) {
+        Json::String(timestamp.to_rfc3339())
+    } else {
+        let nanos = timestamp.timestamp_subsec_nanos();
+        Json::Array(vec![Json::from(timestamp.timestamp()), Json::from(nanos)])
+    }
+}
+
+fn tagged(tag: &str, value: Json) -> Json {
+    let mut object = JsonMap::new();
+    object.insert(tag.to_owned(), value);
+    Json::Object(object)
+}
+
+fn value_from_json_at<'a>(path: &str, json: &Json) -> Result<Value<'a>, JsonError> {
+    let value = match *json {
+        Json::Null => Value::Null,
+        Json::Bool(value) => Value::Bool(value),
+        Json::String(ref value) => Value::Str(Cow::Owned(value.clone())),
+        Json::Number(ref number) => number_from_json(path, number)?,
+        Json::Array(ref values) => {
+            let mut list = List::new();
+            for (index, value) in values.iter().enumerate() {
+                list.push(value_from_json_at(&format!("{}/{}", path, index), value)?);
+            }
+            Value::List(list)
+        }
+        Json::Object(ref object) => {
+            if object.len() == 1 {
+                let (tag, value) = object.iter().next().unwrap();
+                if tag.starts_with('$') {
+                    return tagged_from_json(&format!("{}/{}", path, escape(tag)), tag, value);
+                }
+            }
+            let mut map = Map::new();
+            for (key, value) in object.iter() {
+                let path = format!("{}/{}", path, escape(key));
+                map.insert(key.clone(), value_from_json_at(&path, value)?);
+            }
+            Value::Map(map)
+        }
+    };
+    Ok(value)
+}
+
+fn number_from_json<'a>(path: &str, number: &Number) -> Result<Value<'a>, JsonError> {
+    if let Some(value) = number.as_i64() {
+        if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) {
+            return Ok(Value::I32(value as i32));
+        }
+        return Ok(Value::I64(value));
+    }
+    if number.is_u64() {
+        return Err(invalid(path, "integer out of range"));
+    }
+    match number.as_f64() {
+        Some(value) => Ok(Value::F64(value)),
+        None => Err(invalid(path, "invalid number")),
+    }
+}
+
+fn tagged_from_json<'a>(path: &str, tag: &str, json: &Json) -> Result<Value<'a>, JsonError> {
+    let value = match tag {
+        TAG_I64 => match json.as_i64() {
+            Some(value) => Value::I64(value),
+            None => return Err(invalid(path, "expected a 64 bit integer")),
+        },
+        TAG_F32 => Value::F32(float_from_json(path, json)? as f32),
+        TAG_F64 => Value::F64(float_from_json(path, json)?),
+        TAG_BYTES => match json.as_str().map(base64::decode) {
+            Some(Ok(bytes)) => Value::Bytes(Cow::Owned(bytes)),
+            _ => return Err(invalid(path, "expected a base64 string")),
+        },
+        TAG_UUID => Value::Uuid(uuid_from_json(path, json)?),
+        TAG_TIMESTAMP => Value::Timestamp(timestamp_from_json(path, json)?),
+        TAG_DECIMAL => match json.as_str().map(str::parse) {
+            Some(Ok(decimal)) => Value::Decimal(decimal),
+            _ => return Err(invalid(path, "expected a decimal string")),
+        },
+        TAG_DURATION => Value::Duration(duration_from_json(path, json)?),
+        TAG_I64_ARRAY => {
+            let mut values = Vec::new();
+            for (index, element) in array_from_json(path, json)?.iter().enumerate() {
+                match element.as_i64() {
+                    Some(value) => values.push(value),
+                    None => {
+                        let path = format!("{}/{}", path, index);
+                        return Err(invalid(&path, "expected a 64 bit integer"));
+                    }
+                }
+            }
+            Value::I64Array(values)
+        }
+        TAG_F64_ARRAY => {
+            let mut values = Vec::new();
+            for (index, element) in array_from_json(path, json)?.iter().enumerate() {
+                values.push(float_from_json(&format!("{}/{}", path, index), element)?);
+            }
+            Value::F64Array(values)
+        }
+        TAG_MAP => {
+            let entries = match json.as_array() {
+                Some(entries) => entries,
+                None => return Err(invalid(path, "expected an array of map entries")),
+            };
+            let mut map = Map::new();
+            for (index, entry) in entries.iter().enumerate() {
+                let path = format!("{}/{}", path, index);
+                let (key, value) = match entry.as_array() {
+                    Some(entry) if entry.len() == 2 => (&entry[0], &entry[1]),
+                    _ => return Err(invalid(&path, "expected a [key, value] pair")),
+                };
+                let key: Key = match *key {
+                    Json::String(ref key) => key.clone().into(),
+                    Json::Number(ref key) => match key.as_i64() {
+                        Some(key)
+                            if key >= i64::from(i32::MIN)
+                                && key <= i64::from(i32::MAX) =>
+                        {
+                            (key as i32).into()
+                        }
+                        _ => return Err(invalid(&path, "expected a 32 bit integer key")),
+                    },
+                    _ => return Err(invalid(&path, "expected a string or integer key")),
+                };
+                map.insert(key, value_from_json_at(&format!("{}/1", path), value)?);
+            }
+            Value::Map(map)
+        }
+        _ => return Err(invalid(path, "unknown tag")),
+    };
+    Ok(value)
+}
+
+fn float_from_json(path: &str, json: &Json) -> Result<f64, JsonError> {
+    match *json {
+        Json::Number(ref number) => number
+            .as_f64()
+            .ok_or_else(|| invalid(path, "invalid number")),
+        Json::String(ref value) => match value.as_ref() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => Err(invalid(path, "expected a number, \"NaN\", \"Infinity\" or \"-Infinity\"")),
+        },
+        _ => Err(invalid(path, "expected a number")),
+    }
+}
+
+fn timestamp_from_json(path: &str, json: &Json) -> Result<Timestamp, JsonError> {
+    let timestamp = match *json {
+        Json::String(ref value) => DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&UTC)),
+        Json::Array(ref parts) => match parts[..] {
+            [ref seconds, ref nanos] => match (seconds.as_i64(), nanos.as_u64()) {
+                (Some(seconds), Some(nanos)) if nanos <= u64::from(u32::MAX) => {
+                    UTC.timestamp_opt(seconds, nanos as u32).single()
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    timestamp.ok_or_else(|| {
+        invalid(path, "expected an RFC 3339 timestamp or [seconds, nanoseconds]")
+    })
+}
+
+fn duration_from_json(path: &str, json: &Json) -> Result<Duration, JsonError> {
+    let parts = json.as_array().map(|parts| parts.iter().map(Json::as_u64).collect::<Vec<_>>());
+    match parts.as_ref().map(|parts| &parts[..]) {
+        Some(&[Some(seconds), Some(nanos)]) => {
+            duration(0, seconds, nanos).map_err(|_| invalid(path, "nanoseconds out of range"))
+        }
+        _ => Err(invalid(path, "expected [seconds, nanoseconds]")),
+    }
+}
+
+fn array_from_json<'j>(path: &str, json: &'j Json) -> Result<&'j Vec<Json>, JsonError> {
+    json.as_array().ok_or_else(|| invalid(path, "expected an array"))
+}
+
+fn uuid_from_json(path: &str, json: &Json) -> Result<Uuid, JsonError> {
+    match json.as_str().map(Uuid::parse_str) {
+        Some(Ok(uuid)) => Ok(uuid),
+        _ => Err(invalid(path, "expected a UUID string")),
+    }
+}
+
+fn invalid(path: &str, reason: &str) -> JsonError {
+    JsonError::Invalid {
+        path: path.to_owned(),
+        reason: reason.to_owned(),
+    }
+}
+
+/// Escapes a key for use as a JSON Pointer reference token.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use message::message::{Decimal, ListBuilder, MapBuilder, MessageBuilder};
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 123_456_789))
+            .with_correlation_id(Uuid::parse_str("c6a1ff35-7a3b-4b0c-a4f3-8bba0f5b4b1e").unwrap())
+            .with_header("content-type", "application/json")
+            .with_header("attempt", 3)
+            .with_header("sequence", 1i64 << 40)
+            .with_header("ratio", 0.25f32)
+            .with_header("score", 98.6)
+            .with_header("nan", f64::NAN)
+            .with_header("retry", false)
+            .with_header("signature", Value::Bytes(Cow::Owned(vec![1, 2, 3])))
+            .with_header("trace", Value::Uuid(Uuid::nil()))
+            .with_header("sent", UTC.timestamp(-86_400, 1))
+            .with_header(
+                "ids",
+                MapBuilder::new().insert(7, "seven").insert("$eight", 8).build(),
+            )
+            .with_body(
+                MapBuilder::new()
+                    .insert("name", "jimmie")
+                    .insert("tags", ListBuilder::new().push("a").push(Value::Null).build())
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = example();
+        let json = encode_message(&message);
+        let output = decode_message(&json).unwrap();
+        // NaN is never equal to itself, so compare the re-encoded JSON as well as the parts.
+        assert_eq!(encode_message(&output), json);
+        assert_eq!(output.body(), message.body());
+        assert_eq!(output.timestamp(), message.timestamp());
+        assert_eq!(output.correlation_id(), message.correlation_id());
+        for (key, value) in message.headers().iter() {
+            if *key != Key::from("nan") {
+                assert_eq!(output.headers().get(key), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn encode_format() {
+        let message = MessageBuilder::new()
+            .with_header("int", 1)
+            .with_header("long", 2i64)
+            .with_header("bytes", Value::Bytes(Cow::Borrowed(&b"hi"[..])))
+            .with_body(MapBuilder::new().insert(1, 1.5).build())
+            .build();
+        assert_eq!(
+            encode_message(&message),
+            r#"{"headers":{"int":1,"long":{"$i64":2},"bytes":{"$bytes":"aGk="}},"body":{"$map":[[1,1.5]]}}"#
+        );
+    }
+
+    #[test]
+    fn decode_hand_written() {
+        let message = decode_message(
+            r#"{
+                "timestamp": "2017-07-14T02:40:00Z",
+                "headers": {
+                    "count": 5000000000,
+                    "rate": 2.5,
+                    "when": {"$timestamp": "1970-01-01T00:00:00Z"}
+                },
+                "body": [1, "two"]
+            }"#,
+        ).unwrap();
+        assert_eq!(message.timestamp(), Some(UTC.timestamp(1_500_000_000, 0)));
+        assert_eq!(message.headers().get(&Key::from("count")), Some(&Value::I64(5_000_000_000)));
+        assert_eq!(message.headers().get(&Key::from("rate")), Some(&Value::F64(2.5)));
+        assert_eq!(
+            message.headers().get(&Key::from("when")),
+            Some(&Value::Timestamp(UTC.timestamp(0, 0)))
+        );
+        assert_eq!(message.body(), Some(&ListBuilder::new().push(1).push("two").build().into()));
+    }
+
+    #[test]
+    fn extreme_decimals() {
+        for &scale in [0, 255].iter() {
+            let value = Value::Decimal(Decimal::new(i128::MIN, scale));
+            assert_eq!(value_from_json(&value_to_json(&value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn timestamps_outside_rfc_3339() {
+        for &(seconds, nanos) in [(253_402_300_800, 0), (-62_167_219_201, 999_999_999)].iter() {
+            let timestamp = UTC.timestamp(seconds, nanos);
+            let message = MessageBuilder::new()
+                .with_timestamp(timestamp)
+                .with_header("when", timestamp)
+                .build();
+            let json = encode_message(&message);
+            assert_eq!(
+                json,
+                format!(
+                    r#"{{"timestamp":[{0},{1}],"headers":{{"when":{{"$timestamp":[{0},{1}]}}}}}}"#,
+                    seconds, nanos
+                )
+            );
+            assert_eq!(decode_message(&json).unwrap(), message);
+        }
+        let last = UTC.timestamp(253_402_300_799, 0);
+        assert_eq!(timestamp_to_json(last), Json::String(last.to_rfc3339()));
+        for &invalid in ["[1]", "[1, -1]", "[1, 4294967296]", "[1.5, 0]"].iter() {
+            match decode_message(&format!(r#"{{"timestamp": {}}}"#, invalid)) {
+                Err(JsonError::Invalid { ref path, .. }) if path == "/timestamp" => (),
+                other => panic!("Expected invalid timestamp for {}, got {:?}", invalid, other),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_invalid_reports_path() {
+        match decode_message(r#"{"body": {"items": [1, {"$uuid": "nope"}]}}"#) {
+            Err(JsonError::Invalid { ref path, .. }) if path == "/body/items/1/$uuid" => (),
+            other => panic!("Expected invalid uuid error, got {:?}", other),
+        }
+        match decode_message(r#"{"headers": []}"#) {
+            Err(JsonError::Invalid { ref path, .. }) if path == "/headers" => (),
+            other => panic!("Expected invalid headers error, got {:?}", other),
+        }
+        match decode_message("{") {
+            Err(JsonError::Syntax(_)) => (),
+            other => panic!("Expected syntax error, got {:?}", other),
+        }
+    }
+}
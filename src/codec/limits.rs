@@ -0,0 +1,125 @@
+use codec::error::DecodeError;
+use std::fmt;
+
+/// Upper bounds the decoder enforces on untrusted input.
+///
+/// Counts and lengths are read from the wire before the data they describe, so without limits
+/// a corrupt or hostile message can request huge allocations or nest deeply enough to
+/// overflow the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    max_depth: usize,
+    max_collection_len: usize,
+    max_bytes_len: usize,
+    max_message_size: usize,
+}
+
+/// The individual limits of `DecodeLimits`, as reported by `DecodeError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    CollectionLength,
+    BytesLength,
+    MessageSize,
+}
+
+impl DecodeLimits {
+    pub fn new() -> DecodeLimits {
+        DecodeLimits {
+            max_depth: 64,
+            max_collection_len: 1 << 20,
+            max_bytes_len: 16 << 20,
+            max_message_size: 64 << 20,
+        }
+    }
+
+    pub fn unlimited() -> DecodeLimits {
+        DecodeLimits {
+            max_depth: usize::MAX,
+            max_collection_len: usize::MAX,
+            max_bytes_len: usize::MAX,
+            max_message_size: usize::MAX,
+        }
+    }
+
+    /// Maximum nesting of maps and lists.  Message headers count as one level.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn with_max_depth(mut self, value: usize) -> DecodeLimits {
+        self.max_depth = value;
+        self
+    }
+
+    /// Maximum number of entries in a single map or list, including message headers.
+    pub fn max_collection_len(&self) -> usize {
+        self.max_collection_len
+    }
+
+    pub fn with_max_collection_len(mut self, value: usize) -> DecodeLimits {
+        self.max_collection_len = value;
+        self
+    }
+
+    /// Maximum length in bytes of a single string or bytes value.
+    pub fn max_bytes_len(&self) -> usize {
+        self.max_bytes_len
+    }
+
+    pub fn with_max_bytes_len(mut self, value: usize) -> DecodeLimits {
+        self.max_bytes_len = value;
+        self
+    }
+
+    /// Maximum size in bytes of an encoded message.
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    pub fn with_max_message_size(mut self, value: usize) -> DecodeLimits {
+        self.max_message_size = value;
+        self
+    }
+
+    pub(crate) fn check(
+        &self,
+        limit: Limit,
+        actual: usize,
+        offset: usize,
+    ) -> Result<(), DecodeError> {
+        let max = match limit {
+            Limit::Depth => self.max_depth,
+            Limit::CollectionLength => self.max_collection_len,
+            Limit::BytesLength => self.max_bytes_len,
+            Limit::MessageSize => self.max_message_size,
+        };
+        if actual > max {
+            return Err(DecodeError::LimitExceeded {
+                offset,
+                limit,
+                actual,
+                max,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits::new()
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Limit::Depth => "nesting depth",
+            Limit::CollectionLength => "collection length",
+            Limit::BytesLength => "string or bytes length",
+            Limit::MessageSize => "message size",
+        };
+        f.write_str(name)
+    }
+}
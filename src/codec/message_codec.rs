@@ -1,258 +1,258 @@
-use message::message::{Message, List, Map, Key, Value, Timestamp};
+use message::message::{Message, List, Map, Key, Value, Timestamp, Decimal};
 use uuid::Uuid;
 use std::borrow::Cow;
 use chrono::{UTC, TimeZone};
 use codec::util;
-use bytes::{BytesMut, Buf, BufMut, IntoBuf};
+use codec::Codec;
+use codec::error::DecodeError;
+use codec::buf::{BoundedBuf, DecodeBuf, SliceBuf};
+use codec::compression::{Algorithm, Compression};
+use codec::crc32c::crc32c;
+use codec::limits::{DecodeLimits, Limit};
+use bytes::{Buf, BytesMut, BufMut};
+use std::cmp::Ordering;
+use std::io::Cursor;
+use std::mem;
+use std::time::Duration;
 use codec::size_calculator::calculate_message_size;
 
 pub fn encode_message(message: &Message) -> BytesMut {
     let size = calculate_message_size(message);
     let mut buffer = BytesMut::with_capacity(size as usize);
-    let codec = BinaryMessageCodec;
+    let codec = BinaryMessageCodec::new();
     codec.encode_message(message, &mut buffer);
     buffer
 }
 
-pub fn decode_message<'a, B: IntoBuf>(buffer: B) -> Message<'a> {
-    let codec = BinaryMessageCodec;
-    codec.decode_message(&mut buffer.into_buf())
+pub fn decode_message<'a, T: AsRef<[u8]>>(buffer: T) -> Result<Message<'a>, DecodeError> {
+    let codec = BinaryMessageCodec::new();
+    codec.decode_message(&mut Cursor::new(buffer))
 }
 
-trait MessageDecoder<'a, B> {
-    fn decode_message(&self, buffer: &mut B) -> Message<'a>;
-
-    fn decode_key(&self, buffer: &mut B) -> Key<'a>;
-
-    fn decode_value(&self, buffer: &mut B) -> Value<'a>;
-
-    fn decode_map(&self, buffer: &mut B) -> Map<'a>;
-
-    fn decode_list(&self, buffer: &mut B) -> List<'a>;
-
-    fn decode_string(&self, buffer: &mut B) -> Cow<'a, str>;
-
-    fn decode_timestamp(&self, buffer: &mut B) -> Timestamp;
-
-    fn decode_uuid(&self, buffer: &mut B) -> Uuid;
-
-    fn decode_bytes(&self, buffer: &mut B) -> Cow<'a, [u8]>;
-
-    fn decode_i32(&self, buffer: &mut B) -> i32;
-
-    fn decode_i64(&self, buffer: &mut B) -> i64;
-
-    fn decode_f32(&self, buffer: &mut B) -> f32;
-
-    fn decode_f64(&self, buffer: &mut B) -> f64;
-
-    fn decode_bool(&self, buffer: &mut B) -> bool;
+/// Decodes a message whose strings, bytes and string keys borrow from `buffer` rather than
+/// being copied out of it.
+pub fn decode_message_borrowed<'a>(buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+    let codec = BinaryMessageCodec::new();
+    codec.decode_message(&mut SliceBuf::new(buffer))
 }
 
-trait MessageEncoder<'a, B> {
-    fn encode_message(&self, value: &Message<'a>, buffer: &mut B);
-
-    fn encode_key(&self, value: &Key<'a>, buffer: &mut B);
-
-    fn encode_value(&self, value: &Value<'a>, buffer: &mut B);
-
-    fn encode_map(&self, value: &Map<'a>, buffer: &mut B);
-
-    fn encode_list(&self, value: &List<'a>, buffer: &mut B);
-
-    fn encode_string(&self, value: &Cow<'a, str>, buffer: &mut B);
-
-    fn encode_timestamp(&self, value: Timestamp, buffer: &mut B);
-
-    fn encode_uuid(&self, value: Uuid, buffer: &mut B);
-
-    fn encode_bytes(&self, value: &Cow<'a, [u8]>, buffer: &mut B);
-
-    fn encode_i32(&self, value: i32, buffer: &mut B);
-
-    fn encode_i64(&self, value: i64, buffer: &mut B);
-
-    fn encode_f32(&self, value: f32, buffer: &mut B);
-
-    fn encode_f64(&self, value: f64, buffer: &mut B);
+/// Encodes `message` followed by a big endian CRC32C of the encoded bytes.
+pub fn encode_message_with_checksum(message: &Message) -> BytesMut {
+    let size = calculate_message_size(message) + CHECKSUM_LEN as i32;
+    let mut buffer = BytesMut::with_capacity(size as usize);
+    let codec = BinaryMessageCodec::new().with_checksum(true);
+    codec.encode_message(message, &mut buffer);
+    buffer
+}
 
-    fn encode_bool(&self, value: bool, buffer: &mut B);
+/// Decodes a message written by `encode_message_with_checksum`, failing with
+/// `DecodeError::ChecksumMismatch` if the bytes do not match their checksum.
+pub fn decode_message_with_checksum<'a, T: AsRef<[u8]>>(
+    buffer: T,
+) -> Result<Message<'a>, DecodeError> {
+    let codec = BinaryMessageCodec::new().with_checksum(true);
+    codec.decode_message(&mut Cursor::new(buffer))
 }
 
-pub struct BinaryMessageCodec;
+/// Encodes `message` in canonical form, so that equal messages always encode to the same bytes.
+pub fn encode_message_canonical(message: &Message) -> BytesMut {
+    let size = calculate_message_size(message);
+    let mut buffer = BytesMut::with_capacity(size as usize);
+    let codec = BinaryMessageCodec::new().with_canonical(true);
+    codec.encode_message(message, &mut buffer);
+    buffer
+}
 
-impl<'a, B> MessageDecoder<'a, B> for BinaryMessageCodec
-    where B: Buf
-{
-    fn decode_message(&self, buffer: &mut B) -> Message<'a> {
-        let mut message = Message::new();
+/// Length of the trailing checksum written when checksums are enabled.
+pub const CHECKSUM_LEN: usize = 4;
 
-        let flags = util::Flags::from_bits(self.decode_i32(buffer)).expect("Error reading flags");
+/// Decodes the parts of a message from a buffer of type `B`.
+///
+/// Implemented by the binary formats for every `DecodeBuf`, which decides whether decoded
+/// strings and bytes borrow from the buffer.  The formats share their type tags and layout and
+/// differ in how they write lengths, integers and durations, so a format supplies those and
+/// the message itself, and the traversal of keys, values and collections is provided.
+pub trait MessageDecoder<'a, B: DecodeBuf<'a>> {
+    /// The fewest bytes an encoded `i64` takes, which bounds how many elements of an
+    /// `I64Array` the rest of a buffer can hold.
+    const MIN_I64_LEN: usize;
 
-        if flags.contains(util::Flags::HAS_TIMESTAMP) {
-            message.set_timestamp(Some(self.decode_timestamp(buffer)));
-        }
+    fn limits(&self) -> &DecodeLimits;
 
-        if flags.contains(util::Flags::HAS_EXPIRATION) {
-            message.set_expiration(Some(self.decode_timestamp(buffer)));
-        }
+    fn decode_message(&self, buffer: &mut B) -> Result<Message<'a>, DecodeError>;
 
-        if flags.contains(util::Flags::HAS_CORRELATION_ID) {
-            message.set_correlation_id(Some(self.decode_uuid(buffer)));
-        }
+    /// Decodes a length or count, checking it against `limit`.
+    fn decode_len(&self, buffer: &mut B, limit: Limit) -> Result<usize, DecodeError>;
 
-        if flags.contains(util::Flags::HAS_HEADERS) {
-            let count = self.decode_i32(buffer);
-            for _ in 0..count {
-                message.headers_mut().insert(self.decode_key(buffer), self.decode_value(buffer));
-            }
-        }
+    fn decode_duration(&self, buffer: &mut B) -> Result<Duration, DecodeError>;
 
-        if flags.contains(util::Flags::HAS_BODY) {
-            message.set_body(Some(self.decode_value(buffer)));
-        }
+    fn decode_i32(&self, buffer: &mut B) -> Result<i32, DecodeError>;
 
-        message
-    }
+    fn decode_i64(&self, buffer: &mut B) -> Result<i64, DecodeError>;
 
-    fn decode_key(&self, buffer: &mut B) -> Key<'a> {
+    fn decode_key(&self, buffer: &mut B) -> Result<Key<'a>, DecodeError> {
+        let tag_offset = buffer.position();
+        buffer.require(1)?;
         let key_type = buffer.get_u8();
         match key_type {
-            1 => Key::Str(self.decode_string(buffer)),
-            2 => Key::I32(self.decode_i32(buffer)),
-            _ => panic!("Unsupported key type '{}", key_type),
+            1 => Ok(Key::Str(self.decode_string(buffer)?)),
+            2 => Ok(Key::I32(self.decode_i32(buffer)?)),
+            _ => Err(DecodeError::UnknownKeyType {
+                offset: tag_offset,
+                tag: key_type,
+            }),
         }
     }
 
-    fn decode_value(&self, buffer: &mut B) -> Value<'a> {
+    fn decode_value(&self, buffer: &mut B, depth: usize) -> Result<Value<'a>, DecodeError> {
+        let tag_offset = buffer.position();
+        buffer.require(1)?;
         let value_type = buffer.get_u8();
-        match value_type {
+        let value = match value_type {
             0 => Value::Null,
-            1 => Value::Str(self.decode_string(buffer)),
-            2 => Value::I32(self.decode_i32(buffer)),
-            3 => Value::I64(self.decode_i64(buffer)),
-            4 => Value::F32(self.decode_f32(buffer)),
-            5 => Value::F64(self.decode_f64(buffer)),
-            6 => Value::Bool(self.decode_bool(buffer)),
-            7 => Value::Bytes(self.decode_bytes(buffer)),
-            8 => Value::List(self.decode_list(buffer)),
-            9 => Value::Map(self.decode_map(buffer)),
-            10 => Value::Uuid(self.decode_uuid(buffer)),
-            11 => Value::Timestamp(self.decode_timestamp(buffer)),
-            _ => panic!("Unsupported value type '{}'", value_type),
-        }
-    }
-
-    fn decode_map(&self, buffer: &mut B) -> Map<'a> {
+            1 => Value::Str(self.decode_string(buffer)?),
+            2 => Value::I32(self.decode_i32(buffer)?),
+            3 => Value::I64(self.decode_i64(buffer)?),
+            4 => Value::F32(self.decode_f32(buffer)?),
+            5 => Value::F64(self.decode_f64(buffer)?),
+            6 => Value::Bool(self.decode_bool(buffer)?),
+            7 => Value::Bytes(self.decode_bytes(buffer)?),
+            8 => Value::List(self.decode_list(buffer, depth + 1)?),
+            9 => Value::Map(self.decode_map(buffer, depth + 1)?),
+            10 => Value::Uuid(self.decode_uuid(buffer)?),
+            11 => Value::Timestamp(self.decode_timestamp(buffer)?),
+            12 => Value::Decimal(self.decode_decimal(buffer)?),
+            13 => Value::Duration(self.decode_duration(buffer)?),
+            14 => {
+                let count = self.decode_len(buffer, Limit::CollectionLength)?;
+                let capacity = count.min(buffer.remaining() / Self::MIN_I64_LEN);
+                let mut values = Vec::with_capacity(capacity);
+                for _ in 0..count {
+                    values.push(self.decode_i64(buffer)?);
+                }
+                Value::I64Array(values)
+            }
+            15 => {
+                let count = self.decode_len(buffer, Limit::CollectionLength)?;
+                let capacity = count.min(buffer.remaining() / mem::size_of::<f64>());
+                let mut values = Vec::with_capacity(capacity);
+                for _ in 0..count {
+                    values.push(self.decode_f64(buffer)?);
+                }
+                Value::F64Array(values)
+            }
+            _ => {
+                return Err(DecodeError::UnknownValueType {
+                    offset: tag_offset,
+                    tag: value_type,
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn decode_map(&self, buffer: &mut B, depth: usize) -> Result<Map<'a>, DecodeError> {
+        self.limits().check(Limit::Depth, depth, buffer.position())?;
         let mut map = Map::new();
-        let count = buffer.get_i32_be();
+        let count = self.decode_len(buffer, Limit::CollectionLength)?;
         for _ in 0..count {
-            map.insert(self.decode_key(buffer), self.decode_value(buffer))
+            let key = self.decode_key(buffer)?;
+            let value = self.decode_value(buffer, depth)?;
+            map.insert(key, value);
         }
-        map
+        Ok(map)
     }
 
-    fn decode_list(&self, buffer: &mut B) -> List<'a> {
+    fn decode_list(&self, buffer: &mut B, depth: usize) -> Result<List<'a>, DecodeError> {
+        self.limits().check(Limit::Depth, depth, buffer.position())?;
         let mut list = List::new();
-        let count = buffer.get_i32_be();
+        let count = self.decode_len(buffer, Limit::CollectionLength)?;
         for _ in 0..count {
-            list.push(self.decode_value(buffer));
+            list.push(self.decode_value(buffer, depth)?);
         }
-        list
+        Ok(list)
     }
 
-    fn decode_bytes(&self, buffer: &mut B) -> Cow<'a, [u8]> {
-        let len = buffer.get_i32_be() as usize;
-        let bytes: Vec<u8> = buffer.take(len).collect();
-        bytes.into()
+    fn decode_string(&self, buffer: &mut B) -> Result<Cow<'a, str>, DecodeError> {
+        let len = self.decode_len(buffer, Limit::BytesLength)?;
+        buffer.take_str(len)
     }
 
-    fn decode_string(&self, buffer: &mut B) -> Cow<'a, str> {
-        let len = buffer.get_i32_be() as usize;
-        String::from_utf8(buffer.take(len).collect()).unwrap().into()
-    }
-
-    fn decode_timestamp(&self, buffer: &mut B) -> Timestamp {
-        UTC.timestamp(self.decode_i64(buffer), self.decode_i32(buffer) as u32)
+    fn decode_timestamp(&self, buffer: &mut B) -> Result<Timestamp, DecodeError> {
+        let timestamp_offset = buffer.position();
+        buffer.require(12)?;
+        let seconds = buffer.get_i64_be();
+        let nanos = buffer.get_i32_be();
+        if nanos < 0 {
+            return Err(DecodeError::InvalidTimestamp {
+                offset: timestamp_offset,
+                seconds,
+                nanos,
+            });
+        }
+        UTC.timestamp_opt(seconds, nanos as u32)
+            .single()
+            .ok_or(DecodeError::InvalidTimestamp {
+                offset: timestamp_offset,
+                seconds,
+                nanos,
+            })
     }
 
-    fn decode_uuid(&self, buffer: &mut B) -> Uuid {
-        let bytes: Vec<u8> = buffer.take(16).collect();
-        Uuid::from_bytes(&bytes).unwrap()
+    fn decode_uuid(&self, buffer: &mut B) -> Result<Uuid, DecodeError> {
+        let uuid_offset = buffer.position();
+        let bytes = buffer.take_bytes(16)?;
+        Uuid::from_bytes(&bytes).map_err(|_| DecodeError::InvalidUuid { offset: uuid_offset })
     }
 
-    fn decode_i32(&self, buffer: &mut B) -> i32 {
-        buffer.get_i32_be()
+    fn decode_decimal(&self, buffer: &mut B) -> Result<Decimal, DecodeError> {
+        buffer.require(17)?;
+        let high = buffer.get_i64_be();
+        let low = buffer.get_u64_be();
+        let scale = buffer.get_u8();
+        Ok(Decimal::new(i128::from(high) << 64 | i128::from(low), scale))
     }
 
-    fn decode_i64(&self, buffer: &mut B) -> i64 {
-        buffer.get_i64_be()
+    fn decode_bytes(&self, buffer: &mut B) -> Result<Cow<'a, [u8]>, DecodeError> {
+        let len = self.decode_len(buffer, Limit::BytesLength)?;
+        buffer.take_bytes(len)
     }
 
-    fn decode_f32(&self, buffer: &mut B) -> f32 {
-        buffer.get_f32_be()
+    fn decode_f32(&self, buffer: &mut B) -> Result<f32, DecodeError> {
+        buffer.require(4)?;
+        Ok(buffer.get_f32_be())
     }
 
-    fn decode_f64(&self, buffer: &mut B) -> f64 {
-        buffer.get_f64_be()
+    fn decode_f64(&self, buffer: &mut B) -> Result<f64, DecodeError> {
+        buffer.require(8)?;
+        Ok(buffer.get_f64_be())
     }
 
-    fn decode_bool(&self, buffer: &mut B) -> bool {
+    fn decode_bool(&self, buffer: &mut B) -> Result<bool, DecodeError> {
+        buffer.require(1)?;
         match buffer.get_u8() {
-            0 => false,
-            _ => true,
+            0 => Ok(false),
+            _ => Ok(true),
         }
     }
 }
 
-impl<'a, B> MessageEncoder<'a, B> for BinaryMessageCodec
-    where B: BufMut
-{
-    fn encode_message(&self, message: &Message<'a>, buffer: &mut B) {
-        let mut flags = util::Flags::empty();
-
-        if let Some(_) = message.timestamp() {
-            flags.insert(util::Flags::HAS_TIMESTAMP);
-        }
-
-        if let Some(_) = message.expiration() {
-            flags.insert(util::Flags::HAS_EXPIRATION);
-        }
-
-        if let Some(_) = message.correlation_id() {
-            flags.insert(util::Flags::HAS_CORRELATION_ID);
-        }
-
-        if message.headers().len() > 0 {
-            flags.insert(util::Flags::HAS_HEADERS);
-        }
-
-        if let Some(_) = message.body() {
-            flags.insert(util::Flags::HAS_BODY);
-        }
-
-        self.encode_i32(flags.bits(), buffer);
-
-        if let Some(timestamp) = message.timestamp() {
-            self.encode_timestamp(timestamp, buffer);
-        }
+/// Encodes the parts of a message into a buffer of type `B`.
+///
+/// Implemented by the binary formats for every `BufMut`.  The buffer must have room for the
+/// encoded message, as sized by `size_calculator`.  As with `MessageDecoder`, a format supplies
+/// the message, lengths, integers and durations, and the traversal is provided.
+pub trait MessageEncoder<'a, B: BufMut> {
+    fn encode_message(&self, value: &Message<'a>, buffer: &mut B);
 
-        if let Some(expiration) = message.expiration() {
-            self.encode_timestamp(expiration, buffer);
-        }
+    /// Encodes a length or count.
+    fn encode_len(&self, len: usize, buffer: &mut B);
 
-        if let Some(correlation_id) = message.correlation_id() {
-            self.encode_uuid(correlation_id, buffer);
-        }
+    fn encode_duration(&self, value: Duration, buffer: &mut B);
 
-        if message.headers().len() > 0 {
-            self.encode_map(&message.headers(), buffer);
-        }
+    fn encode_i32(&self, value: i32, buffer: &mut B);
 
-        if let Some(body) = message.body() {
-            self.encode_value(body, buffer);
-        }
-    }
+    fn encode_i64(&self, value: i64, buffer: &mut B);
 
     fn encode_key(&self, key: &Key<'a>, buffer: &mut B) {
         match key {
@@ -314,11 +314,33 @@ impl<'a, B> MessageEncoder<'a, B> for BinaryMessageCodec
                 buffer.put_u8(11);
                 self.encode_timestamp(*value, buffer)
             }
+            Value::Decimal(value) => {
+                buffer.put_u8(12);
+                self.encode_decimal(*value, buffer)
+            }
+            Value::Duration(value) => {
+                buffer.put_u8(13);
+                self.encode_duration(*value, buffer)
+            }
+            Value::I64Array(ref values) => {
+                buffer.put_u8(14);
+                self.encode_len(values.len(), buffer);
+                for value in values {
+                    self.encode_i64(*value, buffer);
+                }
+            }
+            Value::F64Array(ref values) => {
+                buffer.put_u8(15);
+                self.encode_len(values.len(), buffer);
+                for value in values {
+                    self.encode_f64(*value, buffer);
+                }
+            }
         }
     }
 
     fn encode_map(&self, map: &Map<'a>, buffer: &mut B) {
-        self.encode_i32(map.len() as i32, buffer);
+        self.encode_len(map.len(), buffer);
         for (key, value) in map.iter() {
             self.encode_key(key, buffer);
             self.encode_value(value, buffer);
@@ -326,31 +348,451 @@ impl<'a, B> MessageEncoder<'a, B> for BinaryMessageCodec
     }
 
     fn encode_list(&self, list: &List<'a>, buffer: &mut B) {
-        self.encode_i32(list.len() as i32, buffer);
+        self.encode_len(list.len(), buffer);
         for value in list.iter() {
             self.encode_value(value, buffer);
         }
     }
 
     fn encode_string(&self, value: &Cow<'a, str>, buffer: &mut B) {
-        self.encode_i32(value.len() as i32, buffer);
-        buffer.put_slice(value.as_ref().as_bytes());
+        self.encode_len(value.len(), buffer);
+        buffer.put_slice(value.as_bytes());
     }
 
     fn encode_timestamp(&self, value: Timestamp, buffer: &mut B) {
-        self.encode_i64(value.timestamp(), buffer);
-        self.encode_i32(value.timestamp_subsec_nanos() as i32, buffer);
+        buffer.put_i64_be(value.timestamp());
+        buffer.put_i32_be(value.timestamp_subsec_nanos() as i32);
     }
 
     fn encode_uuid(&self, value: Uuid, buffer: &mut B) {
         buffer.put_slice(value.as_bytes());
     }
 
+    fn encode_decimal(&self, value: Decimal, buffer: &mut B) {
+        buffer.put_i64_be((value.mantissa() >> 64) as i64);
+        buffer.put_u64_be(value.mantissa() as u64);
+        buffer.put_u8(value.scale());
+    }
+
     fn encode_bytes(&self, value: &Cow<'a, [u8]>, buffer: &mut B) {
-        self.encode_i32(value.len() as i32, buffer);
+        self.encode_len(value.len(), buffer);
         buffer.put_slice(value.as_ref());
     }
 
+    fn encode_f32(&self, value: f32, buffer: &mut B) {
+        buffer.put_f32_be(value);
+    }
+
+    fn encode_f64(&self, value: f64, buffer: &mut B) {
+        buffer.put_f64_be(value);
+    }
+
+    fn encode_bool(&self, value: bool, buffer: &mut B) {
+        buffer.put_u8(if value { 1 } else { 0 })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BinaryMessageCodec {
+    limits: DecodeLimits,
+    checksum: bool,
+    compression: Option<Compression>,
+    canonical: bool,
+}
+
+impl BinaryMessageCodec {
+    pub fn new() -> BinaryMessageCodec {
+        BinaryMessageCodec {
+            limits: DecodeLimits::new(),
+            checksum: false,
+            compression: None,
+            canonical: false,
+        }
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> BinaryMessageCodec {
+        BinaryMessageCodec {
+            limits,
+            checksum: false,
+            compression: None,
+            canonical: false,
+        }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Whether encoded messages are followed by a CRC32C of their bytes, which is verified
+    /// before decoding.  The checksum is found at the end of the buffer, so a checksummed
+    /// message has to be decoded from a buffer holding exactly that message and its checksum,
+    /// such as a frame.
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    pub fn with_checksum(mut self, checksum: bool) -> BinaryMessageCodec {
+        self.checksum = checksum;
+        self
+    }
+
+    /// How bodies, and optionally headers, are compressed when encoding.  Compressed messages
+    /// are always decoded, whatever this is set to.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> BinaryMessageCodec {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Whether messages are encoded in canonical form: map entries sorted by key, string keys
+    /// before integer keys, and every NaN and negative zero written as the positive quiet NaN
+    /// and positive zero.  Timestamps always have a single representation.  Canonical messages
+    /// are ordinary binary messages, so this does not affect decoding.
+    pub fn canonical(&self) -> bool {
+        self.canonical
+    }
+
+    pub fn with_canonical(mut self, canonical: bool) -> BinaryMessageCodec {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Encodes a message section with `encode`, compressing it if the compression settings
+    /// call for it.  Returns the uncompressed length alongside the compressed data.
+    fn compress_section<F>(&self, headers: bool, encode: F) -> Option<(usize, Vec<u8>)>
+        where F: FnOnce(&mut Vec<u8>)
+    {
+        let compression = self.compression?;
+        if headers && !compression.headers() {
+            return None;
+        }
+        let mut section = Vec::new();
+        encode(&mut section);
+        compression.compress(&section).map(|compressed| (section.len(), compressed))
+    }
+
+    fn encode_compressed<B: BufMut>(&self, section: &(usize, Vec<u8>), buffer: &mut B) {
+        let algorithm = self.compression.expect("Compression settings").algorithm();
+        buffer.put_u8(algorithm.to_u8());
+        buffer.put_i32_be(section.0 as i32);
+        buffer.put_i32_be(section.1.len() as i32);
+        buffer.put_slice(&section.1);
+    }
+
+    /// Decodes the fields of a message, without a checksum.
+    fn decode_fields<'a, B>(&self, buffer: &mut B) -> Result<Message<'a>, DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let mut message = Message::new();
+
+        let flags_offset = buffer.position();
+        let bits = self.decode_i32(buffer)?;
+        let flags = util::Flags::from_bits(bits)
+            .filter(|flags| flags.is_consistent())
+            .ok_or(DecodeError::InvalidFlags {
+                offset: flags_offset,
+                bits,
+            })?;
+
+        if flags.contains(util::Flags::HAS_TIMESTAMP) {
+            message.set_timestamp(Some(self.decode_timestamp(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_EXPIRATION) {
+            message.set_expiration(Some(self.decode_timestamp(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HAS_CORRELATION_ID) {
+            message.set_correlation_id(Some(self.decode_uuid(buffer)?));
+        }
+
+        if flags.contains(util::Flags::HEADERS_COMPRESSED) {
+            *message.headers_mut() =
+                self.decode_compressed(buffer, |section| self.decode_map(section, 1))?;
+        } else if flags.contains(util::Flags::HAS_HEADERS) {
+            self.limits.check(Limit::Depth, 1, buffer.position())?;
+            let count = self.decode_len(buffer, Limit::CollectionLength)?;
+            for _ in 0..count {
+                let key = self.decode_key(buffer)?;
+                let value = self.decode_value(buffer, 1)?;
+                message.headers_mut().insert(key, value);
+            }
+        }
+
+        if flags.contains(util::Flags::BODY_COMPRESSED) {
+            let body = self.decode_compressed(buffer, |section| self.decode_value(section, 0))?;
+            message.set_body(Some(body));
+        } else if flags.contains(util::Flags::HAS_BODY) {
+            message.set_body(Some(self.decode_value(buffer, 0)?));
+        }
+
+        // The buffer may hold more than this message, so the size is known once it is read.
+        self.limits.check(Limit::MessageSize, buffer.position() - flags_offset, flags_offset)?;
+
+        Ok(message)
+    }
+
+    /// Reads a compressed block and decodes the uncompressed section with `decode`, which has to
+    /// consume all of it.
+    pub(crate) fn decode_compressed<'a, B, F, T>(
+        &self,
+        buffer: &mut B,
+        decode: F,
+    ) -> Result<T, DecodeError>
+        where B: DecodeBuf<'a>,
+              F: FnOnce(&mut Cursor<Vec<u8>>) -> Result<T, DecodeError>
+    {
+        let offset = buffer.position();
+        buffer.require(1)?;
+        let id = buffer.get_u8();
+        let algorithm = Algorithm::from_u8(id).ok_or(DecodeError::UnknownCompression {
+            offset,
+            algorithm: id,
+        })?;
+        let len = self.decode_len(buffer, Limit::MessageSize)?;
+        let compressed_len = self.decode_len(buffer, Limit::MessageSize)?;
+        let data_offset = buffer.position();
+        let data = buffer.take_bytes(compressed_len)?;
+        let section = algorithm
+            .decompress(&data, len)
+            .ok_or(DecodeError::InvalidCompressedData { offset: data_offset })?;
+        let mut section = Cursor::new(section);
+        let value = decode(&mut section)?;
+        if section.has_remaining() {
+            return Err(DecodeError::InvalidCompressedData { offset: data_offset });
+        }
+        Ok(value)
+    }
+
+    /// Checks the checksum following the first `len` bytes of `buffer` against them.
+    fn verify_checksum<'a, B>(&self, buffer: &B, len: usize) -> Result<(), DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let bytes = buffer.bytes();
+        let (data, checksum) = bytes[..len + CHECKSUM_LEN].split_at(len);
+        let expected = Cursor::new(checksum).get_u32_be();
+        let actual = crc32c(data);
+        if expected != actual {
+            return Err(DecodeError::ChecksumMismatch {
+                offset: buffer.position() + data.len(),
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Codec for BinaryMessageCodec {
+    fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        if self.compression.is_some() {
+            let mut bytes = Vec::new();
+            self.encode_message(message, &mut bytes);
+            buffer.extend_from_slice(&bytes);
+            return;
+        }
+        buffer.reserve(self.encoded_len(message));
+        self.encode_message(message, buffer);
+    }
+
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut Cursor::new(buffer))
+    }
+
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut SliceBuf::new(buffer))
+    }
+
+    /// With compression enabled the message has to be encoded to be measured.
+    fn encoded_len(&self, message: &Message) -> usize {
+        if self.compression.is_some() {
+            let mut bytes = Vec::new();
+            self.encode_message(message, &mut bytes);
+            return bytes.len();
+        }
+        let size = calculate_message_size(message) as usize;
+        if self.checksum {
+            size + CHECKSUM_LEN
+        } else {
+            size
+        }
+    }
+}
+
+impl<'a, B> MessageDecoder<'a, B> for BinaryMessageCodec
+    where B: DecodeBuf<'a>
+{
+    const MIN_I64_LEN: usize = 8;
+
+    fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    fn decode_message(&self, buffer: &mut B) -> Result<Message<'a>, DecodeError> {
+        if self.checksum {
+            buffer.require(CHECKSUM_LEN)?;
+            let len = buffer.remaining() - CHECKSUM_LEN;
+            self.limits.check(Limit::MessageSize, len, buffer.position())?;
+            self.verify_checksum(buffer, len)?;
+            let message = {
+                let mut data = BoundedBuf::new(buffer, len);
+                let message = self.decode_fields(&mut data)?;
+                if data.has_remaining() {
+                    return Err(DecodeError::TrailingBytes {
+                        offset: data.position(),
+                        len: data.remaining(),
+                    });
+                }
+                message
+            };
+            buffer.advance(CHECKSUM_LEN);
+            return Ok(message);
+        }
+
+        self.decode_fields(buffer)
+    }
+
+    fn decode_len(&self, buffer: &mut B, limit: Limit) -> Result<usize, DecodeError> {
+        let offset = buffer.position();
+        let length = self.decode_i32(buffer)?;
+        if length < 0 {
+            return Err(DecodeError::NegativeLength { offset, length });
+        }
+        self.limits.check(limit, length as usize, offset)?;
+        Ok(length as usize)
+    }
+
+    fn decode_duration(&self, buffer: &mut B) -> Result<Duration, DecodeError> {
+        let offset = buffer.position();
+        buffer.require(12)?;
+        let seconds = buffer.get_u64_be();
+        let nanos = buffer.get_u32_be();
+        duration(offset, seconds, u64::from(nanos))
+    }
+
+    fn decode_i32(&self, buffer: &mut B) -> Result<i32, DecodeError> {
+        buffer.require(4)?;
+        Ok(buffer.get_i32_be())
+    }
+
+    fn decode_i64(&self, buffer: &mut B) -> Result<i64, DecodeError> {
+        buffer.require(8)?;
+        Ok(buffer.get_i64_be())
+    }
+}
+
+impl<'a, B> MessageEncoder<'a, B> for BinaryMessageCodec
+    where B: BufMut
+{
+    fn encode_message(&self, message: &Message<'a>, buffer: &mut B) {
+        if self.checksum {
+            let mut bytes = Vec::with_capacity(calculate_message_size(message) as usize);
+            let codec = BinaryMessageCodec {
+                checksum: false,
+                ..self.clone()
+            };
+            codec.encode_message(message, &mut bytes);
+            buffer.put_slice(&bytes);
+            buffer.put_u32_be(crc32c(&bytes));
+            return;
+        }
+
+        let headers = if !message.headers().is_empty() {
+            self.compress_section(true, |section| self.encode_map(message.headers(), section))
+        } else {
+            None
+        };
+        let body = match message.body() {
+            Some(body) => self.compress_section(false, |section| self.encode_value(body, section)),
+            None => None,
+        };
+
+        let mut flags = util::Flags::empty();
+
+        if let Some(_) = message.timestamp() {
+            flags.insert(util::Flags::HAS_TIMESTAMP);
+        }
+
+        if let Some(_) = message.expiration() {
+            flags.insert(util::Flags::HAS_EXPIRATION);
+        }
+
+        if let Some(_) = message.correlation_id() {
+            flags.insert(util::Flags::HAS_CORRELATION_ID);
+        }
+
+        if !message.headers().is_empty() {
+            flags.insert(util::Flags::HAS_HEADERS);
+        }
+
+        if let Some(_) = message.body() {
+            flags.insert(util::Flags::HAS_BODY);
+        }
+
+        if headers.is_some() {
+            flags.insert(util::Flags::HEADERS_COMPRESSED);
+        }
+
+        if body.is_some() {
+            flags.insert(util::Flags::BODY_COMPRESSED);
+        }
+
+        self.encode_i32(flags.bits(), buffer);
+
+        if let Some(timestamp) = message.timestamp() {
+            self.encode_timestamp(timestamp, buffer);
+        }
+
+        if let Some(expiration) = message.expiration() {
+            self.encode_timestamp(expiration, buffer);
+        }
+
+        if let Some(correlation_id) = message.correlation_id() {
+            self.encode_uuid(correlation_id, buffer);
+        }
+
+        if let Some(ref headers) = headers {
+            self.encode_compressed(headers, buffer);
+        } else if !message.headers().is_empty() {
+            self.encode_map(&message.headers(), buffer);
+        }
+
+        if let Some(ref body) = body {
+            self.encode_compressed(body, buffer);
+        } else if let Some(body) = message.body() {
+            self.encode_value(body, buffer);
+        }
+    }
+
+    fn encode_len(&self, len: usize, buffer: &mut B) {
+        self.encode_i32(len as i32, buffer);
+    }
+
+    fn encode_map(&self, map: &Map<'a>, buffer: &mut B) {
+        self.encode_len(map.len(), buffer);
+        if self.canonical {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| canonical_key_order(a.0, b.0));
+            for (key, value) in entries {
+                self.encode_key(key, buffer);
+                self.encode_value(value, buffer);
+            }
+            return;
+        }
+        for (key, value) in map.iter() {
+            self.encode_key(key, buffer);
+            self.encode_value(value, buffer);
+        }
+    }
+
+    fn encode_duration(&self, value: Duration, buffer: &mut B) {
+        buffer.put_u64_be(value.as_secs());
+        buffer.put_u32_be(value.subsec_nanos());
+    }
+
     fn encode_i32(&self, value: i32, buffer: &mut B) {
         buffer.put_i32_be(value);
     }
@@ -360,15 +802,42 @@ impl<'a, B> MessageEncoder<'a, B> for BinaryMessageCodec
     }
 
     fn encode_f32(&self, value: f32, buffer: &mut B) {
-        buffer.put_f32_be(value);
+        if self.canonical && value.is_nan() {
+            buffer.put_f32_be(f32::NAN);
+        } else if self.canonical && value == 0.0 {
+            buffer.put_f32_be(0.0);
+        } else {
+            buffer.put_f32_be(value);
+        }
     }
 
     fn encode_f64(&self, value: f64, buffer: &mut B) {
-        buffer.put_f64_be(value);
+        if self.canonical && value.is_nan() {
+            buffer.put_f64_be(f64::NAN);
+        } else if self.canonical && value == 0.0 {
+            buffer.put_f64_be(0.0);
+        } else {
+            buffer.put_f64_be(value);
+        }
     }
+}
 
-    fn encode_bool(&self, value: bool, buffer: &mut B) {
-        buffer.put_u8(if value { 1 } else { 0 })
+/// Builds a duration, failing with `DecodeError::InvalidDuration` unless `nanos` is less than a
+/// second.
+pub(crate) fn duration(offset: usize, seconds: u64, nanos: u64) -> Result<Duration, DecodeError> {
+    if nanos >= 1_000_000_000 {
+        return Err(DecodeError::InvalidDuration { offset, seconds, nanos });
+    }
+    Ok(Duration::new(seconds, nanos as u32))
+}
+
+/// Orders string keys by their bytes, before integer keys in numeric order.
+fn canonical_key_order(a: &Key, b: &Key) -> Ordering {
+    match (a, b) {
+        (Key::Str(a), Key::Str(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (Key::Str(_), Key::I32(_)) => Ordering::Less,
+        (Key::I32(_), Key::Str(_)) => Ordering::Greater,
+        (Key::I32(a), Key::I32(b)) => a.cmp(b),
     }
 }
 
@@ -403,10 +872,465 @@ mod tests {
             .build();
 
         let bytes_mut = encode_message(&message);
-        let output = decode_message(bytes_mut.freeze());
+        let output = decode_message(bytes_mut.freeze()).unwrap();
 
         assert_eq!(message, output);
         println!("{:#?}", message);
         println!("{:?}", message.headers().len());
     }
-}
\ No newline at end of file
+
+    fn example() -> Message<'static> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+            .with_header("key", "value")
+            .with_body("body")
+            .build()
+    }
+
+    #[test]
+    fn decode_truncated_message() {
+        let bytes = encode_message(&example());
+        for len in 0..bytes.len() {
+            match decode_message(&bytes[..len]) {
+                Err(DecodeError::Truncated { offset, .. }) => assert!(offset <= len),
+                other => panic!("Expected truncation at length {}, got {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_unknown_value_type() {
+        let mut bytes = encode_message(&example());
+        let tag_offset = bytes.len() - 9;
+        bytes[tag_offset] = 42;
+        assert_eq!(
+            decode_message(bytes.freeze()),
+            Err(DecodeError::UnknownValueType { offset: tag_offset, tag: 42 })
+        );
+    }
+
+    #[test]
+    fn decode_unknown_key_type() {
+        let mut bytes = encode_message(&example());
+        bytes[20] = 3;
+        assert_eq!(
+            decode_message(bytes.freeze()),
+            Err(DecodeError::UnknownKeyType { offset: 20, tag: 3 })
+        );
+    }
+
+    #[test]
+    fn decode_invalid_utf8() {
+        let mut bytes = encode_message(&example());
+        let last = bytes.len() - 1;
+        bytes[last] = 0xff;
+        assert_eq!(
+            decode_message(bytes.freeze()),
+            Err(DecodeError::InvalidUtf8 { offset: last - 3 })
+        );
+    }
+
+    #[test]
+    fn decode_invalid_flags() {
+        let mut bytes = encode_message(&example());
+        bytes[0] = 0x80;
+        match decode_message(bytes.freeze()) {
+            Err(DecodeError::InvalidFlags { offset: 0, .. }) => (),
+            other => panic!("Expected invalid flags, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn typed_values_layout() {
+        let body = |value: Value<'static>| {
+            let bytes = encode_message(&MessageBuilder::new().with_body(value).build());
+            bytes[4..].to_vec()
+        };
+        assert_eq!(
+            body(Value::from(Decimal::new(-12345, 2))),
+            [&[12u8][..], &(-1i64).to_be_bytes(), &(-12345i64).to_be_bytes(), &[2]].concat()
+        );
+        assert_eq!(
+            body(Value::from(Duration::new(90, 500))),
+            [&[13u8][..], &90u64.to_be_bytes(), &500u32.to_be_bytes()].concat()
+        );
+        assert_eq!(
+            body(Value::from(vec![1i64, -2])),
+            [&[14u8][..], &2i32.to_be_bytes(), &1i64.to_be_bytes(), &(-2i64).to_be_bytes()].concat()
+        );
+        assert_eq!(
+            body(Value::from(vec![0.5f64])),
+            [&[15u8][..], &1i32.to_be_bytes(), &0.5f64.to_bits().to_be_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn decode_invalid_duration() {
+        let mut bytes = BytesMut::with_capacity(17);
+        bytes.put_i32_be(util::Flags::HAS_BODY.bits());
+        bytes.put_u8(13);
+        bytes.put_u64_be(1);
+        bytes.put_u32_be(1_000_000_000);
+        assert_eq!(
+            decode_message(&bytes),
+            Err(DecodeError::InvalidDuration { offset: 5, seconds: 1, nanos: 1_000_000_000 })
+        );
+    }
+
+    #[test]
+    fn decode_array_count_beyond_buffer() {
+        let codec = BinaryMessageCodec::with_limits(DecodeLimits::unlimited());
+        for &tag in [14u8, 15].iter() {
+            let mut bytes = BytesMut::with_capacity(9);
+            bytes.put_i32_be(util::Flags::HAS_BODY.bits());
+            bytes.put_u8(tag);
+            bytes.put_i32_be(i32::MAX);
+            assert_eq!(
+                codec.decode(&bytes),
+                Err(DecodeError::Truncated { offset: 9, needed: 8, remaining: 0 })
+            );
+        }
+    }
+
+    #[test]
+    fn decode_borrowed_message() {
+        let message = || {
+            MessageBuilder::new()
+                .with_header("key", "value")
+                .with_header(7, MapBuilder::new().insert("nested", "map").build())
+                .with_body(Value::Bytes(Cow::Borrowed(&[1u8, 2, 3][..])))
+                .build()
+        };
+        let bytes = encode_message(&message()).freeze();
+
+        let output = decode_message_borrowed(&bytes).unwrap();
+        assert_eq!(message(), output);
+
+        let range = bytes.as_ptr() as usize..bytes.as_ptr() as usize + bytes.len();
+        for (key, value) in output.headers().iter() {
+            if let Key::Str(Cow::Owned(_)) = key {
+                panic!("Expected borrowed key, got {:?}", key);
+            }
+            if let Value::Str(ref value) = value {
+                match value {
+                    Cow::Borrowed(value) => assert!(range.contains(&(value.as_ptr() as usize))),
+                    Cow::Owned(_) => panic!("Expected borrowed value, got {:?}", value),
+                }
+            }
+        }
+        match output.body() {
+            Some(Value::Bytes(Cow::Borrowed(body))) => {
+                assert!(range.contains(&(body.as_ptr() as usize)))
+            }
+            other => panic!("Expected borrowed bytes, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_borrowed_truncated_message() {
+        let bytes = encode_message(&example());
+        let len = bytes.len() - 1;
+        assert_eq!(decode_message_borrowed(&bytes[..len]), decode_message(&bytes[..len]));
+    }
+
+    fn nested_lists(depth: usize) -> Message<'static> {
+        let mut value = Value::Null;
+        for _ in 0..depth {
+            value = ListBuilder::new().push(value).build().into();
+        }
+        MessageBuilder::new().with_body(value).build()
+    }
+
+    #[test]
+    fn decode_within_depth_limit() {
+        let message = nested_lists(64);
+        let bytes = encode_message(&message);
+        assert_eq!(decode_message(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn decode_exceeding_depth_limit() {
+        let mut bytes = BytesMut::with_capacity(4 + 100_000 * 5 + 1);
+        bytes.put_i32_be(util::Flags::HAS_BODY.bits());
+        for _ in 0..100_000 {
+            bytes.put_u8(8);
+            bytes.put_i32_be(1);
+        }
+        bytes.put_u8(0);
+        match decode_message(&bytes) {
+            Err(DecodeError::LimitExceeded { limit: Limit::Depth, actual: 65, max: 64, .. }) => (),
+            other => panic!("Expected depth limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_exceeding_collection_limit() {
+        let codec = BinaryMessageCodec::with_limits(DecodeLimits::new().with_max_collection_len(1));
+        let bytes = encode_message(&example());
+        assert!(MessageDecoder::decode_message(&codec, &mut Cursor::new(&bytes)).is_ok());
+
+        let message = MessageBuilder::new().with_header("a", 1).with_header("b", 2).build();
+        let bytes = encode_message(&message);
+        assert_eq!(
+            MessageDecoder::decode_message(&codec, &mut Cursor::new(&bytes)),
+            Err(DecodeError::LimitExceeded {
+                offset: 4,
+                limit: Limit::CollectionLength,
+                actual: 2,
+                max: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn decode_exceeding_bytes_limit() {
+        let codec = BinaryMessageCodec::with_limits(DecodeLimits::new().with_max_bytes_len(3));
+        let bytes = encode_message(&example());
+        match MessageDecoder::decode_message(&codec, &mut Cursor::new(&bytes)) {
+            Err(DecodeError::LimitExceeded { limit: Limit::BytesLength, actual: 5, max: 3, .. }) => (),
+            other => panic!("Expected bytes length limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_exceeding_message_size_limit() {
+        let codec = BinaryMessageCodec::with_limits(DecodeLimits::new().with_max_message_size(8));
+        let bytes = encode_message(&example());
+        match MessageDecoder::decode_message(&codec, &mut Cursor::new(&bytes)) {
+            Err(DecodeError::LimitExceeded { offset: 0, limit: Limit::MessageSize, max: 8, .. }) => (),
+            other => panic!("Expected message size limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn message_size_limit_counts_only_the_message() {
+        let message = example();
+        let mut bytes = encode_message(&message);
+        let len = bytes.len();
+        bytes.extend_from_slice(&encode_message(&message));
+        let limits = DecodeLimits::new().with_max_message_size(len);
+        let codec = BinaryMessageCodec::with_limits(limits);
+        let mut cursor = Cursor::new(&bytes);
+        assert_eq!(MessageDecoder::decode_message(&codec, &mut cursor), Ok(message.clone()));
+        assert_eq!(MessageDecoder::decode_message(&codec, &mut cursor), Ok(message.clone()));
+
+        let bytes = encode_message_with_checksum(&message);
+        let codec = BinaryMessageCodec::with_limits(limits).with_checksum(true);
+        assert_eq!(codec.decode(&bytes), Ok(message.clone()));
+        let limits = limits.with_max_message_size(len - 1);
+        let codec = BinaryMessageCodec::with_limits(limits).with_checksum(true);
+        match codec.decode(&bytes) {
+            Err(DecodeError::LimitExceeded { offset: 0, limit: Limit::MessageSize, .. }) => (),
+            other => panic!("Expected message size limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checksum_round_trip() {
+        let bytes = encode_message_with_checksum(&example());
+        let plain = encode_message(&example());
+        assert_eq!(&bytes[..plain.len()], &plain[..]);
+        assert_eq!(bytes.len(), plain.len() + CHECKSUM_LEN);
+        assert_eq!(decode_message_with_checksum(&bytes), Ok(example()));
+    }
+
+    #[test]
+    fn decode_corrupted_checksum() {
+        let mut bytes = encode_message_with_checksum(&example());
+        let checksum_offset = bytes.len() - CHECKSUM_LEN;
+        bytes[checksum_offset - 1] ^= 0x01;
+        match decode_message_with_checksum(&bytes) {
+            Err(DecodeError::ChecksumMismatch { offset, expected, actual }) => {
+                assert_eq!(offset, checksum_offset);
+                assert_ne!(expected, actual);
+            }
+            other => panic!("Expected checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_bytes_before_checksum() {
+        let mut bytes = encode_message(&example()).to_vec();
+        let junk_offset = bytes.len();
+        bytes.extend_from_slice(&[0xde, 0xad]);
+        let checksum = crc32c(&bytes);
+        bytes.put_u32_be(checksum);
+        assert_eq!(
+            decode_message_with_checksum(&bytes),
+            Err(DecodeError::TrailingBytes { offset: junk_offset, len: 2 })
+        );
+    }
+
+    #[test]
+    fn decode_missing_checksum() {
+        match decode_message_with_checksum(&[0u8, 0][..]) {
+            Err(DecodeError::Truncated { offset: 0, needed: 4, remaining: 2 }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn canonical_encoding() {
+        let message = MessageBuilder::new()
+            .with_header(2, 0.0f32)
+            .with_header("b", -0.0f64)
+            .with_header(-1, f32::NAN)
+            .with_header("a", -f64::NAN)
+            .build();
+        let bytes = encode_message_canonical(&message);
+        let mut expected = vec![0, 0, 0, 2, 0, 0, 0, 4];
+        expected.extend_from_slice(&[1, 0, 0, 0, 1, b'a', 5, 0x7f, 0xf8, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[1, 0, 0, 0, 1, b'b', 5, 0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[2, 0xff, 0xff, 0xff, 0xff, 4, 0x7f, 0xc0, 0, 0]);
+        expected.extend_from_slice(&[2, 0, 0, 0, 2, 4, 0, 0, 0, 0]);
+        assert_eq!(&bytes[..], &expected[..]);
+        assert_eq!(bytes.len(), calculate_message_size(&message) as usize);
+
+        let decoded = decode_message(&bytes).unwrap();
+        assert_eq!(encode_message_canonical(&decoded), bytes);
+    }
+
+    #[test]
+    fn codec_trait() {
+        fn round_trip<C: Codec>(codec: &C) {
+            let mut buffer = BytesMut::new();
+            codec.encode(&example(), &mut buffer);
+            assert_eq!(buffer.len(), codec.encoded_len(&example()));
+            assert_eq!(codec.decode(&buffer), Ok(example()));
+            assert_eq!(codec.decode_borrowed(&buffer), codec.decode(&buffer));
+        }
+        round_trip(&BinaryMessageCodec::new());
+        round_trip(&BinaryMessageCodec::new().with_checksum(true));
+    }
+
+    fn repetitive<'a>() -> Message<'a> {
+        let mut items = ListBuilder::new();
+        for index in 0..50 {
+            let item = MapBuilder::new().insert("id", index).insert("status", "pending").build();
+            items.push_mut(item);
+        }
+        MessageBuilder::new()
+            .with_header("key", "value")
+            .with_header("tags", "a,b,c,".repeat(100))
+            .with_body(items.build())
+            .build()
+    }
+
+    fn encode_with<C: Codec>(codec: &C, message: &Message) -> BytesMut {
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer);
+        buffer
+    }
+
+    fn flags_of(bytes: &[u8]) -> util::Flags {
+        util::Flags::from_bits(Cursor::new(&bytes[..4]).get_i32_be()).unwrap()
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let uncompressed = encode_message(&repetitive()).len();
+        for &algorithm in [Algorithm::Lz4, Algorithm::Deflate].iter() {
+            for &headers in [false, true].iter() {
+                let compression = Compression::new(algorithm).with_headers(headers);
+                let codec = BinaryMessageCodec::new().with_compression(compression);
+                let bytes = encode_with(&codec, &repetitive());
+                assert!(bytes.len() < uncompressed);
+                assert_eq!(bytes.len(), codec.encoded_len(&repetitive()));
+                let flags = flags_of(&bytes);
+                assert!(flags.contains(util::Flags::BODY_COMPRESSED));
+                assert_eq!(flags.contains(util::Flags::HEADERS_COMPRESSED), headers);
+                assert_eq!(decode_message(&bytes), Ok(repetitive()));
+            }
+        }
+    }
+
+    #[test]
+    fn compression_below_threshold() {
+        let codec = BinaryMessageCodec::new()
+            .with_compression(Compression::new(Algorithm::Lz4).with_headers(true));
+        let bytes = encode_with(&codec, &example());
+        assert_eq!(bytes, encode_message(&example()));
+    }
+
+    #[test]
+    fn compression_with_checksum() {
+        let codec = BinaryMessageCodec::new()
+            .with_checksum(true)
+            .with_compression(Compression::new(Algorithm::Lz4));
+        let bytes = encode_with(&codec, &repetitive());
+        assert!(flags_of(&bytes).contains(util::Flags::BODY_COMPRESSED));
+        assert_eq!(decode_message_with_checksum(&bytes), Ok(repetitive()));
+    }
+
+    #[test]
+    fn decode_invalid_compressed_data() {
+        let codec = BinaryMessageCodec::new().with_compression(Compression::new(Algorithm::Lz4));
+        let message = MessageBuilder::new().with_body(repetitive().body().unwrap().clone()).build();
+        let mut bytes = encode_with(&codec, &message);
+        // Claim one more uncompressed byte than the data holds.
+        bytes[8] += 1;
+        assert_eq!(decode_message(&bytes), Err(DecodeError::InvalidCompressedData { offset: 13 }));
+    }
+
+    #[test]
+    fn decode_unknown_compression() {
+        let mut bytes = BytesMut::with_capacity(5);
+        bytes.put_i32_be((util::Flags::HAS_BODY | util::Flags::BODY_COMPRESSED).bits());
+        bytes.put_u8(9);
+        assert_eq!(
+            decode_message(&bytes),
+            Err(DecodeError::UnknownCompression { offset: 4, algorithm: 9 })
+        );
+    }
+
+    #[test]
+    fn decode_compressed_flag_without_section() {
+        let mut bytes = encode_message(&example());
+        bytes[3] |= util::Flags::BODY_COMPRESSED.bits() as u8;
+        bytes[3] &= !(util::Flags::HAS_BODY.bits() as u8);
+        match decode_message(&bytes) {
+            Err(DecodeError::InvalidFlags { offset: 0, .. }) => (),
+            other => panic!("Expected invalid flags, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_compressed_section_with_trailing_bytes() {
+        let section = [0u8, 0];
+        let compressed = Algorithm::Lz4.compress(&section);
+        let mut bytes = BytesMut::with_capacity(13 + compressed.len());
+        bytes.put_i32_be((util::Flags::HAS_BODY | util::Flags::BODY_COMPRESSED).bits());
+        bytes.put_u8(Algorithm::Lz4.to_u8());
+        bytes.put_i32_be(section.len() as i32);
+        bytes.put_i32_be(compressed.len() as i32);
+        bytes.put_slice(&compressed);
+        assert_eq!(
+            BinaryMessageCodec::new().decode(&bytes),
+            Err(DecodeError::InvalidCompressedData { offset: 13 })
+        );
+    }
+
+    #[test]
+    fn decode_compression_bomb() {
+        let codec = BinaryMessageCodec::with_limits(DecodeLimits::new().with_max_message_size(64));
+        let mut bytes = BytesMut::with_capacity(13);
+        bytes.put_i32_be((util::Flags::HAS_BODY | util::Flags::BODY_COMPRESSED).bits());
+        bytes.put_u8(Algorithm::Deflate.to_u8());
+        bytes.put_i32_be(1 << 30);
+        bytes.put_i32_be(0);
+        match codec.decode(&bytes) {
+            Err(DecodeError::LimitExceeded { offset: 5, limit: Limit::MessageSize, .. }) => (),
+            other => panic!("Expected message size limit error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_negative_count() {
+        let mut bytes = encode_message(&MessageBuilder::new().with_body(List::new()).build());
+        bytes[5..9].copy_from_slice(&[0xff, 0xff, 0xff, 0xfe]);
+        assert_eq!(
+            decode_message(bytes.freeze()),
+            Err(DecodeError::NegativeLength { offset: 5, length: -2 })
+        );
+    }
+}
@@ -0,0 +1,833 @@
+//! MessagePack encoding of `Message`.
+//!
+//! A message is written as a MessagePack map with the string keys `timestamp`, `expiration`,
+//! `correlation_id`, `headers` and `body`.  Absent fields are omitted, as are empty headers.
+//!
+//! Values map onto MessagePack types as follows:
+//!
+//! * `Null`, `Bool`, `Str`, `F32`, `F64`, `List` and `Map` use the native types.
+//! * `I32` and `I64` are always written as `int 32` and `int 64` so the distinction survives a
+//!   round trip.  When decoding, any other integer format becomes an `I32` if it fits and an
+//!   `I64` otherwise.
+//! * `Bytes` uses the `bin` family.
+//! * `Uuid` is extension type `1` holding the 16 UUID bytes.
+//! * `Timestamp` is the standard timestamp extension type `-1`, in the smallest of its 32, 64
+//!   and 96 bit forms that can hold the value.
+//! * `Decimal` is extension type `2` holding the mantissa as a big endian 128 bit integer
+//!   followed by the scale byte.
+//! * `Duration` is extension type `3` holding big endian 64 bit seconds and 32 bit nanoseconds.
+//! * `I64Array` and `F64Array` are extension types `4` and `5` holding their elements as big
+//!   endian 64 bit integers and floats.
+//!
+//! `Key::Str` and `Key::I32` become string and integer map keys.
+
+use bytes::{BufMut, BytesMut};
+use chrono::{TimeZone, UTC};
+use codec::buf::{DecodeBuf, SliceBuf};
+use codec::Codec;
+use codec::error::DecodeError;
+use codec::limits::{DecodeLimits, Limit};
+use codec::message_codec::duration;
+use message::message::{Decimal, Key, List, Map, Message, Timestamp, Value};
+use rmp::encode;
+use rmp::Marker;
+use std::io::Cursor;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const UUID_EXT_TYPE: i8 = 1;
+pub const DECIMAL_EXT_TYPE: i8 = 2;
+pub const DURATION_EXT_TYPE: i8 = 3;
+pub const I64_ARRAY_EXT_TYPE: i8 = 4;
+pub const F64_ARRAY_EXT_TYPE: i8 = 5;
+pub const TIMESTAMP_EXT_TYPE: i8 = -1;
+
+const TIMESTAMP: &str = "timestamp";
+const EXPIRATION: &str = "expiration";
+const CORRELATION_ID: &str = "correlation_id";
+const HEADERS: &str = "headers";
+const BODY: &str = "body";
+
+pub fn encode_message(message: &Message) -> BytesMut {
+    let mut buffer = Vec::new();
+    let codec = MessagePackCodec::new();
+    codec.encode_message(message, &mut buffer);
+    BytesMut::from(buffer)
+}
+
+pub fn decode_message<'a, T: AsRef<[u8]>>(buffer: T) -> Result<Message<'a>, DecodeError> {
+    let codec = MessagePackCodec::new();
+    codec.decode_message(&mut Cursor::new(buffer))
+}
+
+/// Decodes a message whose strings, bytes and string keys borrow from `buffer` rather than
+/// being copied out of it.
+pub fn decode_message_borrowed<'a>(buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+    let codec = MessagePackCodec::new();
+    codec.decode_message(&mut SliceBuf::new(buffer))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MessagePackCodec {
+    limits: DecodeLimits,
+}
+
+// Writing into a `Vec` cannot fail, so encoding errors are not surfaced.
+const WRITE_ERROR: &str = "Error writing MessagePack to buffer";
+
+impl MessagePackCodec {
+    pub fn new() -> MessagePackCodec {
+        MessagePackCodec {
+            limits: DecodeLimits::new(),
+        }
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> MessagePackCodec {
+        MessagePackCodec { limits }
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    pub fn encode_message(&self, message: &Message, buffer: &mut Vec<u8>) {
+        let mut fields = 0;
+        fields += message.timestamp().is_some() as u32;
+        fields += message.expiration().is_some() as u32;
+        fields += message.correlation_id().is_some() as u32;
+        fields += (!message.headers().is_empty()) as u32;
+        fields += message.body().is_some() as u32;
+        encode::write_map_len(buffer, fields).expect(WRITE_ERROR);
+
+        if let Some(timestamp) = message.timestamp() {
+            encode::write_str(buffer, TIMESTAMP).expect(WRITE_ERROR);
+            self.encode_timestamp(timestamp, buffer);
+        }
+
+        if let Some(expiration) = message.expiration() {
+            encode::write_str(buffer, EXPIRATION).expect(WRITE_ERROR);
+            self.encode_timestamp(expiration, buffer);
+        }
+
+        if let Some(correlation_id) = message.correlation_id() {
+            encode::write_str(buffer, CORRELATION_ID).expect(WRITE_ERROR);
+            self.encode_uuid(correlation_id, buffer);
+        }
+
+        if !message.headers().is_empty() {
+            encode::write_str(buffer, HEADERS).expect(WRITE_ERROR);
+            self.encode_map(message.headers(), buffer);
+        }
+
+        if let Some(body) = message.body() {
+            encode::write_str(buffer, BODY).expect(WRITE_ERROR);
+            self.encode_value(body, buffer);
+        }
+    }
+
+    fn encode_key(&self, key: &Key, buffer: &mut Vec<u8>) {
+        match key {
+            Key::Str(ref key) => encode::write_str(buffer, key).expect(WRITE_ERROR),
+            Key::I32(key) => encode::write_i32(buffer, *key).expect(WRITE_ERROR),
+        }
+    }
+
+    fn encode_value(&self, value: &Value, buffer: &mut Vec<u8>) {
+        match value {
+            Value::Null => encode::write_nil(buffer).expect(WRITE_ERROR),
+            Value::Str(ref value) => encode::write_str(buffer, value).expect(WRITE_ERROR),
+            Value::I32(value) => encode::write_i32(buffer, *value).expect(WRITE_ERROR),
+            Value::I64(value) => encode::write_i64(buffer, *value).expect(WRITE_ERROR),
+            Value::F32(value) => encode::write_f32(buffer, *value).expect(WRITE_ERROR),
+            Value::F64(value) => encode::write_f64(buffer, *value).expect(WRITE_ERROR),
+            Value::Bool(value) => encode::write_bool(buffer, *value).expect(WRITE_ERROR),
+            Value::Bytes(ref value) => encode::write_bin(buffer, value).expect(WRITE_ERROR),
+            Value::List(ref value) => self.encode_list(value, buffer),
+            Value::Map(ref value) => self.encode_map(value, buffer),
+            Value::Uuid(value) => self.encode_uuid(*value, buffer),
+            Value::Timestamp(value) => self.encode_timestamp(*value, buffer),
+            Value::Decimal(value) => self.encode_decimal(*value, buffer),
+            Value::Duration(value) => self.encode_duration(*value, buffer),
+            Value::I64Array(ref values) => {
+                let len = (values.len() * 8) as u32;
+                encode::write_ext_meta(buffer, len, I64_ARRAY_EXT_TYPE).expect(WRITE_ERROR);
+                for value in values {
+                    buffer.put_i64_be(*value);
+                }
+            }
+            Value::F64Array(ref values) => {
+                let len = (values.len() * 8) as u32;
+                encode::write_ext_meta(buffer, len, F64_ARRAY_EXT_TYPE).expect(WRITE_ERROR);
+                for value in values {
+                    buffer.put_f64_be(*value);
+                }
+            }
+        }
+    }
+
+    fn encode_map(&self, map: &Map, buffer: &mut Vec<u8>) {
+        encode::write_map_len(buffer, map.len() as u32).expect(WRITE_ERROR);
+        for (key, value) in map.iter() {
+            self.encode_key(key, buffer);
+            self.encode_value(value, buffer);
+        }
+    }
+
+    fn encode_list(&self, list: &List, buffer: &mut Vec<u8>) {
+        encode::write_array_len(buffer, list.len() as u32).expect(WRITE_ERROR);
+        for value in list.iter() {
+            self.encode_value(value, buffer);
+        }
+    }
+
+    fn encode_uuid(&self, value: Uuid, buffer: &mut Vec<u8>) {
+        buffer.put_u8(0xd8);
+        buffer.put_i8(UUID_EXT_TYPE);
+        buffer.put_slice(value.as_bytes());
+    }
+
+    fn encode_decimal(&self, value: Decimal, buffer: &mut Vec<u8>) {
+        encode::write_ext_meta(buffer, 17, DECIMAL_EXT_TYPE).expect(WRITE_ERROR);
+        buffer.put_i64_be((value.mantissa() >> 64) as i64);
+        buffer.put_u64_be(value.mantissa() as u64);
+        buffer.put_u8(value.scale());
+    }
+
+    fn encode_duration(&self, value: Duration, buffer: &mut Vec<u8>) {
+        encode::write_ext_meta(buffer, 12, DURATION_EXT_TYPE).expect(WRITE_ERROR);
+        buffer.put_u64_be(value.as_secs());
+        buffer.put_u32_be(value.subsec_nanos());
+    }
+
+    fn encode_timestamp(&self, value: Timestamp, buffer: &mut Vec<u8>) {
+        let seconds = value.timestamp();
+        let nanos = value.timestamp_subsec_nanos();
+        if seconds >> 34 == 0 {
+            let data = (u64::from(nanos) << 34) | seconds as u64;
+            if data >> 32 == 0 {
+                buffer.put_u8(0xd6);
+                buffer.put_i8(TIMESTAMP_EXT_TYPE);
+                buffer.put_u32_be(data as u32);
+            } else {
+                buffer.put_u8(0xd7);
+                buffer.put_i8(TIMESTAMP_EXT_TYPE);
+                buffer.put_u64_be(data);
+            }
+        } else {
+            buffer.put_u8(0xc7);
+            buffer.put_u8(12);
+            buffer.put_i8(TIMESTAMP_EXT_TYPE);
+            buffer.put_u32_be(nanos);
+            buffer.put_i64_be(seconds);
+        }
+    }
+
+    pub fn decode_message<'a, B>(&self, buffer: &mut B) -> Result<Message<'a>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        let mut message = Message::new();
+        let offset = buffer.position();
+        let marker = self.decode_marker(buffer)?;
+        let fields = match self.decode_map_len(marker, buffer)? {
+            Some(fields) => fields,
+            None => return Err(unexpected(offset, marker, "message map")),
+        };
+
+        for _ in 0..fields {
+            let offset = buffer.position();
+            let marker = self.decode_marker(buffer)?;
+            let name = match self.decode_str_len(marker, buffer)? {
+                Some(len) => buffer.take_str(len)?,
+                None => return Err(unexpected(offset, marker, "message field name")),
+            };
+            let expected = match name.as_ref() {
+                TIMESTAMP | EXPIRATION => "timestamp",
+                CORRELATION_ID => "uuid",
+                HEADERS => "headers map",
+                BODY => "value",
+                _ => {
+                    return Err(DecodeError::UnknownField {
+                        offset,
+                        name: name.into_owned(),
+                    })
+                }
+            };
+
+            let offset = buffer.position();
+            buffer.require(1)?;
+            let marker = Marker::from_u8(buffer.bytes()[0]);
+            match (name.as_ref(), self.decode_value(buffer, 0)?) {
+                (TIMESTAMP, Value::Timestamp(value)) => message.set_timestamp(Some(value)),
+                (EXPIRATION, Value::Timestamp(value)) => message.set_expiration(Some(value)),
+                (CORRELATION_ID, Value::Uuid(value)) => message.set_correlation_id(Some(value)),
+                (HEADERS, Value::Map(value)) => *message.headers_mut() = value,
+                (BODY, value) => message.set_body(Some(value)),
+                _ => return Err(unexpected(offset, marker, expected)),
+            }
+        }
+
+        // The buffer may hold more than this message, so the size is known once it is read.
+        self.limits.check(Limit::MessageSize, buffer.position() - offset, offset)?;
+
+        Ok(message)
+    }
+
+    fn decode_marker<'a, B: DecodeBuf<'a>>(&self, buffer: &mut B) -> Result<Marker, DecodeError> {
+        buffer.require(1)?;
+        Ok(Marker::from_u8(buffer.get_u8()))
+    }
+
+    fn decode_key<'a, B: DecodeBuf<'a>>(&self, buffer: &mut B) -> Result<Key<'a>, DecodeError> {
+        let offset = buffer.position();
+        let marker = self.decode_marker(buffer)?;
+        if let Some(len) = self.decode_str_len(marker, buffer)? {
+            return Ok(Key::Str(buffer.take_str(len)?));
+        }
+        match self.decode_int(marker, buffer)? {
+            Some(Value::I32(key)) => Ok(Key::I32(key)),
+            Some(_) => Err(DecodeError::IntegerOverflow { offset }),
+            None => Err(DecodeError::UnknownKeyType {
+                offset,
+                tag: marker.to_u8(),
+            }),
+        }
+    }
+
+    fn decode_value<'a, B>(&self, buffer: &mut B, depth: usize) -> Result<Value<'a>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        let offset = buffer.position();
+        let marker = self.decode_marker(buffer)?;
+        if let Some(value) = self.decode_int(marker, buffer)? {
+            return Ok(value);
+        }
+        if let Some(len) = self.decode_str_len(marker, buffer)? {
+            return Ok(Value::Str(buffer.take_str(len)?));
+        }
+        if let Some(count) = self.decode_map_len(marker, buffer)? {
+            return Ok(Value::Map(self.decode_map(count, buffer, depth + 1)?));
+        }
+        let value = match marker {
+            Marker::Null => Value::Null,
+            Marker::True => Value::Bool(true),
+            Marker::False => Value::Bool(false),
+            Marker::F32 => {
+                buffer.require(4)?;
+                Value::F32(buffer.get_f32_be())
+            }
+            Marker::F64 => {
+                buffer.require(8)?;
+                Value::F64(buffer.get_f64_be())
+            }
+            Marker::Bin8 | Marker::Bin16 | Marker::Bin32 => {
+                let len = self.decode_len(marker, buffer, Limit::BytesLength)?;
+                Value::Bytes(buffer.take_bytes(len)?)
+            }
+            Marker::FixArray(_) | Marker::Array16 | Marker::Array32 => {
+                let count = self.decode_len(marker, buffer, Limit::CollectionLength)?;
+                Value::List(self.decode_list(count, buffer, depth + 1)?)
+            }
+            Marker::FixExt1
+            | Marker::FixExt2
+            | Marker::FixExt4
+            | Marker::FixExt8
+            | Marker::FixExt16
+            | Marker::Ext8
+            | Marker::Ext16
+            | Marker::Ext32 => self.decode_ext(offset, marker, buffer)?,
+            _ => {
+                return Err(DecodeError::UnknownValueType {
+                    offset,
+                    tag: marker.to_u8(),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn decode_map<'a, B>(
+        &self,
+        count: usize,
+        buffer: &mut B,
+        depth: usize,
+    ) -> Result<Map<'a>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        self.limits.check(Limit::Depth, depth, buffer.position())?;
+        let mut map = Map::new();
+        for _ in 0..count {
+            let key = self.decode_key(buffer)?;
+            let value = self.decode_value(buffer, depth)?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+
+    fn decode_list<'a, B>(
+        &self,
+        count: usize,
+        buffer: &mut B,
+        depth: usize,
+    ) -> Result<List<'a>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        self.limits.check(Limit::Depth, depth, buffer.position())?;
+        let mut list = List::new();
+        for _ in 0..count {
+            list.push(self.decode_value(buffer, depth)?);
+        }
+        Ok(list)
+    }
+
+    fn decode_ext<'a, B>(
+        &self,
+        offset: usize,
+        marker: Marker,
+        buffer: &mut B,
+    ) -> Result<Value<'a>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        let len = match marker {
+            Marker::FixExt1 => 1,
+            Marker::FixExt2 => 2,
+            Marker::FixExt4 => 4,
+            Marker::FixExt8 => 8,
+            Marker::FixExt16 => 16,
+            Marker::Ext8 => {
+                buffer.require(1)?;
+                buffer.get_u8() as usize
+            }
+            Marker::Ext16 => {
+                buffer.require(2)?;
+                buffer.get_u16_be() as usize
+            }
+            _ => {
+                buffer.require(4)?;
+                buffer.get_u32_be() as usize
+            }
+        };
+        buffer.require(1)?;
+        let ext_type = buffer.get_i8();
+        buffer.require(len)?;
+        match (ext_type, len) {
+            (UUID_EXT_TYPE, 16) => {
+                let bytes = buffer.take_bytes(16)?;
+                Uuid::from_bytes(&bytes)
+                    .map(Value::Uuid)
+                    .map_err(|_| DecodeError::InvalidUuid { offset })
+            }
+            (TIMESTAMP_EXT_TYPE, 4) => {
+                let seconds = i64::from(buffer.get_u32_be());
+                timestamp(offset, seconds, 0).map(Value::Timestamp)
+            }
+            (TIMESTAMP_EXT_TYPE, 8) => {
+                let data = buffer.get_u64_be();
+                let seconds = (data & 0x0000_0003_ffff_ffff) as i64;
+                timestamp(offset, seconds, (data >> 34) as u32).map(Value::Timestamp)
+            }
+            (TIMESTAMP_EXT_TYPE, 12) => {
+                let nanos = buffer.get_u32_be();
+                let seconds = buffer.get_i64_be();
+                timestamp(offset, seconds, nanos).map(Value::Timestamp)
+            }
+            (DECIMAL_EXT_TYPE, 17) => {
+                let high = buffer.get_i64_be();
+                let low = buffer.get_u64_be();
+                let scale = buffer.get_u8();
+                Ok(Value::Decimal(Decimal::new(i128::from(high) << 64 | i128::from(low), scale)))
+            }
+            (DURATION_EXT_TYPE, 12) => {
+                let seconds = buffer.get_u64_be();
+                let nanos = buffer.get_u32_be();
+                duration(offset, seconds, u64::from(nanos)).map(Value::Duration)
+            }
+            (I64_ARRAY_EXT_TYPE, len) if len % 8 == 0 => {
+                self.limits.check(Limit::CollectionLength, len / 8, offset)?;
+                Ok(Value::I64Array((0..len / 8).map(|_| buffer.get_i64_be()).collect()))
+            }
+            (F64_ARRAY_EXT_TYPE, len) if len % 8 == 0 => {
+                self.limits.check(Limit::CollectionLength, len / 8, offset)?;
+                Ok(Value::F64Array((0..len / 8).map(|_| buffer.get_f64_be()).collect()))
+            }
+            _ => Err(DecodeError::UnknownExtType { offset, ext_type }),
+        }
+    }
+
+    fn decode_int<'a, B>(
+        &self,
+        marker: Marker,
+        buffer: &mut B,
+    ) -> Result<Option<Value<'a>>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        let offset = buffer.position() - 1;
+        let value: i64 = match marker {
+            Marker::FixPos(value) => i64::from(value),
+            Marker::FixNeg(value) => i64::from(value),
+            Marker::U8 => {
+                buffer.require(1)?;
+                i64::from(buffer.get_u8())
+            }
+            Marker::U16 => {
+                buffer.require(2)?;
+                i64::from(buffer.get_u16_be())
+            }
+            Marker::U32 => {
+                buffer.require(4)?;
+                i64::from(buffer.get_u32_be())
+            }
+            Marker::U64 => {
+                buffer.require(8)?;
+                let value = buffer.get_u64_be();
+                if value > i64::MAX as u64 {
+                    return Err(DecodeError::IntegerOverflow { offset });
+                }
+                value as i64
+            }
+            Marker::I8 => {
+                buffer.require(1)?;
+                i64::from(buffer.get_i8())
+            }
+            Marker::I16 => {
+                buffer.require(2)?;
+                i64::from(buffer.get_i16_be())
+            }
+            Marker::I32 => {
+                buffer.require(4)?;
+                return Ok(Some(Value::I32(buffer.get_i32_be())));
+            }
+            Marker::I64 => {
+                buffer.require(8)?;
+                return Ok(Some(Value::I64(buffer.get_i64_be())));
+            }
+            _ => return Ok(None),
+        };
+        if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) {
+            Ok(Some(Value::I32(value as i32)))
+        } else {
+            Ok(Some(Value::I64(value)))
+        }
+    }
+
+    fn decode_str_len<'a, B>(
+        &self,
+        marker: Marker,
+        buffer: &mut B,
+    ) -> Result<Option<usize>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        match marker {
+            Marker::FixStr(_) | Marker::Str8 | Marker::Str16 | Marker::Str32 => {
+                self.decode_len(marker, buffer, Limit::BytesLength).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn decode_map_len<'a, B>(
+        &self,
+        marker: Marker,
+        buffer: &mut B,
+    ) -> Result<Option<usize>, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        match marker {
+            Marker::FixMap(_) | Marker::Map16 | Marker::Map32 => {
+                self.decode_len(marker, buffer, Limit::CollectionLength).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn decode_len<'a, B>(
+        &self,
+        marker: Marker,
+        buffer: &mut B,
+        limit: Limit,
+    ) -> Result<usize, DecodeError>
+    where
+        B: DecodeBuf<'a>,
+    {
+        let offset = buffer.position() - 1;
+        let len = match marker {
+            Marker::FixStr(len) | Marker::FixArray(len) | Marker::FixMap(len) => len as usize,
+            Marker::Str8 | Marker::Bin8 => {
+                buffer.require(1)?;
+                buffer.get_u8() as usize
+            }
+            Marker::Str16 | Marker::Bin16 | Marker::Array16 | Marker::Map16 => {
+                buffer.require(2)?;
+                buffer.get_u16_be() as usize
+            }
+            _ => {
+                buffer.require(4)?;
+                buffer.get_u32_be() as usize
+            }
+        };
+        self.limits.check(limit, len, offset)?;
+        Ok(len)
+    }
+}
+
+fn timestamp(offset: usize, seconds: i64, nanos: u32) -> Result<Timestamp, DecodeError> {
+    UTC.timestamp_opt(seconds, nanos)
+        .single()
+        .ok_or(DecodeError::InvalidTimestamp {
+            offset,
+            seconds,
+            nanos: nanos as i32,
+        })
+}
+
+impl Codec for MessagePackCodec {
+    fn encode(&self, message: &Message, buffer: &mut BytesMut) {
+        let mut bytes = Vec::new();
+        self.encode_message(message, &mut bytes);
+        buffer.extend_from_slice(&bytes);
+    }
+
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut Cursor::new(buffer))
+    }
+
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError> {
+        self.decode_message(&mut SliceBuf::new(buffer))
+    }
+
+    fn encoded_len(&self, message: &Message) -> usize {
+        message_len(message)
+    }
+}
+
+/// The number of bytes `MessagePackCodec` writes for `message`.
+pub fn message_len(message: &Message) -> usize {
+    let mut fields = 0;
+    let mut len = 0;
+    if let Some(timestamp) = message.timestamp() {
+        fields += 1;
+        len += str_len(TIMESTAMP.len()) + timestamp_len(timestamp);
+    }
+    if let Some(expiration) = message.expiration() {
+        fields += 1;
+        len += str_len(EXPIRATION.len()) + timestamp_len(expiration);
+    }
+    if message.correlation_id().is_some() {
+        fields += 1;
+        len += str_len(CORRELATION_ID.len()) + UUID_LEN;
+    }
+    if !message.headers().is_empty() {
+        fields += 1;
+        len += str_len(HEADERS.len()) + map_len(message.headers());
+    }
+    if let Some(body) = message.body() {
+        fields += 1;
+        len += str_len(BODY.len()) + value_len(body);
+    }
+    map_header_len(fields) + len
+}
+
+fn key_len(key: &Key) -> usize {
+    match key {
+        Key::Str(ref key) => str_len(key.len()),
+        Key::I32(_) => 5,
+    }
+}
+
+fn value_len(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Bool(_) => 1,
+        Value::Str(ref value) => str_len(value.len()),
+        Value::I32(_) | Value::F32(_) => 5,
+        Value::I64(_) | Value::F64(_) => 9,
+        Value::Bytes(ref value) => bin_len(value.len()),
+        Value::List(ref list) => {
+            array_header_len(list.len()) + list.iter().map(value_len).sum::<usize>()
+        }
+        Value::Map(ref map) => map_len(map),
+        Value::Uuid(_) => UUID_LEN,
+        Value::Timestamp(value) => timestamp_len(*value),
+        Value::Decimal(_) => ext_len(17),
+        Value::Duration(_) => ext_len(12),
+        Value::I64Array(ref values) => ext_len(values.len() * 8),
+        Value::F64Array(ref values) => ext_len(values.len() * 8),
+    }
+}
+
+fn map_len(map: &Map) -> usize {
+    let entries: usize = map.iter().map(|(key, value)| key_len(key) + value_len(value)).sum();
+    map_header_len(map.len()) + entries
+}
+
+/// Marker, extension type and the 16 UUID bytes.
+const UUID_LEN: usize = 18;
+
+fn str_len(len: usize) -> usize {
+    let header = match len {
+        0..=31 => 1,
+        32..=0xff => 2,
+        0x100..=0xffff => 3,
+        _ => 5,
+    };
+    header + len
+}
+
+fn bin_len(len: usize) -> usize {
+    let header = match len {
+        0..=0xff => 2,
+        0x100..=0xffff => 3,
+        _ => 5,
+    };
+    header + len
+}
+
+fn array_header_len(len: usize) -> usize {
+    match len {
+        0..=15 => 1,
+        16..=0xffff => 3,
+        _ => 5,
+    }
+}
+
+fn map_header_len(len: usize) -> usize {
+    array_header_len(len)
+}
+
+fn ext_len(len: usize) -> usize {
+    let header = match len {
+        1 | 2 | 4 | 8 | 16 => 2,
+        0..=0xff => 3,
+        0x100..=0xffff => 4,
+        _ => 6,
+    };
+    header + len
+}
+
+fn timestamp_len(value: Timestamp) -> usize {
+    let seconds = value.timestamp();
+    let nanos = value.timestamp_subsec_nanos();
+    if seconds >> 34 != 0 {
+        15
+    } else if nanos == 0 && seconds >> 32 == 0 {
+        6
+    } else {
+        10
+    }
+}
+
+fn unexpected(offset: usize, marker: Marker, expected: &'static str) -> DecodeError {
+    DecodeError::UnexpectedType {
+        offset,
+        tag: marker.to_u8(),
+        expected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use message::message::{ListBuilder, MapBuilder, MessageBuilder};
+    use std::borrow::Cow;
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 123_456_789))
+            .with_expiration(UTC.timestamp(1_500_000_060, 0))
+            .with_correlation_id(Uuid::parse_str("c6a1ff35-7a3b-4b0c-a4f3-8bba0f5b4b1e").unwrap())
+            .with_header("null", Value::Null)
+            .with_header("str", "string")
+            .with_header("i32", 32)
+            .with_header("i64", 64i64)
+            .with_header("f32", 32.5f32)
+            .with_header("f64", 64.5f64)
+            .with_header("bool", true)
+            .with_header("bytes", Value::Bytes(Cow::Owned(vec![1, 2, 3])))
+            .with_header("uuid", Value::Uuid(Uuid::nil()))
+            .with_header(42, ListBuilder::new().push("one").push(2).build())
+            .with_body(
+                MapBuilder::new()
+                    .insert("firstName", "jimmie")
+                    .insert(-7, UTC.timestamp(-1, 500))
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let bytes = encode_message(&example());
+        assert_eq!(decode_message(&bytes), Ok(example()));
+        assert_eq!(decode_message_borrowed(&bytes), Ok(example()));
+    }
+
+    #[test]
+    fn encode_simple_message() {
+        let message = MessageBuilder::new().with_body("hi").build();
+        let bytes = encode_message(&message);
+        assert_eq!(&bytes[..], b"\x81\xa4body\xa2hi");
+    }
+
+    #[test]
+    fn decode_compact_integers() {
+        let bytes = b"\x81\xa7headers\x84\x01\x05\xa1a\xff\xa1b\xcd\x01\x00\xa1c\xce\xff\xff\xff\xff";
+        let message = decode_message(&bytes[..]).unwrap();
+        let headers = message.headers();
+        assert_eq!(headers.get(&Key::from(1)), Some(&Value::I32(5)));
+        assert_eq!(headers.get(&Key::from("a")), Some(&Value::I32(-1)));
+        assert_eq!(headers.get(&Key::from("b")), Some(&Value::I32(256)));
+        assert_eq!(headers.get(&Key::from("c")), Some(&Value::I64(4_294_967_295)));
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let cases = vec![
+            (UTC.timestamp(1_500_000_000, 0), 6),
+            (UTC.timestamp(1_500_000_000, 1), 10),
+            (UTC.timestamp(1 << 34, 0), 15),
+            (UTC.timestamp(-1_500_000_000, 999_999_999), 15),
+        ];
+        for (timestamp, len) in cases {
+            let mut buffer = Vec::new();
+            MessagePackCodec::new().encode_timestamp(timestamp, &mut buffer);
+            assert_eq!(buffer.len(), len, "Unexpected length for {:?}", timestamp);
+            let message = MessageBuilder::new().with_timestamp(timestamp).build();
+            assert_eq!(decode_message(encode_message(&message)), Ok(message));
+        }
+    }
+
+    #[test]
+    fn decode_unknown_field() {
+        let bytes = b"\x81\xa5extra\xc0";
+        assert_eq!(
+            decode_message(&bytes[..]),
+            Err(DecodeError::UnknownField { offset: 1, name: "extra".to_owned() })
+        );
+    }
+
+    #[test]
+    fn decode_mistyped_field() {
+        let bytes = b"\x81\xa9timestamp\x01";
+        assert_eq!(
+            decode_message(&bytes[..]),
+            Err(DecodeError::UnexpectedType { offset: 11, tag: 1, expected: "timestamp" })
+        );
+    }
+
+    #[test]
+    fn decode_truncated_message() {
+        let bytes = encode_message(&example());
+        for len in 0..bytes.len() {
+            match decode_message(&bytes[..len]) {
+                Err(DecodeError::Truncated { .. }) => (),
+                other => panic!("Expected truncation at length {}, got {:?}", len, other),
+            }
+        }
+    }
+}
@@ -1,3 +1,291 @@
+pub mod buf;
+pub mod error;
+pub mod limits;
 pub mod util;
+pub mod varint;
+pub mod crc32c;
+pub mod compression;
 pub mod size_calculator;
 pub mod message_codec;
+pub mod compact;
+pub mod batch;
+pub mod view;
+pub mod frame;
+pub mod message_pack;
+pub mod json;
+pub mod envelope;
+pub mod encryption;
+
+use bytes::BytesMut;
+use codec::error::DecodeError;
+use message::message::Message;
+
+/// A wire format for messages.
+///
+/// Implemented by `BinaryMessageCodec`, `CompactMessageCodec`, `MessagePackCodec` and
+/// `EnvelopeCodec`, and by `EncryptedCodec` around any of them.  Code that stores or transmits
+/// messages should take a `Codec` rather than calling a particular format's free functions, so
+/// the format can be chosen or wrapped by the caller.
+///
+/// The JSON representation in `json` is not a `Codec`.  It is text meant to be read and
+/// written by people, and reports a `JsonError` rather than a `DecodeError`.
+pub trait Codec {
+    /// Appends the encoding of `message` to `buffer`, growing it as needed.
+    fn encode(&self, message: &Message, buffer: &mut BytesMut);
+
+    /// Decodes a message, copying strings and bytes out of `buffer`.
+    fn decode<'a>(&self, buffer: &[u8]) -> Result<Message<'a>, DecodeError>;
+
+    /// Decodes a message whose strings, bytes and string keys borrow from `buffer`.
+    fn decode_borrowed<'a>(&self, buffer: &'a [u8]) -> Result<Message<'a>, DecodeError>;
+
+    /// The exact number of bytes `encode` appends for `message`.
+    fn encoded_len(&self, message: &Message) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use codec::compact::CompactMessageCodec;
+    use codec::compression::{Algorithm, Compression};
+    use codec::encryption::{EncryptedCodec, Encryption, EncryptionKey, KEY_LEN};
+    use codec::envelope::{CodecId, EnvelopeCodec};
+    use codec::message_codec::BinaryMessageCodec;
+    use codec::message_pack::MessagePackCodec;
+    use message::message::{Decimal, Key, List, Map, Timestamp, Value};
+    use quickcheck::{quickcheck, Arbitrary, Gen};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const MAX_DEPTH: usize = 3;
+
+    impl Arbitrary for Message<'static> {
+        fn arbitrary<G: Gen>(g: &mut G) -> Message<'static> {
+            let mut message = Message::new();
+            if g.gen() {
+                message.set_timestamp(Some(arbitrary_timestamp(g)));
+            }
+            if g.gen() {
+                message.set_expiration(Some(arbitrary_timestamp(g)));
+            }
+            if g.gen() {
+                message.set_correlation_id(Some(arbitrary_uuid(g)));
+            }
+            *message.headers_mut() = arbitrary_map(g, 1);
+            if g.gen() {
+                message.set_body(Some(arbitrary_value(g, 0)));
+            }
+            message
+        }
+    }
+
+    impl Arbitrary for Decimal {
+        /// The product of two `i64`s never reaches the ends of the `i128` range, so those are
+        /// picked directly some of the time.
+        fn arbitrary<G: Gen>(g: &mut G) -> Decimal {
+            let mantissa = if g.gen_range(0, 4) == 0 {
+                *g.choose(&[i128::MIN, i128::MAX, 0]).unwrap()
+            } else {
+                i128::from(g.gen::<i64>()) * i128::from(g.gen::<i64>())
+            };
+            Decimal::new(mantissa, g.gen())
+        }
+    }
+
+    fn arbitrary_key<G: Gen>(g: &mut G) -> Key<'static> {
+        if g.gen() {
+            Key::from(String::arbitrary(g))
+        } else {
+            Key::from(g.gen::<i32>())
+        }
+    }
+
+    fn arbitrary_value<G: Gen>(g: &mut G, depth: usize) -> Value<'static> {
+        let kinds = if depth < MAX_DEPTH { 18 } else { 16 };
+        match g.gen_range(0, kinds) {
+            0 => Value::Null,
+            1 => Value::from(String::arbitrary(g)),
+            // Long enough to need the wider string and bytes length prefixes.
+            2 => Value::from("x".repeat(g.gen_range(0, 70_000))),
+            3 => Value::from(g.gen::<i32>()),
+            4 => Value::from(g.gen::<i64>()),
+            5 => Value::from(f32::arbitrary(g)),
+            6 => Value::from(f64::arbitrary(g)),
+            7 => Value::from(g.gen::<bool>()),
+            8 => Value::Bytes(Vec::<u8>::arbitrary(g).into()),
+            9 => Value::Bytes(vec![0u8; g.gen_range(0, 70_000)].into()),
+            10 => Value::Uuid(arbitrary_uuid(g)),
+            11 => Value::from(arbitrary_timestamp(g)),
+            12 => Value::from(Decimal::arbitrary(g)),
+            13 => Value::from(Duration::new(g.gen(), g.gen_range(0, 1_000_000_000))),
+            14 => Value::from(Vec::<i64>::arbitrary(g)),
+            15 => Value::from(Vec::<f64>::arbitrary(g)),
+            16 => Value::from(arbitrary_list(g, depth + 1)),
+            _ => Value::from(arbitrary_map(g, depth + 1)),
+        }
+    }
+
+    fn arbitrary_list<G: Gen>(g: &mut G, depth: usize) -> List<'static> {
+        let mut list = List::new();
+        for _ in 0..g.gen_range(0, 20) {
+            list.push(arbitrary_value(g, depth));
+        }
+        list
+    }
+
+    fn arbitrary_map<G: Gen>(g: &mut G, depth: usize) -> Map<'static> {
+        let mut map = Map::new();
+        for _ in 0..g.gen_range(0, 20) {
+            map.insert(arbitrary_key(g), arbitrary_value(g, depth));
+        }
+        map
+    }
+
+    fn arbitrary_uuid<G: Gen>(g: &mut G) -> Uuid {
+        Uuid::from_bytes(&g.gen::<[u8; 16]>()).unwrap()
+    }
+
+    /// Covers whole seconds and negative and far future times, which need the different
+    /// timestamp forms of MessagePack.
+    fn arbitrary_timestamp<G: Gen>(g: &mut G) -> Timestamp {
+        let seconds = g.gen_range(-1 << 40, 1 << 40);
+        let nanos = if g.gen() { 0 } else { g.gen_range(0, 1_000_000_000) };
+        UTC.timestamp(seconds, nanos)
+    }
+
+    /// Checks that `encoded_len` is exact and that the message survives a round trip, copied
+    /// and borrowed, and that the borrowed message encodes to the same bytes.
+    fn round_trips<C: Codec>(codec: &C, message: Message<'static>) -> bool {
+        let mut buffer = BytesMut::new();
+        codec.encode(&message, &mut buffer);
+        if buffer.len() != codec.encoded_len(&message) {
+            return false;
+        }
+        if codec.decode(&buffer) != Ok(message) {
+            return false;
+        }
+        let borrowed = match codec.decode_borrowed(&buffer) {
+            Ok(borrowed) => borrowed,
+            Err(_) => return false,
+        };
+        let mut reencoded = BytesMut::new();
+        codec.encode(&borrowed, &mut reencoded);
+        reencoded == buffer
+    }
+
+    /// Like `round_trips`, for an `EncryptedCodec`, whose encoding of a message changes with
+    /// every nonce.
+    fn round_trips_encrypted<C: Codec>(codec: &C, message: Message<'static>) -> bool {
+        let mut buffer = BytesMut::new();
+        codec.encode(&message, &mut buffer);
+        if buffer.len() != codec.encoded_len(&message) {
+            return false;
+        }
+        if codec.decode(&buffer).as_ref() != Ok(&message) {
+            return false;
+        }
+        let borrowed = codec.decode_borrowed(&buffer).map(Message::into_owned);
+        borrowed == Ok(message)
+    }
+
+    fn encryption() -> Encryption {
+        Encryption::new(EncryptionKey::new("k1", [1u8; KEY_LEN]))
+            .with_header("secret")
+            .with_header(7)
+    }
+
+    #[test]
+    fn decimal_string_round_trip() {
+        fn property(decimal: Decimal) -> bool {
+            decimal.to_string().parse() == Ok(decimal)
+        }
+        quickcheck(property as fn(Decimal) -> bool);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            round_trips(&BinaryMessageCodec::new(), message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn binary_with_checksum_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            round_trips(&BinaryMessageCodec::new().with_checksum(true), message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn binary_with_compression_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            let compression = Compression::new(Algorithm::Lz4)
+                .with_threshold(0)
+                .with_headers(true);
+            round_trips(&BinaryMessageCodec::new().with_compression(compression), message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn binary_with_checksum_and_compression_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            let compression = Compression::new(Algorithm::Deflate)
+                .with_threshold(0)
+                .with_headers(true);
+            let codec = BinaryMessageCodec::new()
+                .with_checksum(true)
+                .with_compression(compression);
+            round_trips(&codec, message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            round_trips(&CompactMessageCodec::new(), message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn message_pack_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            round_trips(&MessagePackCodec::new(), message)
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    #[test]
+    fn envelope_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            [CodecId::Binary, CodecId::Compact, CodecId::MessagePack]
+                .iter()
+                .all(|&codec| round_trips(&EnvelopeCodec::new(codec), message.clone()))
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+
+    /// Covers the inner codecs whose `encoded_len` `EncryptedCodec` can compute exactly, which
+    /// excludes a `BinaryMessageCodec` with compression.
+    #[test]
+    fn encrypted_round_trip() {
+        fn property(message: Message<'static>) -> bool {
+            let binary = BinaryMessageCodec::new().with_checksum(true);
+            round_trips_encrypted(&EncryptedCodec::new(binary, encryption()), message.clone())
+                && round_trips_encrypted(
+                    &EncryptedCodec::new(CompactMessageCodec::new(), encryption()),
+                    message.clone(),
+                )
+                && round_trips_encrypted(
+                    &EncryptedCodec::new(MessagePackCodec::new(), encryption()),
+                    message,
+                )
+        }
+        quickcheck(property as fn(Message<'static>) -> bool);
+    }
+}
@@ -4,26 +4,37 @@ use message::message::Value;
 use message::message::Map;
 use message::message::List;
 use message::message::Timestamp;
+use message::message::Decimal;
+use codec::varint::{varint_len, zigzag_encode_i32, zigzag_encode_i64};
 use std::str;
+use std::time::Duration;
 use uuid::Uuid;
 use chrono::prelude::*;
 
 pub fn calculate_message_size(message: &Message) -> i32 {
-    let calculator = SizeCalculator;
+    let calculator = SizeCalculator::new();
+    let mut size = 0;
+    calculator.visit_message(message, &mut size);
+    size
+}
+
+/// Size of `message` in the compact binary format.
+pub fn calculate_compact_message_size(message: &Message) -> i32 {
+    let calculator = SizeCalculator::with_mode(SizeMode::Compact);
     let mut size = 0;
     calculator.visit_message(message, &mut size);
     size
 }
 
 pub fn calculate_key_size(key: &Key) -> i32 {
-    let calculator = SizeCalculator;
+    let calculator = SizeCalculator::new();
     let mut size = 0;
     calculator.visit_key(key, &mut size);
     size
 }
 
 pub fn calculate_value_size(value: &Value) -> i32 {
-    let calculator = SizeCalculator;
+    let calculator = SizeCalculator::new();
     let mut size = 0;
     calculator.visit_value(value, &mut size);
     size
@@ -60,17 +71,64 @@ pub trait MessageVisitor<'a> {
 
     fn visit_timestamp(&self, value: Timestamp, buffer: &'a mut Self::Output);
 
+    fn visit_decimal(&self, value: Decimal, buffer: &'a mut Self::Output);
+
+    fn visit_duration(&self, value: Duration, buffer: &'a mut Self::Output);
+
     fn visit_null(&self, buffer: &'a mut Self::Output);
 }
 
-pub struct SizeCalculator;
+/// The binary format whose encoded size a `SizeCalculator` computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeMode {
+    /// Fixed width lengths, counts, flags and integers, as written by `BinaryMessageCodec`.
+    Binary,
+    /// Varint lengths, counts and flags and zigzag varint integers, as written by
+    /// `CompactMessageCodec`.
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SizeCalculator {
+    mode: SizeMode,
+}
+
+impl SizeCalculator {
+    pub fn new() -> SizeCalculator {
+        SizeCalculator::with_mode(SizeMode::Binary)
+    }
+
+    pub fn with_mode(mode: SizeMode) -> SizeCalculator {
+        SizeCalculator { mode }
+    }
+
+    pub fn mode(&self) -> SizeMode {
+        self.mode
+    }
+
+    fn length_size(&self, length: usize) -> i32 {
+        match self.mode {
+            SizeMode::Binary => 4,
+            SizeMode::Compact => varint_len(length as u64) as i32,
+        }
+    }
+}
+
+impl Default for SizeCalculator {
+    fn default() -> Self {
+        SizeCalculator::new()
+    }
+}
 
 impl<'a> MessageVisitor<'a> for SizeCalculator {
     type Output = i32;
 
     fn visit_message(&self, message: &'a Message, buffer: &'a mut Self::Output) {
-        // flags
-        *buffer += 4;
+        // flags, at most 5 bits so a single byte in compact mode
+        *buffer += match self.mode {
+            SizeMode::Binary => 4,
+            SizeMode::Compact => 1,
+        };
 
         if let Some(_) = message.timestamp() {
             *buffer += 12;
@@ -84,7 +142,7 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
             *buffer += 16;
         }
 
-        if message.headers().len() > 0 {
+        if !message.headers().is_empty() {
             self.visit_map(&message.headers(), buffer);
         }
 
@@ -94,7 +152,7 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
     }
 
     fn visit_map(&self, value: &'a Map, buffer: &'a mut Self::Output) {
-        *buffer += 4;
+        *buffer += self.length_size(value.len());
         for (key, value) in value.iter() {
             self.visit_key(key, buffer);
             self.visit_value(value, buffer);
@@ -102,7 +160,7 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
     }
 
     fn visit_list(&self, list: &'a List, buffer: &'a mut Self::Output) {
-        *buffer += 4;
+        *buffer += self.length_size(list.len());
         for value in list.iter() {
             self.visit_value(value, buffer);
         }
@@ -131,19 +189,39 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
             Value::List(ref value) => self.visit_list(value, buffer),
             Value::Uuid(value) => self.visit_uuid(*value, buffer),
             Value::Timestamp(value) => self.visit_timestamp(*value, buffer),
+            Value::Decimal(value) => self.visit_decimal(*value, buffer),
+            Value::Duration(value) => self.visit_duration(*value, buffer),
+            Value::I64Array(ref values) => {
+                *buffer += self.length_size(values.len());
+                for value in values {
+                    self.visit_i64(*value, buffer);
+                }
+            }
+            Value::F64Array(ref values) => {
+                *buffer += self.length_size(values.len());
+                for value in values {
+                    self.visit_f64(*value, buffer);
+                }
+            }
         }
     }
 
     fn visit_bytes(&self, value: &'a [u8], buffer: &'a mut Self::Output) {
-        *buffer += 4 + (value.len() as i32);
+        *buffer += self.length_size(value.len()) + (value.len() as i32);
     }
 
-    fn visit_i32(&self, _value: i32, buffer: &'a mut Self::Output) {
-        *buffer += 4;
+    fn visit_i32(&self, value: i32, buffer: &'a mut Self::Output) {
+        *buffer += match self.mode {
+            SizeMode::Binary => 4,
+            SizeMode::Compact => varint_len(u64::from(zigzag_encode_i32(value))) as i32,
+        };
     }
 
-    fn visit_i64(&self, _value: i64, buffer: &'a mut Self::Output) {
-        *buffer += 8;
+    fn visit_i64(&self, value: i64, buffer: &'a mut Self::Output) {
+        *buffer += match self.mode {
+            SizeMode::Binary => 8,
+            SizeMode::Compact => varint_len(zigzag_encode_i64(value)) as i32,
+        };
     }
 
     fn visit_f32(&self, _value: f32, buffer: &'a mut Self::Output) {
@@ -159,7 +237,7 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
     }
 
     fn visit_str(&self, value: &'a str, buffer: &'a mut Self::Output) {
-        *buffer += 4 + (value.len() as i32);
+        *buffer += self.length_size(value.len()) + (value.len() as i32);
     }
 
     fn visit_uuid(&self, _value: Uuid, buffer: &'a mut Self::Output) {
@@ -170,6 +248,20 @@ impl<'a> MessageVisitor<'a> for SizeCalculator {
         *buffer += 12;
     }
 
+    fn visit_decimal(&self, _value: Decimal, buffer: &'a mut Self::Output) {
+        *buffer += 17;
+    }
+
+    fn visit_duration(&self, value: Duration, buffer: &'a mut Self::Output) {
+        *buffer += match self.mode {
+            SizeMode::Binary => 12,
+            SizeMode::Compact => {
+                let seconds = varint_len(value.as_secs());
+                (seconds + varint_len(u64::from(value.subsec_nanos()))) as i32
+            }
+        };
+    }
+
     fn visit_null(&self, _buffer: &'a mut Self::Output) {
         ()
     }
@@ -213,6 +305,21 @@ mod tests {
         assert_eq!(calculate_value_size(&Value::from(true)), 2);
     }
 
+    #[test]
+    fn calculate_compact_sizes() {
+        let mut message = Message::new();
+        assert_eq!(calculate_compact_message_size(&message), 1);
+        message.headers_mut().insert("key1", -1);
+        message.headers_mut().insert(300, 1i64 << 40);
+        message.set_body(Some("hello"));
+        assert_eq!(calculate_compact_message_size(&message), 1 + 1 + 6 + 2 + 3 + 7 + 7);
+
+        let calculator = SizeCalculator::with_mode(SizeMode::Compact);
+        let mut size = 0;
+        calculator.visit_value(&Value::from("x".repeat(128)), &mut size);
+        assert_eq!(size, 1 + 2 + 128);
+    }
+
     #[test]
     fn calculate_key_sizes() {
         assert_eq!(calculate_key_size(&Key::from("string")), 11);
@@ -5,6 +5,16 @@ bitflags! {
         const HAS_BODY           = 0b00000000000000000000000000000100;
         const HAS_EXPIRATION     = 0b00000000000000000000000000001000;
         const HAS_CORRELATION_ID = 0b00000000000000000000000000010000;
+        const HEADERS_COMPRESSED = 0b00000000000000000000000000100000;
+        const BODY_COMPRESSED    = 0b00000000000000000000000001000000;
+    }
+}
+
+impl Flags {
+    /// Whether every compressed section the flags name is also present.
+    pub fn is_consistent(&self) -> bool {
+        (!self.contains(Flags::HEADERS_COMPRESSED) || self.contains(Flags::HAS_HEADERS))
+            && (!self.contains(Flags::BODY_COMPRESSED) || self.contains(Flags::HAS_BODY))
     }
 }
 
@@ -44,6 +54,14 @@ mod test {
         assert_eq!(4, Flags::HAS_BODY.bits());
     }
 
+    #[test]
+    fn compressed_flags_require_section() {
+        assert!(Flags::empty().is_consistent());
+        assert!((Flags::HAS_BODY | Flags::BODY_COMPRESSED).is_consistent());
+        assert!(!Flags::BODY_COMPRESSED.is_consistent());
+        assert!(!(Flags::HAS_BODY | Flags::HEADERS_COMPRESSED).is_consistent());
+    }
+
     #[test]
     fn int_to_flags() {
         assert_eq!(Flags::from_bits(4).unwrap(), Flags::HAS_BODY);
@@ -51,6 +69,6 @@ mod test {
             Flags::from_bits(6).unwrap(),
             Flags::HAS_BODY | Flags::HAS_HEADERS
         );
-        assert_eq!(Flags::from_bits(31).unwrap(), Flags::all());
+        assert_eq!(Flags::from_bits(127).unwrap(), Flags::all());
     }
 }
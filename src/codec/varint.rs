@@ -0,0 +1,131 @@
+//! Unsigned LEB128 varints and zigzag encoding, as used by the compact binary format.
+//!
+//! A varint stores seven bits per byte, least significant group first, with the high bit of
+//! each byte set when more bytes follow.  Zigzag encoding maps signed integers onto unsigned
+//! ones so that values close to zero, negative or positive, get short varints.
+
+use bytes::BufMut;
+use codec::buf::DecodeBuf;
+use codec::error::DecodeError;
+
+/// The longest encoding of a `u64` varint.
+pub const MAX_VARINT_LEN: usize = 10;
+
+pub fn varint_len(value: u64) -> usize {
+    let bits = 64 - (value | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+pub fn encode_varint<B: BufMut>(mut value: u64, buffer: &mut B) {
+    while value >= 0x80 {
+        buffer.put_u8((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.put_u8(value as u8);
+}
+
+/// Decodes a varint, rejecting encodings longer than `MAX_VARINT_LEN` bytes or holding more
+/// than 64 bits.
+pub fn decode_varint<'a, B: DecodeBuf<'a>>(buffer: &mut B) -> Result<u64, DecodeError> {
+    let offset = buffer.position();
+    let mut value = 0u64;
+    for index in 0..MAX_VARINT_LEN {
+        buffer.require(1)?;
+        let byte = buffer.get_u8();
+        if index == MAX_VARINT_LEN - 1 && byte > 1 {
+            return Err(DecodeError::InvalidVarint { offset });
+        }
+        value |= u64::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidVarint { offset })
+}
+
+pub fn zigzag_encode_i32(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+pub fn zigzag_decode_i32(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+pub fn zigzag_encode_i64(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn zigzag_decode_i64(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::buf::SliceBuf;
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u64::from(u32::MAX),
+            u64::MAX,
+        ];
+        for &value in values.iter() {
+            let mut buffer = Vec::new();
+            encode_varint(value, &mut buffer);
+            assert_eq!(buffer.len(), varint_len(value), "Length of {}", value);
+            assert_eq!(decode_varint(&mut SliceBuf::new(&buffer)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn varint_bytes() {
+        let mut buffer = Vec::new();
+        encode_varint(300, &mut buffer);
+        assert_eq!(buffer, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn decode_truncated_varint() {
+        match decode_varint(&mut SliceBuf::new(&[0x80, 0x80])) {
+            Err(DecodeError::Truncated { offset: 2, .. }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_overlong_varint() {
+        let bytes = [0xff; 11];
+        assert_eq!(
+            decode_varint(&mut SliceBuf::new(&bytes)),
+            Err(DecodeError::InvalidVarint { offset: 0 })
+        );
+        let mut bytes = [0xff; 10];
+        bytes[9] = 0x02;
+        assert_eq!(
+            decode_varint(&mut SliceBuf::new(&bytes)),
+            Err(DecodeError::InvalidVarint { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn zigzag() {
+        assert_eq!(zigzag_encode_i32(0), 0);
+        assert_eq!(zigzag_encode_i32(-1), 1);
+        assert_eq!(zigzag_encode_i32(1), 2);
+        assert_eq!(zigzag_encode_i32(i32::MIN), u32::MAX);
+        for &value in [0, -1, 1, i32::MIN, i32::MAX].iter() {
+            assert_eq!(zigzag_decode_i32(zigzag_encode_i32(value)), value);
+        }
+        for &value in [0, -1, 1, i64::MIN, i64::MAX].iter() {
+            assert_eq!(zigzag_decode_i64(zigzag_encode_i64(value)), value);
+        }
+    }
+}
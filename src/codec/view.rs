@@ -0,0 +1,377 @@
+//! Lazy decoding of binary format messages.
+//!
+//! A `MessageView` decodes the fixed size fields of a message up front and leaves headers and
+//! body in the buffer until they are asked for.  Headers can be projected onto a set of keys,
+//! in which case the values of all other headers are skipped over without being built.
+//!
+//! Compressed headers or bodies are decompressed when they are asked for, and values decoded
+//! from them own their data rather than borrowing from the buffer.
+
+use bytes::Buf;
+use codec::buf::{DecodeBuf, SliceBuf};
+use codec::error::DecodeError;
+use codec::limits::{DecodeLimits, Limit};
+use codec::message_codec::{BinaryMessageCodec, MessageDecoder};
+use codec::util;
+use message::message::{Key, Map, Message, Timestamp, Value};
+use uuid::Uuid;
+
+/// A binary format message whose headers and body are decoded on demand.
+///
+/// Strings, bytes and string keys decoded through a view borrow from the underlying buffer.
+#[derive(Debug, Clone)]
+pub struct MessageView<'a> {
+    buffer: &'a [u8],
+    codec: BinaryMessageCodec,
+    flags: util::Flags,
+    timestamp: Option<Timestamp>,
+    expiration: Option<Timestamp>,
+    correlation_id: Option<Uuid>,
+    headers_offset: usize,
+}
+
+impl<'a> MessageView<'a> {
+    pub fn new(buffer: &'a [u8]) -> Result<MessageView<'a>, DecodeError> {
+        MessageView::with_limits(buffer, DecodeLimits::new())
+    }
+
+    pub fn with_limits(
+        buffer: &'a [u8],
+        limits: DecodeLimits,
+    ) -> Result<MessageView<'a>, DecodeError> {
+        let codec = BinaryMessageCodec::with_limits(limits);
+        let mut cursor = SliceBuf::new(buffer);
+
+        limits.check(Limit::MessageSize, cursor.remaining(), cursor.position())?;
+
+        let flags_offset = cursor.position();
+        let bits = codec.decode_i32(&mut cursor)?;
+        let flags = util::Flags::from_bits(bits)
+            .filter(|flags| flags.is_consistent())
+            .ok_or(DecodeError::InvalidFlags {
+                offset: flags_offset,
+                bits,
+            })?;
+
+        let mut timestamp = None;
+        if flags.contains(util::Flags::HAS_TIMESTAMP) {
+            timestamp = Some(codec.decode_timestamp(&mut cursor)?);
+        }
+
+        let mut expiration = None;
+        if flags.contains(util::Flags::HAS_EXPIRATION) {
+            expiration = Some(codec.decode_timestamp(&mut cursor)?);
+        }
+
+        let mut correlation_id = None;
+        if flags.contains(util::Flags::HAS_CORRELATION_ID) {
+            correlation_id = Some(codec.decode_uuid(&mut cursor)?);
+        }
+
+        Ok(MessageView {
+            buffer,
+            codec,
+            flags,
+            timestamp,
+            expiration,
+            correlation_id,
+            headers_offset: cursor.position(),
+        })
+    }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    pub fn expiration(&self) -> Option<Timestamp> {
+        self.expiration
+    }
+
+    pub fn correlation_id(&self) -> Option<Uuid> {
+        self.correlation_id
+    }
+
+    pub fn has_headers(&self) -> bool {
+        self.flags.contains(util::Flags::HAS_HEADERS)
+    }
+
+    pub fn has_body(&self) -> bool {
+        self.flags.contains(util::Flags::HAS_BODY)
+    }
+
+    /// Decodes all headers.
+    pub fn headers(&self) -> Result<Map<'a>, DecodeError> {
+        self.decode_headers_where(|_| true)
+    }
+
+    /// Decodes the headers whose keys are in `keys`, skipping over all other values.
+    pub fn decode_headers(&self, keys: &[Key]) -> Result<Map<'a>, DecodeError> {
+        self.decode_headers_where(|key| keys.iter().any(|wanted| wanted == key))
+    }
+
+    /// Decodes the value of a single header, skipping over all others.
+    pub fn header(&self, key: &Key) -> Result<Option<Value<'a>>, DecodeError> {
+        let headers = self.decode_headers_where(|candidate| candidate == key)?;
+        Ok(headers.iter().next().map(|(_, value)| value.clone()))
+    }
+
+    /// Decodes the body, skipping over the headers before it.
+    pub fn body(&self) -> Result<Option<Value<'a>>, DecodeError> {
+        if !self.has_body() {
+            return Ok(None);
+        }
+        let mut cursor = self.cursor_at(self.headers_offset);
+        if self.flags.contains(util::Flags::HEADERS_COMPRESSED) {
+            self.skip_compressed(&mut cursor)?;
+        } else if self.has_headers() {
+            self.skip_map(&mut cursor, 1)?;
+        }
+        if self.flags.contains(util::Flags::BODY_COMPRESSED) {
+            let body = self.codec.decode_compressed(&mut cursor, |section| {
+                self.codec.decode_value(section, 0)
+            })?;
+            return Ok(Some(body));
+        }
+        Ok(Some(self.codec.decode_value(&mut cursor, 0)?))
+    }
+
+    /// Decodes the whole message.
+    pub fn to_message(&self) -> Result<Message<'a>, DecodeError> {
+        self.codec.decode_message(&mut SliceBuf::new(self.buffer))
+    }
+
+    fn cursor_at(&self, offset: usize) -> SliceBuf<'a> {
+        let mut cursor = SliceBuf::new(self.buffer);
+        cursor.advance(offset);
+        cursor
+    }
+
+    fn decode_headers_where<F>(&self, wanted: F) -> Result<Map<'a>, DecodeError>
+        where F: Fn(&Key<'a>) -> bool
+    {
+        if !self.has_headers() {
+            return Ok(Map::new());
+        }
+        let mut cursor = self.cursor_at(self.headers_offset);
+        if self.flags.contains(util::Flags::HEADERS_COMPRESSED) {
+            return self.codec.decode_compressed(&mut cursor, |section| {
+                self.project_headers(section, wanted)
+            });
+        }
+        self.project_headers(&mut cursor, wanted)
+    }
+
+    fn project_headers<B, F>(&self, cursor: &mut B, wanted: F) -> Result<Map<'a>, DecodeError>
+        where B: DecodeBuf<'a>,
+              F: Fn(&Key<'a>) -> bool
+    {
+        let mut headers = Map::new();
+        self.codec.limits().check(Limit::Depth, 1, cursor.position())?;
+        let count = self.codec.decode_len(cursor, Limit::CollectionLength)?;
+        for _ in 0..count {
+            let key = self.codec.decode_key(cursor)?;
+            if wanted(&key) {
+                let value = self.codec.decode_value(cursor, 1)?;
+                headers.insert(key, value);
+            } else {
+                self.skip_value(cursor, 1)?;
+            }
+        }
+        Ok(headers)
+    }
+
+    fn skip_compressed(&self, cursor: &mut SliceBuf<'a>) -> Result<(), DecodeError> {
+        skip_fixed(cursor, 1)?;
+        self.codec.decode_len(cursor, Limit::MessageSize)?;
+        let compressed_len = self.codec.decode_len(cursor, Limit::MessageSize)?;
+        skip_fixed(cursor, compressed_len)
+    }
+
+    fn skip_map<B>(&self, cursor: &mut B, depth: usize) -> Result<(), DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        self.codec.limits().check(Limit::Depth, depth, cursor.position())?;
+        let count = self.codec.decode_len(cursor, Limit::CollectionLength)?;
+        for _ in 0..count {
+            self.skip_key(cursor)?;
+            self.skip_value(cursor, depth)?;
+        }
+        Ok(())
+    }
+
+    fn skip_list<B>(&self, cursor: &mut B, depth: usize) -> Result<(), DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        self.codec.limits().check(Limit::Depth, depth, cursor.position())?;
+        let count = self.codec.decode_len(cursor, Limit::CollectionLength)?;
+        for _ in 0..count {
+            self.skip_value(cursor, depth)?;
+        }
+        Ok(())
+    }
+
+    fn skip_key<B: DecodeBuf<'a>>(&self, cursor: &mut B) -> Result<(), DecodeError> {
+        let tag_offset = cursor.position();
+        cursor.require(1)?;
+        match cursor.get_u8() {
+            1 => self.skip_sized(cursor),
+            2 => skip_fixed(cursor, 4),
+            tag => Err(DecodeError::UnknownKeyType { offset: tag_offset, tag }),
+        }
+    }
+
+    fn skip_value<B>(&self, cursor: &mut B, depth: usize) -> Result<(), DecodeError>
+        where B: DecodeBuf<'a>
+    {
+        let tag_offset = cursor.position();
+        cursor.require(1)?;
+        match cursor.get_u8() {
+            0 => Ok(()),
+            1 | 7 => self.skip_sized(cursor),
+            2 | 4 => skip_fixed(cursor, 4),
+            3 | 5 => skip_fixed(cursor, 8),
+            6 => skip_fixed(cursor, 1),
+            8 => self.skip_list(cursor, depth + 1),
+            9 => self.skip_map(cursor, depth + 1),
+            10 => skip_fixed(cursor, 16),
+            11 | 13 => skip_fixed(cursor, 12),
+            12 => skip_fixed(cursor, 17),
+            14 | 15 => {
+                let count = self.codec.decode_len(cursor, Limit::CollectionLength)?;
+                skip_fixed(cursor, count * 8)
+            }
+            tag => Err(DecodeError::UnknownValueType { offset: tag_offset, tag }),
+        }
+    }
+
+    fn skip_sized<B: DecodeBuf<'a>>(&self, cursor: &mut B) -> Result<(), DecodeError> {
+        let len = self.codec.decode_len(cursor, Limit::BytesLength)?;
+        skip_fixed(cursor, len)
+    }
+}
+
+fn skip_fixed<'a, B: DecodeBuf<'a>>(cursor: &mut B, len: usize) -> Result<(), DecodeError> {
+    cursor.require(len)?;
+    cursor.advance(len);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use bytes::BytesMut;
+    use codec::compression::{Algorithm, Compression};
+    use codec::message_codec::encode_message;
+    use codec::Codec;
+    use message::message::{ListBuilder, MapBuilder, MessageBuilder};
+
+    fn example<'a>() -> Message<'a> {
+        MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+            .with_correlation_id(Uuid::nil())
+            .with_header("route", "orders")
+            .with_header(
+                "nested",
+                MapBuilder::new()
+                    .insert("list", ListBuilder::new().push(1).push(2i64).push(Value::Null).build())
+                    .insert(3, UTC.timestamp(0, 0))
+                    .build(),
+            )
+            .with_header(7, 1.5)
+            .with_body("body")
+            .build()
+    }
+
+    #[test]
+    fn eager_fields() {
+        let bytes = encode_message(&example());
+        let view = MessageView::new(&bytes).unwrap();
+        assert_eq!(view.timestamp(), Some(UTC.timestamp(1_500_000_000, 0)));
+        assert_eq!(view.expiration(), None);
+        assert_eq!(view.correlation_id(), Some(Uuid::nil()));
+        assert!(view.has_headers());
+        assert!(view.has_body());
+    }
+
+    #[test]
+    fn lazy_headers_and_body() {
+        let bytes = encode_message(&example());
+        let view = MessageView::new(&bytes).unwrap();
+        assert_eq!(&view.headers().unwrap(), example().headers());
+        assert_eq!(view.body().unwrap(), Some(Value::from("body")));
+        assert_eq!(view.to_message().unwrap(), example());
+    }
+
+    #[test]
+    fn project_headers() {
+        let bytes = encode_message(&example());
+        let view = MessageView::new(&bytes).unwrap();
+        let keys = [Key::from(7), Key::from("route"), Key::from("missing")];
+        let headers = view.decode_headers(&keys).unwrap();
+        let expected = MapBuilder::new().insert("route", "orders").insert(7, 1.5).build();
+        assert_eq!(headers, expected);
+        assert_eq!(view.header(&Key::from(7)).unwrap(), Some(Value::F64(1.5)));
+        assert_eq!(view.header(&Key::from("missing")).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_message() {
+        let bytes = encode_message(&Message::new());
+        let view = MessageView::new(&bytes).unwrap();
+        assert_eq!(view.headers().unwrap(), Map::new());
+        assert_eq!(view.body().unwrap(), None);
+    }
+
+    #[test]
+    fn skipped_values_are_still_validated() {
+        let mut bytes = encode_message(&example());
+        // Tag of the first value inside the "nested" header.
+        let header_offset = 4 + 12 + 16 + 4;
+        let offset = header_offset + (1 + 4 + 5) + (1 + 4 + 6) + (1 + 4 + 6) + 1 + 4 + (1 + 4 + 4);
+        assert_eq!(bytes[offset], 8);
+        bytes[offset] = 42;
+        let view = MessageView::new(&bytes).unwrap();
+        assert_eq!(
+            view.decode_headers(&[Key::from("route")]),
+            Err(DecodeError::UnknownValueType { offset, tag: 42 })
+        );
+        assert_eq!(view.body(), Err(DecodeError::UnknownValueType { offset, tag: 42 }));
+    }
+
+    #[test]
+    fn compressed_sections() {
+        let compression = Compression::new(Algorithm::Deflate).with_threshold(0).with_headers(true);
+        let codec = BinaryMessageCodec::new().with_compression(compression);
+        let message = || {
+            MessageBuilder::new()
+                .with_header("route", "orders")
+                .with_header("padding", "x".repeat(100))
+                .with_body("y".repeat(100))
+                .build()
+        };
+        let mut bytes = BytesMut::new();
+        codec.encode(&message(), &mut bytes);
+        let view = MessageView::new(&bytes).unwrap();
+        assert_eq!(&view.headers().unwrap(), message().headers());
+        assert_eq!(view.header(&Key::from("route")).unwrap(), Some(Value::from("orders")));
+        assert_eq!(view.body().unwrap(), Some(Value::from("y".repeat(100))));
+        assert_eq!(view.to_message().unwrap(), message());
+    }
+
+    #[test]
+    fn truncated_view() {
+        let bytes = encode_message(&example());
+        match MessageView::new(&bytes[..10]) {
+            Err(DecodeError::Truncated { offset: 4, .. }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+        let view = MessageView::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert!(view.headers().is_ok());
+        let body = view.body();
+        match body {
+            Err(DecodeError::Truncated { .. }) => (),
+            other => panic!("Expected truncation, got {:?}", other),
+        }
+    }
+}
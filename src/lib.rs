@@ -5,12 +5,24 @@
 extern crate bitflags;
 extern crate base64;
 extern crate bytes;
+extern crate chacha20poly1305;
 extern crate chrono;
+extern crate flate2;
 extern crate linked_hash_map;
+extern crate lz4_flex;
+#[cfg(test)]
+extern crate quickcheck;
+extern crate rmp;
 extern crate uuid;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_bytes;
+extern crate serde_json;
+extern crate sha2;
 
 pub mod codec;
 pub mod message;
 pub mod pipeline;
-//pub mod topic;
+pub mod topic;
@@ -0,0 +1,284 @@
+//! Typed access to values.
+//!
+//! `TryFrom<&Value>` is implemented for each type a value can hold, failing with a
+//! `ConversionError` when the value holds another type.  Conversions are exact except for the
+//! lossless widenings of `I32` to `i64` and `F32` to `f64`.  `Map` builds typed getters on
+//! these, which return `Ok(None)` for a missing key.
+
+use message::error::ConversionError;
+use message::message::{Decimal, Key, List, Map, Timestamp, Value};
+use std::convert::TryFrom;
+use std::time::Duration;
+use uuid::Uuid;
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for &'v str {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<&'v str, ConversionError> {
+        match *value {
+            Value::Str(ref value) => Ok(value),
+            _ => Err(ConversionError::new("string", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for &'v [u8] {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<&'v [u8], ConversionError> {
+        match *value {
+            Value::Bytes(ref value) => Ok(value),
+            _ => Err(ConversionError::new("bytes", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for i32 {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<i32, ConversionError> {
+        match *value {
+            Value::I32(value) => Ok(value),
+            _ => Err(ConversionError::new("i32", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for i64 {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<i64, ConversionError> {
+        match *value {
+            Value::I64(value) => Ok(value),
+            Value::I32(value) => Ok(i64::from(value)),
+            _ => Err(ConversionError::new("i64", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for f32 {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<f32, ConversionError> {
+        match *value {
+            Value::F32(value) => Ok(value),
+            _ => Err(ConversionError::new("f32", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<f64, ConversionError> {
+        match *value {
+            Value::F64(value) => Ok(value),
+            Value::F32(value) => Ok(f64::from(value)),
+            _ => Err(ConversionError::new("f64", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<bool, ConversionError> {
+        match *value {
+            Value::Bool(value) => Ok(value),
+            _ => Err(ConversionError::new("bool", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for Uuid {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<Uuid, ConversionError> {
+        match *value {
+            Value::Uuid(value) => Ok(value),
+            _ => Err(ConversionError::new("uuid", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for Timestamp {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<Timestamp, ConversionError> {
+        match *value {
+            Value::Timestamp(value) => Ok(value),
+            _ => Err(ConversionError::new("timestamp", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for Decimal {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<Decimal, ConversionError> {
+        match *value {
+            Value::Decimal(value) => Ok(value),
+            _ => Err(ConversionError::new("decimal", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for Duration {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<Duration, ConversionError> {
+        match *value {
+            Value::Duration(value) => Ok(value),
+            _ => Err(ConversionError::new("duration", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for &'v List<'a> {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<&'v List<'a>, ConversionError> {
+        match *value {
+            Value::List(ref value) => Ok(value),
+            _ => Err(ConversionError::new("list", value.type_name())),
+        }
+    }
+}
+
+impl<'v, 'a> TryFrom<&'v Value<'a>> for &'v Map<'a> {
+    type Error = ConversionError;
+
+    fn try_from(value: &'v Value<'a>) -> Result<&'v Map<'a>, ConversionError> {
+        match *value {
+            Value::Map(ref value) => Ok(value),
+            _ => Err(ConversionError::new("map", value.type_name())),
+        }
+    }
+}
+
+impl<'a> Map<'a> {
+    /// The value of `key` converted to `T`, or `None` if the map has no such key.
+    pub fn get_as<'m, T, K>(&'m self, key: K) -> Result<Option<T>, ConversionError>
+    where
+        T: TryFrom<&'m Value<'a>, Error = ConversionError>,
+        K: Into<Key<'a>>,
+    {
+        self.get(&key.into()).map(T::try_from).transpose()
+    }
+
+    pub fn get_str<'m, K>(&'m self, key: K) -> Result<Option<&'m str>, ConversionError>
+    where
+        K: Into<Key<'a>>,
+    {
+        self.get_as(key)
+    }
+
+    pub fn get_bytes<'m, K>(&'m self, key: K) -> Result<Option<&'m [u8]>, ConversionError>
+    where
+        K: Into<Key<'a>>,
+    {
+        self.get_as(key)
+    }
+
+    pub fn get_i32<K: Into<Key<'a>>>(&self, key: K) -> Result<Option<i32>, ConversionError> {
+        self.get_as(key)
+    }
+
+    /// Accepts `I32` values as well as `I64`.
+    pub fn get_i64<K: Into<Key<'a>>>(&self, key: K) -> Result<Option<i64>, ConversionError> {
+        self.get_as(key)
+    }
+
+    /// Accepts `F32` values as well as `F64`.
+    pub fn get_f64<K: Into<Key<'a>>>(&self, key: K) -> Result<Option<f64>, ConversionError> {
+        self.get_as(key)
+    }
+
+    pub fn get_bool<K: Into<Key<'a>>>(&self, key: K) -> Result<Option<bool>, ConversionError> {
+        self.get_as(key)
+    }
+
+    pub fn get_uuid<K: Into<Key<'a>>>(&self, key: K) -> Result<Option<Uuid>, ConversionError> {
+        self.get_as(key)
+    }
+
+    pub fn get_timestamp<K>(&self, key: K) -> Result<Option<Timestamp>, ConversionError>
+    where
+        K: Into<Key<'a>>,
+    {
+        self.get_as(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use message::message::{ListBuilder, MapBuilder, MessageBuilder};
+
+    #[test]
+    fn typed_getters() {
+        let message = MessageBuilder::new()
+            .with_header("name", "value")
+            .with_header("attempt", 3)
+            .with_header("retry", true)
+            .with_header("id", Value::Uuid(Uuid::nil()))
+            .with_header("sent", UTC.timestamp(1_500_000_000, 0))
+            .with_header(7, vec![1u8, 2])
+            .build();
+        let headers = message.headers();
+
+        assert_eq!(headers.get_str("name"), Ok(Some("value")));
+        assert_eq!(headers.get_i32("attempt"), Ok(Some(3)));
+        assert_eq!(headers.get_i64("attempt"), Ok(Some(3)));
+        assert_eq!(headers.get_bool("retry"), Ok(Some(true)));
+        assert_eq!(headers.get_uuid("id"), Ok(Some(Uuid::nil())));
+        assert_eq!(headers.get_timestamp("sent"), Ok(Some(UTC.timestamp(1_500_000_000, 0))));
+        assert_eq!(headers.get_bytes(7), Ok(Some(&[1u8, 2][..])));
+        assert_eq!(headers.get_str("missing"), Ok(None));
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let headers = MessageBuilder::new()
+            .with_header("name", "value")
+            .with_header("big", i64::MAX)
+            .build()
+            .headers()
+            .clone();
+
+        let error = headers.get_i64("name").unwrap_err();
+        assert_eq!(error, ConversionError::new("i64", "string"));
+        assert_eq!(error.to_string(), "Expected i64 but found string");
+        assert_eq!(headers.get_i32("big"), Err(ConversionError::new("i32", "i64")));
+        assert_eq!(headers.get_str("big"), Err(ConversionError::new("string", "i64")));
+    }
+
+    #[test]
+    fn widening() {
+        assert_eq!(i64::try_from(&Value::I32(-7)), Ok(-7));
+        assert_eq!(f64::try_from(&Value::F32(1.5)), Ok(1.5));
+        assert_eq!(f32::try_from(&Value::F64(1.5)), Err(ConversionError::new("f32", "f64")));
+        assert_eq!(i32::try_from(&Value::I64(1)), Err(ConversionError::new("i32", "i64")));
+    }
+
+    #[test]
+    fn get_as() {
+        let headers = MapBuilder::new()
+            .insert("nested", MapBuilder::new().insert("depth", 2).build())
+            .insert("list", ListBuilder::new().push(1).build())
+            .insert("price", Decimal::new(1999, 2))
+            .insert("timeout", Duration::from_secs(30))
+            .build();
+
+        let nested: &Map = headers.get_as("nested").unwrap().unwrap();
+        assert_eq!(nested.get_i64("depth"), Ok(Some(2)));
+        assert_eq!(headers.get_as::<&List, _>("list").unwrap().map(List::len), Some(1));
+        assert_eq!(headers.get_as("price"), Ok(Some(Decimal::new(1999, 2))));
+        assert_eq!(headers.get_as("timeout"), Ok(Some(Duration::from_secs(30))));
+        assert_eq!(
+            headers.get_as::<Duration, _>("price"),
+            Err(ConversionError::new("duration", "decimal"))
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! `Deserialize` for the message model, and `from_value` for turning a `Value` into any
+//! `Deserialize` type.
+
+use message::error::Error;
+use message::message::{Key, List, Map, Message, Timestamp, Value};
+use message::ser::TimestampRepr;
+use serde::de::{
+    self, Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer,
+    MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use serde::de::value::SeqDeserializer;
+use std::borrow::Cow;
+use std::fmt;
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+/// Converts a `Value` into any `Deserialize` type.
+///
+/// This is the inverse of `to_value`: maps deserialize into structs and maps, lists into
+/// sequences and tuples, and a string or single entry map into an enum.  `Uuid`, `Timestamp`
+/// and `Decimal` values are presented as a hyphenated UUID string, an RFC 3339 string and a
+/// decimal string, a `Duration` as a `(seconds, nanoseconds)` sequence and the typed arrays as
+/// sequences.  A `Timestamp` that RFC 3339 cannot represent is presented as a `(seconds,
+/// nanoseconds)` sequence, as `to_value` writes it.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+#[derive(Deserialize)]
+struct MessageRepr<'a> {
+    #[serde(default)]
+    timestamp: Option<TimestampRepr>,
+    #[serde(default)]
+    expiration: Option<TimestampRepr>,
+    #[serde(default)]
+    correlation_id: Option<String>,
+    #[serde(default = "Map::new")]
+    headers: Map<'a>,
+    #[serde(default)]
+    body: Option<Value<'a>>,
+}
+
+impl<'de, 'a> Deserialize<'de> for Message<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MessageRepr::deserialize(deserializer)?;
+        let mut message = Message::new();
+        if let Some(ref value) = repr.timestamp {
+            message.set_timestamp(Some(parse_timestamp(value)?));
+        }
+        if let Some(ref value) = repr.expiration {
+            message.set_expiration(Some(parse_timestamp(value)?));
+        }
+        if let Some(ref value) = repr.correlation_id {
+            let uuid = Uuid::parse_str(value)
+                .map_err(|_| de::Error::custom(format!("Invalid UUID '{}'", value)))?;
+            message.set_correlation_id(Some(uuid));
+        }
+        *message.headers_mut() = repr.headers;
+        message.set_body(repr.body);
+        Ok(message)
+    }
+}
+
+fn parse_timestamp<E: de::Error>(repr: &TimestampRepr) -> Result<Timestamp, E> {
+    repr.timestamp()
+        .ok_or_else(|| E::custom(format!("Invalid timestamp {}", repr)))
+}
+
+impl<'de, 'a> Deserialize<'de> for Key<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(KeyVisitor(PhantomData))
+    }
+}
+
+struct KeyVisitor<'a>(PhantomData<Key<'a>>);
+
+impl<'de, 'a> Visitor<'de> for KeyVisitor<'a> {
+    type Value = Key<'a>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string or an integer that fits in an i32")
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Key<'a>, E> {
+        if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) {
+            Ok(Key::I32(value as i32))
+        } else {
+            Err(E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Key<'a>, E> {
+        if value <= i32::MAX as u64 {
+            Ok(Key::I32(value as i32))
+        } else {
+            Err(E::invalid_value(de::Unexpected::Unsigned(value), &self))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Key<'a>, E> {
+        Ok(Key::Str(Cow::Owned(value.to_owned())))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Key<'a>, E> {
+        Ok(Key::Str(Cow::Owned(value)))
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for Value<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor(PhantomData))
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for List<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.deserialize_seq(ValueVisitor(PhantomData))? {
+            Value::List(list) => Ok(list),
+            _ => Err(de::Error::custom("expected a list")),
+        }
+    }
+}
+
+impl<'de, 'a> Deserialize<'de> for Map<'a> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match deserializer.deserialize_map(ValueVisitor(PhantomData))? {
+            Value::Map(map) => Ok(map),
+            _ => Err(de::Error::custom("expected a map")),
+        }
+    }
+}
+
+struct ValueVisitor<'a>(PhantomData<Value<'a>>);
+
+impl<'de, 'a> Visitor<'de> for ValueVisitor<'a> {
+    type Value = Value<'a>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value<'a>, E> {
+        Ok(Value::Bool(value))
+    }
+
+    fn visit_i32<E: de::Error>(self, value: i32) -> Result<Value<'a>, E> {
+        Ok(Value::I32(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value<'a>, E> {
+        if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) {
+            Ok(Value::I32(value as i32))
+        } else {
+            Ok(Value::I64(value))
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value<'a>, E> {
+        if value <= i64::MAX as u64 {
+            self.visit_i64(value as i64)
+        } else {
+            Err(E::invalid_value(de::Unexpected::Unsigned(value), &self))
+        }
+    }
+
+    fn visit_f32<E: de::Error>(self, value: f32) -> Result<Value<'a>, E> {
+        Ok(Value::F32(value))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value<'a>, E> {
+        Ok(Value::F64(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value<'a>, E> {
+        Ok(Value::Str(Cow::Owned(value.to_owned())))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Value<'a>, E> {
+        Ok(Value::Str(Cow::Owned(value)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Value<'a>, E> {
+        Ok(Value::Bytes(Cow::Owned(value.to_owned())))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Value<'a>, E> {
+        Ok(Value::Bytes(Cow::Owned(value)))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value<'a>, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value<'a>, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value<'a>, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Value<'a>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value<'a>, A::Error> {
+        let mut list = List::new();
+        while let Some(value) = seq.next_element::<Value<'a>>()? {
+            list.push(value);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value<'a>, A::Error> {
+        let mut map = Map::new();
+        while let Some((key, value)) = access.next_entry::<Key<'a>, Value<'a>>()? {
+            map.insert(key, value);
+        }
+        Ok(Value::Map(map))
+    }
+}
+
+impl<'de, 'a> Deserializer<'de> for &'de Value<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self {
+            Value::Null => visitor.visit_unit(),
+            Value::Str(ref value) => visitor.visit_borrowed_str(value),
+            Value::I32(value) => visitor.visit_i32(value),
+            Value::I64(value) => visitor.visit_i64(value),
+            Value::F32(value) => visitor.visit_f32(value),
+            Value::F64(value) => visitor.visit_f64(value),
+            Value::Bool(value) => visitor.visit_bool(value),
+            Value::Bytes(ref value) => visitor.visit_borrowed_bytes(value),
+            Value::List(ref list) => visitor.visit_seq(ListAccess { iter: list.iter() }),
+            Value::Map(ref map) => visitor.visit_map(MapEntryAccess {
+                iter: map.iter(),
+                value: None,
+            }),
+            Value::Uuid(value) => visitor.visit_string(value.hyphenated().to_string()),
+            Value::Timestamp(value) => match TimestampRepr::new(value) {
+                TimestampRepr::Rfc3339(value) => visitor.visit_string(value),
+                TimestampRepr::Parts(seconds, nanos) => {
+                    let parts = vec![seconds, i64::from(nanos)];
+                    visitor.visit_seq(SeqDeserializer::new(parts.into_iter()))
+                }
+            },
+            Value::Decimal(value) => visitor.visit_string(value.to_string()),
+            Value::Duration(value) => {
+                let parts = vec![value.as_secs(), u64::from(value.subsec_nanos())];
+                visitor.visit_seq(SeqDeserializer::new(parts.into_iter()))
+            }
+            Value::I64Array(ref values) => {
+                visitor.visit_seq(SeqDeserializer::new(values.iter().cloned()))
+            }
+            Value::F64Array(ref values) => {
+                visitor.visit_seq(SeqDeserializer::new(values.iter().cloned()))
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match *self {
+            Value::Str(ref variant) => visitor.visit_enum(variant.as_ref().into_deserializer()),
+            Value::Map(ref map) if map.len() == 1 => {
+                let (variant, value) = map.iter().next().unwrap();
+                visitor.visit_enum(VariantValueAccess { variant, value })
+            }
+            _ => Err(de::Error::custom("expected a string or a single entry map for an enum")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de, 'a> Deserializer<'de> for &'de Key<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self {
+            Key::Str(ref key) => visitor.visit_borrowed_str(key),
+            Key::I32(key) => visitor.visit_i32(key),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+struct ListAccess<'de, 'a: 'de> {
+    iter: ::std::slice::Iter<'de, Value<'a>>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for ListAccess<'de, 'a> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapEntryAccess<'de, 'a: 'de> {
+    iter: ::linked_hash_map::Iter<'de, Key<'a>, Value<'a>>,
+    value: Option<&'de Value<'a>>,
+}
+
+impl<'de, 'a> MapAccess<'de> for MapEntryAccess<'de, 'a> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("next_value_seed called before next_key_seed")),
+        }
+    }
+}
+
+struct VariantValueAccess<'de, 'a: 'de> {
+    variant: &'de Key<'a>,
+    value: &'de Value<'a>,
+}
+
+impl<'de, 'a> EnumAccess<'de> for VariantValueAccess<'de, 'a> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> VariantAccess<'de> for VariantValueAccess<'de, 'a> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match *self.value {
+            Value::Null => Ok(()),
+            _ => Err(de::Error::custom("expected a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        Deserializer::deserialize_seq(self.value, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        Deserializer::deserialize_map(self.value, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, UTC};
+    use message::message::{ListBuilder, MapBuilder, MessageBuilder};
+    use message::to_value;
+    use serde_json;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        customer: String,
+        items: Vec<(String, u8)>,
+        attributes: HashMap<i32, String>,
+        note: Option<String>,
+        status: Status,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Status {
+        Open,
+        Held(String),
+        Shipped { carrier: String },
+    }
+
+    #[test]
+    fn struct_round_trip() {
+        let mut attributes = HashMap::new();
+        attributes.insert(1, "gift".to_owned());
+        for status in [
+            Status::Open,
+            Status::Held("fraud".to_owned()),
+            Status::Shipped { carrier: "ups".to_owned() },
+        ] {
+            let order = Order {
+                id: 42,
+                customer: "jimmie".to_owned(),
+                items: vec![("A-1".to_owned(), 2)],
+                attributes: attributes.clone(),
+                note: Some("leave at door".to_owned()),
+                status,
+            };
+            let value = to_value(&order).unwrap();
+            assert_eq!(from_value::<Order>(&value), Ok(order));
+        }
+    }
+
+    #[test]
+    fn from_value_type_mismatch() {
+        let value = Value::Map(MapBuilder::new().insert("id", "not a number").build());
+        assert!(from_value::<Order>(&value).is_err());
+    }
+
+    #[test]
+    fn message_through_json() {
+        let message = MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(1_500_000_000, 123))
+            .with_correlation_id(Uuid::nil())
+            .with_header("attempt", 3)
+            .with_header("name", "seven")
+            .with_body(ListBuilder::new().push("a").push(2.5).push(Value::Null).build())
+            .build();
+        let json = serde_json::to_string(&message).unwrap();
+        let output: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(output, message);
+    }
+
+    #[test]
+    fn message_with_timestamps_outside_rfc_3339() {
+        let message = MessageBuilder::new()
+            .with_timestamp(UTC.timestamp(300_000_000_000, 5))
+            .with_expiration(UTC.timestamp(-62_167_219_201, 0))
+            .build();
+        let json = serde_json::to_string(&message).unwrap();
+        let expected = r#"{"timestamp":[300000000000,5],"expiration":[-62167219201,0],"#;
+        assert!(json.starts_with(expected), "{}", json);
+        let output: Message = serde_json::from_str(&json).unwrap();
+        assert_eq!(output, message);
+        assert_eq!(from_value::<Message>(&to_value(&message).unwrap()), Ok(message));
+    }
+
+    #[test]
+    fn message_with_missing_fields() {
+        let output: Message = serde_json::from_str(r#"{"body": {"key": "value"}}"#).unwrap();
+        let expected = MessageBuilder::new()
+            .with_body(MapBuilder::new().insert("key", "value").build())
+            .build();
+        assert_eq!(output, expected);
+    }
+}
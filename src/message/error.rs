@@ -0,0 +1,68 @@
+use serde::{de, ser};
+use std::error;
+use std::fmt;
+
+/// Error produced when converting between serde data types and `Value` with `to_value` and
+/// `from_value`, or when a message model type is serialized or deserialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    message: String,
+}
+
+impl Error {
+    pub(crate) fn new<T: fmt::Display>(message: T) -> Error {
+        Error {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg)
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg)
+    }
+}
+
+/// Error produced when a `Value` does not hold the type it is converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionError {
+    expected: &'static str,
+    found: &'static str,
+}
+
+impl ConversionError {
+    pub(crate) fn new(expected: &'static str, found: &'static str) -> ConversionError {
+        ConversionError { expected, found }
+    }
+
+    pub fn expected(&self) -> &'static str {
+        self.expected
+    }
+
+    /// Type name of the value that was found, as given by `Value::type_name`.
+    pub fn found(&self) -> &'static str {
+        self.found
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected {} but found {}", self.expected, self.found)
+    }
+}
+
+impl error::Error for ConversionError {}
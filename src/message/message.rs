@@ -1,7 +1,13 @@
 use linked_hash_map::LinkedHashMap;
 use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 use uuid::Uuid;
 use chrono::{DateTime, UTC};
+use codec::message_codec::encode_message_canonical;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Message<'a> {
@@ -62,11 +68,59 @@ impl<'a> Message<'a> {
         }
     }
 
+    pub fn body_mut(&mut self) -> Option<&mut Value<'a>> {
+        self.body.as_mut()
+    }
+
     pub fn set_body<V: Into<Value<'a>>>(&mut self, value: Option<V>) {
         self.body = value.map(|v| v.into()).or(None);
     }
+
+    #[deprecated(note = "use `MessageBuilder::new().with_header`")]
+    pub fn with_property<K, V>(key: K, value: V) -> MessageBuilder<'a>
+    where
+        K: Into<Key<'a>>,
+        V: Into<Value<'a>>,
+    {
+        MessageBuilder::new().with_header(key, value)
+    }
+
+    #[deprecated(note = "use `MessageBuilder::new().with_body`")]
+    pub fn with_body<V: Into<Value<'a>>>(value: V) -> MessageBuilder<'a> {
+        MessageBuilder::new().with_body(value)
+    }
+
+    #[deprecated(note = "use `headers`")]
+    pub fn properties(&self) -> &Map<'a> {
+        &self.headers
+    }
+
+    /// SHA-256 digest of the canonical binary encoding of the message.
+    ///
+    /// The digest is stable across releases and the same for messages that differ only in the
+    /// order of their map entries, so it can be used to deduplicate and address messages.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(encode_message_canonical(self)));
+        hash
+    }
+
+    /// Copies every borrowed string and byte slice, so the message no longer borrows from the
+    /// buffer it was decoded from and can be sent to other threads or queued.
+    pub fn into_owned(self) -> OwnedMessage {
+        Message {
+            timestamp: self.timestamp,
+            expiration: self.expiration,
+            correlation_id: self.correlation_id,
+            headers: self.headers.into_owned(),
+            body: self.body.map(Value::into_owned),
+        }
+    }
 }
 
+/// A message that owns all of its data.
+pub type OwnedMessage = Message<'static>;
+
 pub struct MessageBuilder<'a> {
     message: Message<'a>,
 }
@@ -110,6 +164,15 @@ impl<'a> MessageBuilder<'a> {
         self
     }
 
+    #[deprecated(note = "use `with_header`")]
+    pub fn with_property<K, V>(self, key: K, value: V) -> MessageBuilder<'a>
+    where
+        K: Into<Key<'a>>,
+        V: Into<Value<'a>>,
+    {
+        self.with_header(key, value)
+    }
+
 
     pub fn build(self) -> Message<'a> {
         self.message
@@ -122,6 +185,15 @@ pub enum Key<'a> {
     I32(i32),
 }
 
+impl<'a> Key<'a> {
+    pub fn into_owned(self) -> Key<'static> {
+        match self {
+            Key::Str(key) => Key::Str(Cow::Owned(key.into_owned())),
+            Key::I32(key) => Key::I32(key),
+        }
+    }
+}
+
 impl<'a> From<&'a str> for Key<'a> {
     fn from(key: &'a str) -> Self {
         Key::Str(Cow::Borrowed(key))
@@ -156,13 +228,120 @@ impl<'a> List<'a> {
         self.inner.iter()
     }
 
+    pub fn iter_mut<'l>(&'l mut self) -> std::slice::IterMut<'l, Value<'a>> {
+        self.inner.iter_mut()
+    }
+
     pub fn push<V: Into<Value<'a>>>(&mut self, value: V) {
         self.inner.push(value.into());
     }
 
+    pub fn pop(&mut self) -> Option<Value<'a>> {
+        self.inner.pop()
+    }
+
+    /// Inserts `value` at `index`, shifting later elements along.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the length of the list.
+    pub fn insert<V: Into<Value<'a>>>(&mut self, index: usize, value: V) {
+        self.inner.insert(index, value.into());
+    }
+
+    /// Removes and returns the element at `index`, shifting later elements back.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Value<'a> {
+        self.inner.remove(index)
+    }
+
+    pub fn retain<F: FnMut(&Value<'a>) -> bool>(&mut self, keep: F) {
+        self.inner.retain(keep);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.inner.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value<'a>> {
+        self.inner.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Value<'a>> {
+        self.inner.get_mut(index)
+    }
+
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn into_owned(self) -> List<'static> {
+        List { inner: self.inner.into_iter().map(Value::into_owned).collect() }
+    }
+}
+
+impl<'a> std::ops::Index<usize> for List<'a> {
+    type Output = Value<'a>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.inner[index]
+    }
+}
+
+impl<'a> std::ops::IndexMut<usize> for List<'a> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.inner[index]
+    }
+}
+
+impl<'a, V: Into<Value<'a>>> Extend<V> for List<'a> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, values: I) {
+        self.inner.extend(values.into_iter().map(Into::into));
+    }
+}
+
+impl<'a, V: Into<Value<'a>>> std::iter::FromIterator<V> for List<'a> {
+    fn from_iter<I: IntoIterator<Item = V>>(values: I) -> List<'a> {
+        List { inner: values.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl<'a> IntoIterator for List<'a> {
+    type Item = Value<'a>;
+    type IntoIter = std::vec::IntoIter<Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'l, 'a> IntoIterator for &'l List<'a> {
+    type Item = &'l Value<'a>;
+    type IntoIter = std::slice::Iter<'l, Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<'l, 'a> IntoIterator for &'l mut List<'a> {
+    type Item = &'l mut Value<'a>;
+    type IntoIter = std::slice::IterMut<'l, Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter_mut()
+    }
 }
 
 pub struct ListBuilder<'a> {
@@ -189,6 +368,14 @@ impl<'a> ListBuilder<'a> {
         self.list.push(value.into());
     }
 
+    #[deprecated(note = "use `push`")]
+    pub fn append<V>(self, value: V) -> ListBuilder<'a>
+        where
+            V: Into<Value<'a>>,
+    {
+        self.push(value)
+    }
+
     pub fn build(self) -> List<'a> {
         self.list
     }
@@ -204,25 +391,132 @@ impl<'a> Map<'a> {
         Map{ inner: LinkedHashMap::new() }
     }
 
-    pub fn insert<K: Into<Key<'a>>, V: Into<Value<'a>>>(&mut self, key: K, value: V) {
-        self.inner.insert(key.into(), value.into());
+    pub fn builder() -> MapBuilder<'a> { MapBuilder::new() }
+
+    /// Inserts `value` under `key`, returning the value it replaces.  The entry moves to the end
+    /// of the map, whether or not it was already present.
+    pub fn insert<K, V>(&mut self, key: K, value: V) -> Option<Value<'a>>
+        where
+            K: Into<Key<'a>>,
+            V: Into<Value<'a>>,
+    {
+        self.inner.insert(key.into(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &Key<'a>) -> Option<Value<'a>> {
+        self.inner.remove(key)
+    }
+
+    pub fn entry<'m, K>(&'m mut self, key: K) -> linked_hash_map::Entry<'m, Key<'a>, Value<'a>>
+        where
+            K: Into<Key<'a>>,
+    {
+        self.inner.entry(key.into())
+    }
+
+    /// Keeps only the entries for which `keep` returns `true`, in their original order.
+    pub fn retain<F>(&mut self, mut keep: F)
+        where
+            F: FnMut(&Key<'a>, &mut Value<'a>) -> bool,
+    {
+        let entries = std::mem::replace(&mut self.inner, LinkedHashMap::new());
+        for (key, mut value) in entries {
+            if keep(&key, &mut value) {
+                self.inner.insert(key, value);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
     }
 
     pub fn get(&self, key: &Key<'a>) -> Option<&Value<'a>> {
         self.inner.get(key)
     }
 
+    pub fn get_mut(&mut self, key: &Key<'a>) -> Option<&mut Value<'a>> {
+        self.inner.get_mut(key)
+    }
+
     pub fn iter(&self) -> linked_hash_map::Iter<Key<'a>, Value<'a>> {
         self.inner.iter()
     }
 
+    pub fn iter_mut<'m>(&'m mut self) -> linked_hash_map::IterMut<'m, Key<'a>, Value<'a>> {
+        self.inner.iter_mut()
+    }
+
+    pub fn keys<'m>(&'m self) -> linked_hash_map::Keys<'m, Key<'a>, Value<'a>> {
+        self.inner.keys()
+    }
+
+    pub fn values<'m>(&'m self) -> linked_hash_map::Values<'m, Key<'a>, Value<'a>> {
+        self.inner.values()
+    }
+
     pub fn len(&self) -> usize {
         self.inner.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub fn contains_key(&self, key: &Key<'a>) -> bool {
         self.inner.contains_key(key)
     }
+
+    pub fn into_owned(self) -> Map<'static> {
+        let inner = self.inner
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        Map { inner }
+    }
+}
+
+impl<'a, K: Into<Key<'a>>, V: Into<Value<'a>>> Extend<(K, V)> for Map<'a> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<'a, K: Into<Key<'a>>, V: Into<Value<'a>>> std::iter::FromIterator<(K, V)> for Map<'a> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Map<'a> {
+        let mut map = Map::new();
+        map.extend(entries);
+        map
+    }
+}
+
+impl<'a> IntoIterator for Map<'a> {
+    type Item = (Key<'a>, Value<'a>);
+    type IntoIter = linked_hash_map::IntoIter<Key<'a>, Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'m, 'a> IntoIterator for &'m Map<'a> {
+    type Item = (&'m Key<'a>, &'m Value<'a>);
+    type IntoIter = linked_hash_map::Iter<'m, Key<'a>, Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<'m, 'a> IntoIterator for &'m mut Map<'a> {
+    type Item = (&'m Key<'a>, &'m mut Value<'a>);
+    type IntoIter = linked_hash_map::IterMut<'m, Key<'a>, Value<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter_mut()
+    }
 }
 
 pub struct MapBuilder<'a> {
@@ -270,9 +564,58 @@ pub enum Value<'a> {
     Map(Map<'a>),
     Uuid(Uuid),
     Timestamp(Timestamp),
+    Decimal(Decimal),
+    Duration(Duration),
+    I64Array(Vec<i64>),
+    F64Array(Vec<f64>),
 //    Key(Key<'a>),
 }
 
+impl<'a> Value<'a> {
+    /// Name of the variant, as used in `ConversionError`.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            Value::Null => "null",
+            Value::Str(_) => "string",
+            Value::I32(_) => "i32",
+            Value::I64(_) => "i64",
+            Value::F32(_) => "f32",
+            Value::F64(_) => "f64",
+            Value::Bool(_) => "bool",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Uuid(_) => "uuid",
+            Value::Timestamp(_) => "timestamp",
+            Value::Decimal(_) => "decimal",
+            Value::Duration(_) => "duration",
+            Value::I64Array(_) => "i64 array",
+            Value::F64Array(_) => "f64 array",
+        }
+    }
+
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::Null => Value::Null,
+            Value::Str(value) => Value::Str(Cow::Owned(value.into_owned())),
+            Value::I32(value) => Value::I32(value),
+            Value::I64(value) => Value::I64(value),
+            Value::F32(value) => Value::F32(value),
+            Value::F64(value) => Value::F64(value),
+            Value::Bool(value) => Value::Bool(value),
+            Value::Bytes(value) => Value::Bytes(Cow::Owned(value.into_owned())),
+            Value::List(value) => Value::List(value.into_owned()),
+            Value::Map(value) => Value::Map(value.into_owned()),
+            Value::Uuid(value) => Value::Uuid(value),
+            Value::Timestamp(value) => Value::Timestamp(value),
+            Value::Decimal(value) => Value::Decimal(value),
+            Value::Duration(value) => Value::Duration(value),
+            Value::I64Array(value) => Value::I64Array(value),
+            Value::F64Array(value) => Value::F64Array(value),
+        }
+    }
+}
+
 impl<'a> From<&'a str> for Value<'a> {
     fn from(value: &'a str) -> Self {
         Value::Str(Cow::Borrowed(value))
@@ -315,6 +658,16 @@ impl<'a> From<bool> for Value<'a> {
     }
 }
 
+impl<'a> From<Vec<u8>> for Value<'a> {
+    fn from(value: Vec<u8>) -> Self {
+        Value::Bytes(Cow::Owned(value))
+    }
+}
+
+impl<'a> From<Uuid> for Value<'a> {
+    fn from(value: Uuid) -> Self { Value::Uuid(value) }
+}
+
 impl<'a> From<Map<'a>> for Value<'a> {
     fn from(value: Map<'a>) -> Self { Value::Map(value) }
 }
@@ -327,8 +680,117 @@ impl<'a> From<Timestamp> for Value<'a> {
     fn from(value: Timestamp) -> Self { Value::Timestamp(value) }
 }
 
+impl<'a> From<Decimal> for Value<'a> {
+    fn from(value: Decimal) -> Self { Value::Decimal(value) }
+}
+
+impl<'a> From<Duration> for Value<'a> {
+    fn from(value: Duration) -> Self { Value::Duration(value) }
+}
+
+impl<'a> From<Vec<i64>> for Value<'a> {
+    fn from(value: Vec<i64>) -> Self { Value::I64Array(value) }
+}
+
+impl<'a> From<Vec<f64>> for Value<'a> {
+    fn from(value: Vec<f64>) -> Self { Value::F64Array(value) }
+}
+
 pub type Timestamp = DateTime<UTC>;
 
+/// An exact decimal number, `mantissa * 10^-scale`.
+///
+/// Decimals compare by representation, so `1.0` and `1.00` are not equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u8,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i128, scale: u8) -> Decimal {
+        Decimal { mantissa, scale }
+    }
+
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    /// The number of digits after the decimal point.
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = self.mantissa.unsigned_abs().to_string();
+        let scale = self.scale as usize;
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        if scale == 0 {
+            write!(f, "{}{}", sign, digits)
+        } else if digits.len() > scale {
+            let (whole, fraction) = digits.split_at(digits.len() - scale);
+            write!(f, "{}{}.{}", sign, whole, fraction)
+        } else {
+            write!(f, "{}0.{}{}", sign, "0".repeat(scale - digits.len()), digits)
+        }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// Parses an optionally signed number with an optional fraction, such as `-12.340`.  The
+    /// scale is the number of digits after the point.
+    fn from_str(value: &str) -> Result<Decimal, ParseDecimalError> {
+        let (negative, unsigned) = match value.as_bytes().first() {
+            Some(b'-') => (true, &value[1..]),
+            Some(b'+') => (false, &value[1..]),
+            _ => (false, value),
+        };
+        let (whole, fraction) = match unsigned.find('.') {
+            Some(point) => (&unsigned[..point], &unsigned[point + 1..]),
+            None => (unsigned, ""),
+        };
+        if whole.is_empty() || fraction.len() > u8::MAX as usize {
+            return Err(ParseDecimalError);
+        }
+        if unsigned.ends_with('.') {
+            return Err(ParseDecimalError);
+        }
+        let mut magnitude: u128 = 0;
+        for digit in whole.bytes().chain(fraction.bytes()) {
+            if !digit.is_ascii_digit() {
+                return Err(ParseDecimalError);
+            }
+            magnitude = magnitude
+                .checked_mul(10)
+                .and_then(|magnitude| magnitude.checked_add(u128::from(digit - b'0')))
+                .ok_or(ParseDecimalError)?;
+        }
+        // The magnitude of `i128::MIN` does not fit in an `i128`, so apply the sign unsigned.
+        let mantissa = if negative {
+            0i128.checked_sub_unsigned(magnitude)
+        } else {
+            0i128.checked_add_unsigned(magnitude)
+        };
+        Ok(Decimal::new(mantissa.ok_or(ParseDecimalError)?, fraction.len() as u8))
+    }
+}
+
+/// Error returned when a string is not a valid `Decimal`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseDecimalError;
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Invalid decimal number")
+    }
+}
+
+impl Error for ParseDecimalError {}
+
 
 #[cfg(test)]
 mod tests {
@@ -365,4 +827,191 @@ mod tests {
             println!("{:?}: {:?}", key, value);
         }
     }
+
+    #[test]
+    fn into_owned() {
+        let message = MessageBuilder::new()
+            .with_header("key", "value")
+            .with_header(7, ListBuilder::new().push(vec![1u8, 2]).build())
+            .with_header("map", MapBuilder::new().insert("nested", "string").build())
+            .with_body("body")
+            .build();
+        let bytes = ::codec::message_codec::encode_message(&message).to_vec();
+        let borrowed = ::codec::message_codec::decode_message_borrowed(&bytes).unwrap();
+        let owned = borrowed.into_owned();
+        drop(bytes);
+        assert_eq!(owned, message);
+
+        let sent: OwnedMessage = ::std::thread::spawn(move || owned).join().unwrap();
+        for (key, value) in sent.headers().iter() {
+            if let Key::Str(Cow::Borrowed(_)) = *key {
+                panic!("Expected owned key, got {:?}", key);
+            }
+            if let Value::Str(Cow::Borrowed(_)) = *value {
+                panic!("Expected owned value, got {:?}", value);
+            }
+        }
+        assert_eq!(sent.body(), Some(&Value::Str(Cow::Owned("body".to_string()))));
+    }
+
+    #[test]
+    fn list_mutation() {
+        let mut list: List = vec![1, 2, 3].into_iter().collect();
+        list.insert(0, "first");
+        list.extend(vec![4, 5]);
+        assert_eq!(list.len(), 6);
+        assert_eq!(list.remove(1), Value::from(1));
+        assert_eq!(list.pop(), Some(Value::from(5)));
+        list.retain(|value| *value != Value::from(3));
+        list[0] = Value::from("replaced");
+        for value in &mut list {
+            if let Value::I32(ref mut number) = *value {
+                *number *= 10;
+            }
+        }
+        let values: Vec<Value> = list.clone().into_iter().collect();
+        assert_eq!(values, vec![Value::from("replaced"), Value::from(20), Value::from(40)]);
+        assert_eq!((&list).into_iter().count(), 3);
+
+        list.truncate(1);
+        assert_eq!(list.get(0), Some(&Value::from("replaced")));
+        assert_eq!(list.get(1), None);
+        list.clear();
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn map_mutation() {
+        let mut map: Map = vec![("a", 1), ("b", 2), ("c", 3)].into_iter().collect();
+        assert_eq!(map.insert("a", 10), Some(Value::from(1)));
+        assert_eq!(map.insert("d", 4), None);
+        assert_eq!(map.remove(&Key::from("b")), Some(Value::from(2)));
+        assert_eq!(map.remove(&Key::from("b")), None);
+
+        *map.entry("e").or_insert(Value::from(0)) = Value::from(5);
+        if let Value::I32(ref mut c) = *map.entry("c").or_insert(Value::Null) {
+            *c += 30;
+        }
+        *map.get_mut(&Key::from("d")).unwrap() = Value::from(40);
+        map.extend(vec![(7, "seven")]);
+
+        let keys: Vec<Key> = map.keys().cloned().collect();
+        assert_eq!(keys, vec![
+            Key::from("c"),
+            Key::from("a"),
+            Key::from("d"),
+            Key::from("e"),
+            Key::from(7),
+        ]);
+        let values: Vec<&Value> = map.values().collect();
+        assert_eq!(values[..2], [&Value::from(33), &Value::from(10)]);
+
+        map.retain(|key, value| {
+            if let Value::I32(ref mut number) = *value {
+                *number += 1;
+            }
+            *key != Key::from("c")
+        });
+        for (_, value) in &mut map {
+            if let Value::Str(_) = *value {
+                *value = Value::Null;
+            }
+        }
+        let entries: Vec<(Key, Value)> = map.clone().into_iter().collect();
+        assert_eq!(entries, vec![
+            (Key::from("a"), Value::from(11)),
+            (Key::from("d"), Value::from(41)),
+            (Key::from("e"), Value::from(6)),
+            (Key::from(7), Value::Null),
+        ]);
+        assert_eq!((&map).into_iter().count(), 4);
+
+        map.clear();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn rewrite_body_in_place() {
+        let items = ListBuilder::new().push(1).build();
+        let mut message = MessageBuilder::new()
+            .with_body(MapBuilder::new().insert("items", items).build())
+            .build();
+        if let Some(&mut Value::Map(ref mut body)) = message.body_mut() {
+            body.insert("count", 1);
+        }
+        assert_eq!(message.body().unwrap().pointer("/count"), Some(&Value::from(1)));
+    }
+
+    #[test]
+    fn decimal_strings() {
+        for &(text, mantissa, scale) in [
+            ("0", 0, 0),
+            ("12.34", 1234, 2),
+            ("-12.340", -12340, 3),
+            ("0.005", 5, 3),
+            ("-0.5", -5, 1),
+        ].iter() {
+            let decimal = Decimal::new(mantissa, scale);
+            assert_eq!(text.parse(), Ok(decimal));
+            assert_eq!(decimal.to_string(), text);
+        }
+        assert_eq!("+1.5".parse(), Ok(Decimal::new(15, 1)));
+        for &decimal in [
+            Decimal::new(i128::MIN, 0),
+            Decimal::new(i128::MIN, 255),
+            Decimal::new(i128::MAX, 0),
+        ].iter() {
+            assert_eq!(decimal.to_string().parse(), Ok(decimal));
+        }
+        assert_eq!(
+            Decimal::new(i128::MIN, 0).to_string(),
+            "-170141183460469231731687303715884105728"
+        );
+        for &text in [
+            "170141183460469231731687303715884105728",
+            "-170141183460469231731687303715884105729",
+        ].iter() {
+            assert_eq!(text.parse::<Decimal>(), Err(ParseDecimalError), "{}", text);
+        }
+        let overflow = "9".repeat(41);
+        for &text in ["", "-", ".5", "1.", "1.2.3", "1e5", &overflow].iter() {
+            assert_eq!(text.parse::<Decimal>(), Err(ParseDecimalError), "{}", text);
+        }
+    }
+
+    #[test]
+    fn typed_value_conversions() {
+        assert_eq!(Value::from(Decimal::new(1, 0)), Value::Decimal(Decimal::new(1, 0)));
+        assert_eq!(Value::from(Duration::new(1, 5)), Value::Duration(Duration::new(1, 5)));
+        assert_eq!(Value::from(vec![1i64, 2]), Value::I64Array(vec![1, 2]));
+        assert_eq!(Value::from(vec![1.5f64]), Value::F64Array(vec![1.5]));
+    }
+
+    #[test]
+    fn content_hash_ignores_insertion_order() {
+        let nan = f64::NAN;
+        let first = MessageBuilder::new()
+            .with_header("b", 2)
+            .with_header(1, "one")
+            .with_header("a", MapBuilder::new().insert("y", 0.0).insert("x", nan).build())
+            .with_body("body")
+            .build();
+        let second = MessageBuilder::new()
+            .with_header("a", MapBuilder::new().insert("x", -nan).insert("y", -0.0).build())
+            .with_header(1, "one")
+            .with_header("b", 2)
+            .with_body("body")
+            .build();
+        assert_eq!(first.content_hash(), second.content_hash());
+
+        let different = MessageBuilder::new().with_header("b", 3).build();
+        assert_ne!(first.content_hash(), different.content_hash());
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        let message = MessageBuilder::new().with_header("key", "value").with_body(1).build();
+        let hex: String = message.content_hash().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "d690573c16ce15c8548608a56a10fbc67222eb76566896ecf0ba37cf28169455");
+    }
 }
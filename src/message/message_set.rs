@@ -1,16 +1,26 @@
-use message::Message;
-
-pub struct MessageSet {
+use message::message::Message;
+
+/// A batch of messages with consecutive offsets, starting at `index`.
+///
+/// A message set is the unit of produce requests, segment appends and fetch responses, and is
+/// encoded as a whole by `codec::batch::encode_message_set`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSet<'a> {
     index: u64,
-    messages: Vec<Message>,
+    messages: Vec<Message<'a>>,
 }
 
-impl MessageSet {
-//    pub fn index(&self) -> u64 {
-//        self.index
-//    }
+impl<'a> MessageSet<'a> {
+    pub fn new(index: u64, messages: Vec<Message<'a>>) -> MessageSet<'a> {
+        MessageSet { index, messages }
+    }
+
+    /// Offset of the first message in the set.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
 
-    pub fn iter(&self) -> MessageSetIter {
+    pub fn iter<'s>(&'s self) -> MessageSetIter<'s, 'a> {
         MessageSetIter {
             counter: 0,
             index: self.index,
@@ -22,19 +32,31 @@ impl MessageSet {
         self.messages.len()
     }
 
-    pub fn starting_at(index: u64) -> MessageSetBuilder {
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn messages(&self) -> &[Message<'a>] {
+        &self.messages
+    }
+
+    pub fn into_messages(self) -> Vec<Message<'a>> {
+        self.messages
+    }
+
+    pub fn starting_at(index: u64) -> MessageSetBuilder<'a> {
         MessageSetBuilder::starting_at(index)
     }
 }
 
-pub struct MessageSetIter<'a> {
+pub struct MessageSetIter<'s, 'a: 's> {
     counter: usize,
     index: u64,
-    messages: &'a Vec<Message>,
+    messages: &'s Vec<Message<'a>>,
 }
 
-impl<'a> Iterator for MessageSetIter<'a> {
-    type Item = (u64, &'a Message);
+impl<'s, 'a> Iterator for MessageSetIter<'s, 'a> {
+    type Item = (u64, &'s Message<'a>);
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.messages.get(self.counter) {
@@ -49,25 +71,25 @@ impl<'a> Iterator for MessageSetIter<'a> {
     }
 }
 
-pub struct MessageSetBuilder {
+pub struct MessageSetBuilder<'a> {
     index: u64,
-    messages: Vec<Message>,
+    messages: Vec<Message<'a>>,
 }
 
-impl MessageSetBuilder {
-    fn starting_at(index: u64) -> MessageSetBuilder {
+impl<'a> MessageSetBuilder<'a> {
+    pub fn starting_at(index: u64) -> MessageSetBuilder<'a> {
         MessageSetBuilder {
             index,
             messages: Vec::new(),
         }
     }
 
-    fn append(mut self, message: Message) -> MessageSetBuilder {
+    pub fn append(mut self, message: Message<'a>) -> MessageSetBuilder<'a> {
         self.messages.push(message);
         self
     }
 
-    fn build(self) -> MessageSet {
+    pub fn build(self) -> MessageSet<'a> {
         MessageSet {
             index: self.index,
             messages: self.messages,
@@ -78,26 +100,29 @@ impl MessageSetBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use message::message::MessageBuilder;
 
     #[test]
     fn message_set_add() {
         let message_set = MessageSet::starting_at(50)
-            .append(Message::with_body("Hello").build())
-            .append(Message::with_body("World").build())
+            .append(MessageBuilder::new().with_body("Hello").build())
+            .append(MessageBuilder::new().with_body("World").build())
             .build();
 
         let mut builder2 = MessageSet::starting_at(60);
 
         for i in 0..10 {
-            builder2 = builder2.append(Message::with_property("iter", i).build());
+            builder2 = builder2.append(MessageBuilder::new().with_header("iter", i).build());
         }
 
         let message_set2 = builder2.build();
 
         assert_eq!(message_set.len(), 2);
+        assert_eq!(message_set2.len(), 10);
 
-        for (index, message) in message_set.iter().chain(message_set2.iter()) {
-            eprintln!("index = {:?}, message: {:?}", index, message);
-        }
+        let indexes: Vec<u64> = message_set.iter().chain(message_set2.iter())
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(indexes, vec![50, 51, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69]);
     }
 }
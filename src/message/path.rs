@@ -0,0 +1,299 @@
+//! Paths into nested values.
+//!
+//! `Value::pointer` follows a path written in the style of JSON Pointer (RFC 6901): each
+//! segment starts with `/`, and `~1` and `~0` stand for `/` and `~` inside a segment.  A
+//! segment selects a list element by its index, or a map entry by its key.  Map keys are
+//! matched as `Key::Str` first and, if the map has no such key and the segment is a number, as
+//! `Key::I32`, so `/7` reaches the entry with key `7` whichever form it was written with.
+//!
+//! A `Query` is a path that may also contain `*` segments, which match every element of a list
+//! or every value of a map.  A query selects every value its path leads to, in order.
+
+use message::message::{Key, Value};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+impl<'a> Value<'a> {
+    /// The value at `pointer`, or `None` if the pointer is malformed or leads nowhere.  The
+    /// empty pointer refers to this value.
+    pub fn pointer(&self, pointer: &str) -> Option<&Value<'a>> {
+        let mut target = self;
+        for segment in segments(pointer)? {
+            target = child(target, &segment?)?;
+        }
+        Some(target)
+    }
+
+    /// Mutable counterpart of `pointer`.
+    pub fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value<'a>> {
+        let mut target = self;
+        for segment in segments(pointer)? {
+            target = child_mut(target, &segment?)?;
+        }
+        Some(target)
+    }
+
+    /// Every value selected by `query`.
+    pub fn query<'v>(&'v self, query: &Query) -> Vec<&'v Value<'a>> {
+        let mut selected = Vec::new();
+        select(self, &query.segments, &mut selected);
+        selected
+    }
+}
+
+/// A path with optional `*` wildcards, parsed from a string such as `/order/items/*/sku`.
+///
+/// A key named `*` cannot be matched by a query; use `Value::pointer` to reach it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Name(String),
+    Wildcard,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query, ParseQueryError> {
+        query.parse()
+    }
+
+    /// Whether `query` selects at least one value from `value`.
+    pub fn matches(&self, value: &Value) -> bool {
+        !value.query(self).is_empty()
+    }
+}
+
+impl FromStr for Query {
+    type Err = ParseQueryError;
+
+    fn from_str(query: &str) -> Result<Query, ParseQueryError> {
+        let segments = segments(query)
+            .ok_or(ParseQueryError)?
+            .map(|segment| match segment {
+                Some(Cow::Borrowed("*")) => Ok(Segment::Wildcard),
+                Some(name) => Ok(Segment::Name(name.into_owned())),
+                None => Err(ParseQueryError),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Query { segments })
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for segment in &self.segments {
+            match *segment {
+                Segment::Name(ref name) => {
+                    write!(f, "/{}", name.replace('~', "~0").replace('/', "~1"))?
+                }
+                Segment::Wildcard => f.write_str("/*")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when a string is not a valid `Query`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseQueryError;
+
+impl fmt::Display for ParseQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Invalid query")
+    }
+}
+
+impl Error for ParseQueryError {}
+
+/// The unescaped segments of `path`, or `None` if it does not start with `/`.  A segment is
+/// `None` if it holds an invalid escape.
+fn segments<'p>(path: &'p str) -> Option<impl Iterator<Item = Option<Cow<'p, str>>>> {
+    let rest = if path.is_empty() {
+        None
+    } else {
+        Some(path.strip_prefix('/')?)
+    };
+    Some(rest.into_iter().flat_map(|rest| rest.split('/')).map(unescape))
+}
+
+fn unescape<'s>(segment: &'s str) -> Option<Cow<'s, str>> {
+    if !segment.contains('~') {
+        return Some(Cow::Borrowed(segment));
+    }
+    let mut unescaped = String::with_capacity(segment.len());
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => match chars.next() {
+                Some('0') => unescaped.push('~'),
+                Some('1') => unescaped.push('/'),
+                _ => return None,
+            },
+            c => unescaped.push(c),
+        }
+    }
+    Some(Cow::Owned(unescaped))
+}
+
+/// A list index is a number without a sign or leading zeros.
+fn index(segment: &str) -> Option<usize> {
+    let digits = segment.bytes().all(|b| b.is_ascii_digit());
+    if !digits || segment.is_empty() || (segment.len() > 1 && segment.starts_with('0')) {
+        return None;
+    }
+    segment.parse().ok()
+}
+
+/// The key of the entry `segment` selects in a map whose keys are tested with `contains`.
+fn key<'a, F: Fn(&Key<'a>) -> bool>(segment: &str, contains: F) -> Option<Key<'a>> {
+    let key = Key::from(segment.to_string());
+    if contains(&key) {
+        return Some(key);
+    }
+    let key = Key::from(segment.parse::<i32>().ok()?);
+    if contains(&key) {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+fn child<'v, 'a>(value: &'v Value<'a>, segment: &str) -> Option<&'v Value<'a>> {
+    match *value {
+        Value::List(ref list) => list.get(index(segment)?),
+        Value::Map(ref map) => map.get(&key(segment, |key| map.contains_key(key))?),
+        _ => None,
+    }
+}
+
+fn child_mut<'v, 'a>(value: &'v mut Value<'a>, segment: &str) -> Option<&'v mut Value<'a>> {
+    match *value {
+        Value::List(ref mut list) => list.get_mut(index(segment)?),
+        Value::Map(ref mut map) => {
+            let key = key(segment, |key| map.contains_key(key))?;
+            map.get_mut(&key)
+        }
+        _ => None,
+    }
+}
+
+fn select<'v, 'a>(value: &'v Value<'a>, segments: &[Segment], selected: &mut Vec<&'v Value<'a>>) {
+    match segments.split_first() {
+        None => selected.push(value),
+        Some((Segment::Name(name), rest)) => {
+            if let Some(child) = child(value, name) {
+                select(child, rest, selected);
+            }
+        }
+        Some((Segment::Wildcard, rest)) => match *value {
+            Value::List(ref list) => {
+                for element in list.iter() {
+                    select(element, rest, selected);
+                }
+            }
+            Value::Map(ref map) => {
+                for (_, entry) in map.iter() {
+                    select(entry, rest, selected);
+                }
+            }
+            _ => {}
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use message::message::{ListBuilder, MapBuilder};
+
+    fn order() -> Value<'static> {
+        let item = |sku: &'static str, quantity: i32| {
+            MapBuilder::new().insert("sku", sku).insert("quantity", quantity).build()
+        };
+        let items = ListBuilder::new().push(item("A1", 2)).push(item("B2", 1)).build();
+        MapBuilder::new()
+            .insert(
+                "order",
+                MapBuilder::new()
+                    .insert("items", items)
+                    .insert(7, "numeric key")
+                    .insert("a/b", "slash")
+                    .insert("m~n", "tilde")
+                    .build(),
+            )
+            .build()
+            .into()
+    }
+
+    #[test]
+    fn pointer() {
+        let order = order();
+        assert_eq!(order.pointer(""), Some(&order));
+        assert_eq!(order.pointer("/order/items/0/sku"), Some(&Value::from("A1")));
+        assert_eq!(order.pointer("/order/items/1/quantity"), Some(&Value::from(1)));
+        assert_eq!(order.pointer("/order/7"), Some(&Value::from("numeric key")));
+        assert_eq!(order.pointer("/order/a~1b"), Some(&Value::from("slash")));
+        assert_eq!(order.pointer("/order/m~0n"), Some(&Value::from("tilde")));
+
+        for &missing in [
+            "order",
+            "/missing",
+            "/order/items/2",
+            "/order/items/01",
+            "/order/items/+1",
+            "/order/items/-1",
+            "/order/items/0/sku/0",
+            "/order/m~2n",
+            "/order/8",
+        ].iter() {
+            assert_eq!(order.pointer(missing), None, "{}", missing);
+        }
+    }
+
+    #[test]
+    fn pointer_prefers_string_keys() {
+        let map: Value = MapBuilder::new().insert(1, "number").insert("1", "string").build().into();
+        assert_eq!(map.pointer("/1"), Some(&Value::from("string")));
+    }
+
+    #[test]
+    fn pointer_mut() {
+        let mut order = order();
+        *order.pointer_mut("/order/items/0/quantity").unwrap() = Value::from(5);
+        *order.pointer_mut("/order/7").unwrap() = Value::Null;
+        assert_eq!(order.pointer("/order/items/0/quantity"), Some(&Value::from(5)));
+        assert_eq!(order.pointer("/order/7"), Some(&Value::Null));
+        assert_eq!(order.pointer_mut("/order/items/9"), None);
+    }
+
+    #[test]
+    fn query() {
+        let order = order();
+        let skus = Query::parse("/order/items/*/sku").unwrap();
+        assert_eq!(order.query(&skus), vec![&Value::from("A1"), &Value::from("B2")]);
+
+        let quantities = Query::parse("/*/items/*/quantity").unwrap();
+        assert_eq!(order.query(&quantities), vec![&Value::from(2), &Value::from(1)]);
+
+        let all = Query::parse("/order/*").unwrap();
+        assert_eq!(order.query(&all).len(), 4);
+        assert!(skus.matches(&order));
+        assert!(!Query::parse("/order/items/*/price").unwrap().matches(&order));
+        assert_eq!(order.query(&Query::parse("").unwrap()), vec![&order]);
+    }
+
+    #[test]
+    fn parse_query() {
+        let query = Query::parse("/a~1b/*/m~0n/0").unwrap();
+        assert_eq!(query.to_string(), "/a~1b/*/m~0n/0");
+        assert_eq!("/x".parse::<Query>(), Query::parse("/x"));
+        for &invalid in ["x", "*/a", "/a~", "/a~2"].iter() {
+            assert_eq!(Query::parse(invalid), Err(ParseQueryError), "{}", invalid);
+        }
+    }
+}
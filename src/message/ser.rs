@@ -0,0 +1,812 @@
+//! `Serialize` for the message model, and `to_value` for turning any `Serialize` type into a
+//! `Value`.
+//!
+//! Values serialize to the closest serde data type: `Null` is a unit, `List` a sequence, `Map`
+//! a map keyed by strings and `i32`s, and so on.  `Uuid` and `Timestamp` serialize as a
+//! hyphenated UUID string and an RFC 3339 string, `Decimal` as its decimal string, `Duration` as
+//! a `(seconds, nanoseconds)` tuple and the typed arrays as sequences.  Timestamps outside the
+//! years 0000 to 9999, which RFC 3339 cannot represent, serialize as a `(seconds, nanoseconds)`
+//! tuple since the Unix epoch, as in `codec::json`.  Through other formats they come back as
+//! `Value::Str` and `Value::List`, `I64` values that fit in 32 bits come back as `I32`, and
+//! formats with string-only map keys such as JSON turn `i32` keys into strings; use
+//! `codec::json` when a lossless textual form is needed.
+
+use chrono::{DateTime, Datelike, TimeZone, UTC};
+use message::de::from_value;
+use message::error::Error;
+use message::message::{Key, List, Map, Message, Timestamp, Value};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json;
+use std::borrow::Cow;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Newtype struct names `Value` uses for the variants serde has no type for, so that `to_value`
+/// can restore the original variant.  Other serializers ignore the name and see the inner value.
+pub(crate) const UUID_TOKEN: &str = "$hydramq::Uuid";
+pub(crate) const TIMESTAMP_TOKEN: &str = "$hydramq::Timestamp";
+pub(crate) const DECIMAL_TOKEN: &str = "$hydramq::Decimal";
+pub(crate) const DURATION_TOKEN: &str = "$hydramq::Duration";
+pub(crate) const I64_ARRAY_TOKEN: &str = "$hydramq::I64Array";
+pub(crate) const F64_ARRAY_TOKEN: &str = "$hydramq::F64Array";
+
+/// Converts any `Serialize` type into a `Value`.
+///
+/// Structs and maps become `Value::Map`, sequences and tuples `Value::List`, unit enum
+/// variants their name as a `Value::Str` and other enum variants a single entry map from the
+/// variant name to its contents.  Map keys must be strings, chars or integers that fit in an
+/// `i32`.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value<'static>, Error> {
+    value.serialize(ValueSerializer)
+}
+
+/// The serde form of a `Timestamp`: an RFC 3339 string when it can represent the timestamp.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum TimestampRepr {
+    Rfc3339(String),
+    Parts(i64, u32),
+}
+
+impl TimestampRepr {
+    pub(crate) fn new(timestamp: Timestamp) -> TimestampRepr {
+        if (0..=9999).contains(&timestamp.year()) {
+            TimestampRepr::Rfc3339(timestamp.to_rfc3339())
+        } else {
+            TimestampRepr::Parts(timestamp.timestamp(), timestamp.timestamp_subsec_nanos())
+        }
+    }
+
+    pub(crate) fn timestamp(&self) -> Option<Timestamp> {
+        match *self {
+            TimestampRepr::Rfc3339(ref value) => DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|value| value.with_timezone(&UTC)),
+            TimestampRepr::Parts(seconds, nanos) => UTC.timestamp_opt(seconds, nanos).single(),
+        }
+    }
+}
+
+impl fmt::Display for TimestampRepr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimestampRepr::Rfc3339(ref value) => write!(f, "'{}'", value),
+            TimestampRepr::Parts(seconds, nanos) => write!(f, "[{}, {}]", seconds, nanos),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MessageRef<'m, 'a: 'm> {
+    timestamp: Option<TimestampRepr>,
+    expiration: Option<TimestampRepr>,
+    correlation_id: Option<String>,
+    headers: &'m Map<'a>,
+    body: Option<&'m Value<'a>>,
+}
+
+impl<'a> Serialize for Message<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MessageRef {
+            timestamp: self.timestamp().map(TimestampRepr::new),
+            expiration: self.expiration().map(TimestampRepr::new),
+            correlation_id: self.correlation_id().map(|value| value.hyphenated().to_string()),
+            headers: self.headers(),
+            body: self.body(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'a> Serialize for Key<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Key::Str(ref key) => serializer.serialize_str(key),
+            Key::I32(key) => serializer.serialize_i32(key),
+        }
+    }
+}
+
+impl<'a> Serialize for Value<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Str(ref value) => serializer.serialize_str(value),
+            Value::I32(value) => serializer.serialize_i32(value),
+            Value::I64(value) => serializer.serialize_i64(value),
+            Value::F32(value) => serializer.serialize_f32(value),
+            Value::F64(value) => serializer.serialize_f64(value),
+            Value::Bool(value) => serializer.serialize_bool(value),
+            Value::Bytes(ref value) => serializer.serialize_bytes(value),
+            Value::List(ref value) => value.serialize(serializer),
+            Value::Map(ref value) => value.serialize(serializer),
+            Value::Uuid(value) => {
+                serializer.serialize_newtype_struct(UUID_TOKEN, &value.hyphenated().to_string())
+            }
+            Value::Timestamp(value) => {
+                serializer.serialize_newtype_struct(TIMESTAMP_TOKEN, &TimestampRepr::new(value))
+            }
+            Value::Decimal(value) => {
+                serializer.serialize_newtype_struct(DECIMAL_TOKEN, &value.to_string())
+            }
+            Value::Duration(value) => {
+                let parts = (value.as_secs(), value.subsec_nanos());
+                serializer.serialize_newtype_struct(DURATION_TOKEN, &parts)
+            }
+            Value::I64Array(ref values) => {
+                serializer.serialize_newtype_struct(I64_ARRAY_TOKEN, values)
+            }
+            Value::F64Array(ref values) => {
+                serializer.serialize_newtype_struct(F64_ARRAY_TOKEN, values)
+            }
+        }
+    }
+}
+
+impl<'a> Serialize for List<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for value in self.iter() {
+            seq.serialize_element(value)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'a> Serialize for Map<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+struct ValueSerializer;
+
+impl Serializer for ValueSerializer {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeValueMap;
+    type SerializeStruct = SerializeValueMap;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn serialize_bool(self, value: bool) -> Result<Value<'static>, Error> {
+        Ok(Value::Bool(value))
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Value<'static>, Error> {
+        Ok(Value::I32(i32::from(value)))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Value<'static>, Error> {
+        Ok(Value::I32(i32::from(value)))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Value<'static>, Error> {
+        Ok(Value::I32(value))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Value<'static>, Error> {
+        Ok(Value::I64(value))
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Value<'static>, Error> {
+        Ok(Value::I32(i32::from(value)))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Value<'static>, Error> {
+        Ok(Value::I32(i32::from(value)))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Value<'static>, Error> {
+        if value <= i32::MAX as u32 {
+            Ok(Value::I32(value as i32))
+        } else {
+            Ok(Value::I64(i64::from(value)))
+        }
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Value<'static>, Error> {
+        if value <= i64::MAX as u64 {
+            Ok(Value::I64(value as i64))
+        } else {
+            Err(Error::new(format!("Integer {} is out of range for I64", value)))
+        }
+    }
+
+    fn serialize_f32(self, value: f32) -> Result<Value<'static>, Error> {
+        Ok(Value::F32(value))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<Value<'static>, Error> {
+        Ok(Value::F64(value))
+    }
+
+    fn serialize_char(self, value: char) -> Result<Value<'static>, Error> {
+        Ok(Value::Str(Cow::Owned(value.to_string())))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Value<'static>, Error> {
+        Ok(Value::Str(Cow::Owned(value.to_owned())))
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Value<'static>, Error> {
+        Ok(Value::Bytes(Cow::Owned(value.to_owned())))
+    }
+
+    fn serialize_none(self) -> Result<Value<'static>, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value<'static>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value<'static>, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value<'static>, Error> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value<'static>, Error> {
+        Ok(Value::Str(Cow::Borrowed(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value<'static>, Error> {
+        if name == DURATION_TOKEN {
+            // The seconds are a `u64`, which a `Value` cannot hold, so read the parts as JSON.
+            return serde_json::to_value(value)
+                .and_then(serde_json::from_value::<(u64, u32)>)
+                .ok()
+                .filter(|&(_, nanos)| nanos < 1_000_000_000)
+                .map(|(seconds, nanos)| Value::Duration(Duration::new(seconds, nanos)))
+                .ok_or_else(|| Error::new("Invalid duration"));
+        }
+        let value = value.serialize(self)?;
+        match (name, value) {
+            (UUID_TOKEN, Value::Str(ref value)) => Uuid::parse_str(value)
+                .map(Value::Uuid)
+                .map_err(|_| Error::new(format!("Invalid UUID '{}'", value))),
+            (TIMESTAMP_TOKEN, ref value) => {
+                let repr: TimestampRepr = from_value(value)?;
+                repr.timestamp()
+                    .map(Value::Timestamp)
+                    .ok_or_else(|| Error::new(format!("Invalid timestamp {}", repr)))
+            }
+            (DECIMAL_TOKEN, Value::Str(ref value)) => value
+                .parse()
+                .map(Value::Decimal)
+                .map_err(|_| Error::new(format!("Invalid decimal '{}'", value))),
+            (I64_ARRAY_TOKEN, Value::List(ref list)) => list
+                .iter()
+                .map(|value| match *value {
+                    Value::I64(value) => Ok(value),
+                    _ => Err(Error::new("Expected an I64 array element")),
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::I64Array),
+            (F64_ARRAY_TOKEN, Value::List(ref list)) => list
+                .iter()
+                .map(|value| match *value {
+                    Value::F64(value) => Ok(value),
+                    _ => Err(Error::new("Expected an F64 array element")),
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::F64Array),
+            (_, value) => Ok(value),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value<'static>, Error> {
+        let mut map = Map::new();
+        map.insert(variant, value.serialize(self)?);
+        Ok(Value::Map(map))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SerializeList, Error> {
+        Ok(SerializeList { list: List::new() })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeList, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeTupleVariant, Error> {
+        Ok(SerializeTupleVariant {
+            variant,
+            list: List::new(),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeValueMap, Error> {
+        Ok(SerializeValueMap {
+            map: Map::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeValueMap, Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeStructVariant, Error> {
+        Ok(SerializeStructVariant {
+            variant,
+            map: Map::new(),
+        })
+    }
+}
+
+struct SerializeList {
+    list: List<'static>,
+}
+
+impl SerializeSeq for SerializeList {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.list.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        Ok(Value::List(self.list))
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct SerializeTupleVariant {
+    variant: &'static str,
+    list: List<'static>,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.list.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        let mut map = Map::new();
+        map.insert(self.variant, self.list);
+        Ok(Value::Map(map))
+    }
+}
+
+struct SerializeValueMap {
+    map: Map<'static>,
+    key: Option<Key<'static>>,
+}
+
+impl SerializeMap for SerializeValueMap {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::new("serialize_value called before serialize_key"))?;
+        self.map.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        Ok(Value::Map(self.map))
+    }
+}
+
+impl ser::SerializeStruct for SerializeValueMap {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.map.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        SerializeMap::end(self)
+    }
+}
+
+struct SerializeStructVariant {
+    variant: &'static str,
+    map: Map<'static>,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = Value<'static>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.map.insert(key, value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value<'static>, Error> {
+        let mut map = Map::new();
+        map.insert(self.variant, self.map);
+        Ok(Value::Map(map))
+    }
+}
+
+/// Serializes map keys, which must be strings or integers that fit in an `i32`.
+struct KeySerializer;
+
+fn key_error() -> Error {
+    Error::new("Map keys must be strings or integers that fit in an i32")
+}
+
+impl Serializer for KeySerializer {
+    type Ok = Key<'static>;
+    type Error = Error;
+
+    type SerializeSeq = ser::Impossible<Key<'static>, Error>;
+    type SerializeTuple = ser::Impossible<Key<'static>, Error>;
+    type SerializeTupleStruct = ser::Impossible<Key<'static>, Error>;
+    type SerializeTupleVariant = ser::Impossible<Key<'static>, Error>;
+    type SerializeMap = ser::Impossible<Key<'static>, Error>;
+    type SerializeStruct = ser::Impossible<Key<'static>, Error>;
+    type SerializeStructVariant = ser::Impossible<Key<'static>, Error>;
+
+    fn serialize_bool(self, _value: bool) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_i8(self, value: i8) -> Result<Key<'static>, Error> {
+        Ok(Key::I32(i32::from(value)))
+    }
+
+    fn serialize_i16(self, value: i16) -> Result<Key<'static>, Error> {
+        Ok(Key::I32(i32::from(value)))
+    }
+
+    fn serialize_i32(self, value: i32) -> Result<Key<'static>, Error> {
+        Ok(Key::I32(value))
+    }
+
+    fn serialize_i64(self, value: i64) -> Result<Key<'static>, Error> {
+        if value >= i64::from(i32::MIN) && value <= i64::from(i32::MAX) {
+            Ok(Key::I32(value as i32))
+        } else {
+            Err(key_error())
+        }
+    }
+
+    fn serialize_u8(self, value: u8) -> Result<Key<'static>, Error> {
+        Ok(Key::I32(i32::from(value)))
+    }
+
+    fn serialize_u16(self, value: u16) -> Result<Key<'static>, Error> {
+        Ok(Key::I32(i32::from(value)))
+    }
+
+    fn serialize_u32(self, value: u32) -> Result<Key<'static>, Error> {
+        self.serialize_i64(i64::from(value))
+    }
+
+    fn serialize_u64(self, value: u64) -> Result<Key<'static>, Error> {
+        if value <= i32::MAX as u64 {
+            Ok(Key::I32(value as i32))
+        } else {
+            Err(key_error())
+        }
+    }
+
+    fn serialize_f32(self, _value: f32) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_f64(self, _value: f64) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_char(self, value: char) -> Result<Key<'static>, Error> {
+        Ok(Key::Str(Cow::Owned(value.to_string())))
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Key<'static>, Error> {
+        Ok(Key::Str(Cow::Owned(value.to_owned())))
+    }
+
+    fn serialize_bytes(self, _value: &[u8]) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_none(self) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Key<'static>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Key<'static>, Error> {
+        Ok(Key::Str(Cow::Borrowed(variant)))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Key<'static>, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Key<'static>, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Err(key_error())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(key_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use message::message::{Decimal, ListBuilder, MapBuilder};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Order {
+        id: u64,
+        customer: String,
+        items: Vec<Item>,
+        note: Option<String>,
+        status: Status,
+    }
+
+    #[derive(Serialize)]
+    struct Item {
+        sku: &'static str,
+        quantity: u8,
+        price: f64,
+    }
+
+    #[derive(Serialize)]
+    enum Status {
+        Open,
+        Shipped { carrier: String },
+    }
+
+    #[test]
+    fn struct_to_value() {
+        let order = Order {
+            id: 42,
+            customer: "jimmie".to_owned(),
+            items: vec![Item { sku: "A-1", quantity: 2, price: 9.5 }],
+            note: None,
+            status: Status::Open,
+        };
+        let expected = MapBuilder::new()
+            .insert("id", 42i64)
+            .insert("customer", "jimmie")
+            .insert(
+                "items",
+                ListBuilder::new()
+                    .push(
+                        MapBuilder::new()
+                            .insert("sku", "A-1")
+                            .insert("quantity", 2)
+                            .insert("price", 9.5)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .insert("note", Value::Null)
+            .insert("status", "Open")
+            .build();
+        assert_eq!(to_value(&order), Ok(Value::Map(expected)));
+    }
+
+    #[test]
+    fn enum_variant_to_value() {
+        let status = Status::Shipped { carrier: "ups".to_owned() };
+        let expected = MapBuilder::new()
+            .insert("Shipped", MapBuilder::new().insert("carrier", "ups").build())
+            .build();
+        assert_eq!(to_value(&status), Ok(Value::Map(expected)));
+    }
+
+    #[test]
+    fn value_to_value_is_lossless() {
+        let value = Value::Map(
+            MapBuilder::new()
+                .insert(7, Value::Uuid(Uuid::nil()))
+                .insert("when", UTC.timestamp(1_500_000_000, 5))
+                .insert("bytes", Value::Bytes(Cow::Borrowed(&[1u8, 2][..])))
+                .insert("long", 1i64)
+                .insert("price", Decimal::new(-1999, 2))
+                .insert("timeout", Duration::new(30, 5))
+                .insert("ids", vec![1i64, -2])
+                .insert("weights", vec![0.5f64])
+                .build(),
+        );
+        assert_eq!(to_value(&value), Ok(value));
+    }
+
+    #[test]
+    fn extreme_timestamps_and_durations() {
+        for value in [
+            Value::Timestamp(UTC.timestamp(300_000_000_000, 0)),
+            Value::Timestamp(UTC.timestamp(-62_167_219_201, 999_999_999)),
+            Value::Duration(Duration::new(u64::MAX, 1)),
+        ] {
+            assert_eq!(to_value(&value), Ok(value));
+        }
+    }
+
+    #[test]
+    fn extreme_decimals() {
+        for &scale in [0, 255].iter() {
+            let value = Value::Decimal(Decimal::new(i128::MIN, scale));
+            assert_eq!(to_value(&value), Ok(value));
+        }
+    }
+
+    #[test]
+    fn unsupported_map_keys() {
+        let mut map = BTreeMap::new();
+        map.insert(true, 1);
+        assert!(to_value(&map).is_err());
+        let mut map = BTreeMap::new();
+        map.insert(u64::MAX, 1);
+        assert!(to_value(&map).is_err());
+    }
+}
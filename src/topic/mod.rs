@@ -2,12 +2,13 @@ use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
 use std::io::{self, Seek, SeekFrom, Write};
 
-use message::{Message};
+use message::message::{Message, OwnedMessage};
+use message::message_set::MessageSet;
 
 use bytes::{Buf, BufMut, BytesMut, IntoBuf};
 
-use codec::encode_message;
-use codec::decode_message;
+use codec::Codec;
+use codec::message_codec::BinaryMessageCodec;
 
 use std::cell::RefCell;
 use std::ops::Range;
@@ -16,9 +17,9 @@ pub mod segment;
 
 pub struct SegmentNumber(i32);
 
-impl Into<String> for SegmentNumber {
-    fn into(self) -> String {
-        self.0.to_string()
+impl From<SegmentNumber> for String {
+    fn from(value: SegmentNumber) -> Self {
+        value.0.to_string()
     }
 }
 
@@ -28,8 +29,12 @@ impl From<String> for SegmentNumber {
     }
 }
 
-pub struct FileSegment {
+/// A segment stored as two files in a directory: `segment.dat` holds each encoded message
+/// preceded by its length, and `segment.idx` the position of each message in `segment.dat`.
+/// Messages are encoded with `C`, the binary format by default.
+pub struct FileSegment<C = BinaryMessageCodec> {
     directory: PathBuf,
+    codec: C,
     dat: RefCell<File>,
     idx: RefCell<File>,
 }
@@ -37,13 +42,31 @@ pub struct FileSegment {
 pub trait Segment {
     fn write(&self, message: &Message);
 
-    fn read(&self, offset: u32) -> Option<Message>;
+    /// Appends the messages of a set in order, returning the offset of the first.  The
+    /// messages take the next offsets in the segment, whatever the index of the set, and are
+    /// stored one by one rather than in the batch format of `codec::batch`.
+    fn append(&self, messages: &MessageSet) -> u32;
+
+    fn read(&self, offset: u32) -> Option<OwnedMessage>;
 
     fn size(&self) -> u32;
 }
 
 impl FileSegment {
     pub fn with_directory<P>(directory: P) -> FileSegment
+    where
+        P: Into<PathBuf>,
+    {
+        FileSegment::with_codec(directory, BinaryMessageCodec::new())
+    }
+
+    pub fn with_temp_directory() -> FileSegment {
+        FileSegment::with_directory(temp_directory())
+    }
+}
+
+impl<C: Codec> FileSegment<C> {
+    pub fn with_codec<P>(directory: P, codec: C) -> FileSegment<C>
     where
         P: Into<PathBuf>,
     {
@@ -57,8 +80,8 @@ impl FileSegment {
             .append(true)
             .read(true)
             .create(true)
-            .open(dat.clone())
-            .expect(format!("Error creating {:?}", dat).as_str());
+            .open(dat)
+            .unwrap_or_else(|_| panic!("Error creating {:?}", dat));
 
         let mut idx = directory.clone();
         idx.push("segment.idx");
@@ -67,21 +90,20 @@ impl FileSegment {
             .append(true)
             .read(true)
             .create(true)
-            .open(idx.clone())
-            .expect(format!("Error creating {:?}", idx).as_str());
+            .open(idx)
+            .unwrap_or_else(|_| panic!("Error creating {:?}", idx));
         let idx = RefCell::new(idx);
         let dat = RefCell::new(dat);
         FileSegment {
             directory,
+            codec,
             dat,
             idx,
         }
     }
 
-    pub fn with_temp_directory() -> FileSegment {
-        let directory =
-            ::std::env::temp_dir().join(::uuid::Uuid::new_v4().hyphenated().to_string());
-        FileSegment::with_directory(directory)
+    pub fn codec(&self) -> &C {
+        &self.codec
     }
 
     pub fn directory(&self) -> &Path {
@@ -100,50 +122,65 @@ impl FileSegment {
         Ok(())
     }
 
-    pub fn iter(&self) -> FileSegmentIter {
+    pub fn iter<'s>(&'s self) -> FileSegmentIter<'s, C> {
         let range = Range {
             start: 0,
             end: self.size(),
         };
         FileSegmentIter {
             range,
-            segment: &self,
+            segment: self,
         }
     }
-}
 
-impl Segment for FileSegment {
-    fn write(&self, message: &Message) {
-        let mut header = BytesMut::with_capacity(4);
-        use message::MessageVisitor;
-        let calculator = ::message::BinaryFormatSizeCalculator {};
-        let mut size = 0;
-        calculator.visit_message(&message, &mut size);
-        let mut contents = BytesMut::with_capacity(size);
+    /// Writes the messages to `segment.dat` and their positions to `segment.idx`, one write
+    /// per file.
+    fn write_messages<'m, 'a: 'm, I>(&self, messages: I)
+    where
+        I: IntoIterator<Item = &'m Message<'a>>,
+    {
         let mut dat_borrow = self.dat.borrow_mut();
-        let message_start = dat_borrow.seek(SeekFrom::End(0)).unwrap();
-        encode_message(message, &mut contents);
-        header.put_u32_le(contents.len() as u32);
-        let header = header.freeze();
-        let contents = contents.freeze();
-
-        dat_borrow.write_all(header.as_ref()).unwrap();
+        let start = dat_borrow.seek(SeekFrom::End(0)).unwrap();
+        let mut contents = BytesMut::new();
+        let mut positions = BytesMut::new();
+        for message in messages {
+            let position = contents.len();
+            positions.reserve(4);
+            positions.put_u32_le(start as u32 + position as u32);
+            // The size is filled in once the message is encoded, as measuring it up front
+            // would encode a compressed message twice.
+            contents.reserve(4);
+            contents.put_u32_le(0);
+            self.codec.encode(message, &mut contents);
+            let size = (contents.len() - position - 4) as u32;
+            contents[position..position + 4].copy_from_slice(&size.to_le_bytes());
+        }
         dat_borrow.write_all(contents.as_ref()).unwrap();
         let mut idx_borrow = self.idx.borrow_mut();
         idx_borrow.seek(SeekFrom::End(0)).unwrap();
-        let mut message_start_buffer = BytesMut::with_capacity(4);
-        message_start_buffer.put_u32_le(message_start as u32);
-        idx_borrow.write_all(&mut message_start_buffer).unwrap();
+        idx_borrow.write_all(positions.as_ref()).unwrap();
+    }
+}
+
+impl<C: Codec> Segment for FileSegment<C> {
+    fn write(&self, message: &Message) {
+        self.write_messages(Some(message));
     }
 
-    fn read(&self, offset: u32) -> Option<Message> {
+    fn append(&self, messages: &MessageSet) -> u32 {
+        let offset = self.size();
+        self.write_messages(messages.messages());
+        offset
+    }
+
+    fn read(&self, offset: u32) -> Option<OwnedMessage> {
         if self.size() == 0 || offset > self.size() - 1 {
             return None;
         }
         let mut header = [0u8; 4];
         let mut idx_borrow = self.idx.borrow_mut();
         idx_borrow
-            .seek(SeekFrom::Start((offset * 4) as u64))
+            .seek(SeekFrom::Start(u64::from(offset) * 4))
             .unwrap();
         use std::io::Read;
         idx_borrow.read_exact(&mut header[..]).unwrap();
@@ -151,15 +188,14 @@ impl Segment for FileSegment {
         let message_start = header_bytes.get_u32_le();
         let mut dat_borrow = self.dat.borrow_mut();
         dat_borrow
-            .seek(SeekFrom::Start(message_start as u64))
+            .seek(SeekFrom::Start(u64::from(message_start)))
             .unwrap();
         dat_borrow.read_exact(&mut header[..]).unwrap();
         let mut header_bytes = ::bytes::Bytes::from(&header[..]).into_buf();
         let message_size = header_bytes.get_u32_le();
         let mut message_buffer = vec![0u8; message_size as usize];
         dat_borrow.read_exact(&mut message_buffer[..]).unwrap();
-        let mut message_bytes = message_buffer.into_buf();
-        Some(decode_message(&mut message_bytes))
+        Some(self.codec.decode(&message_buffer).expect("Error decoding segment message"))
     }
 
     fn size(&self) -> u32 {
@@ -167,34 +203,28 @@ impl Segment for FileSegment {
     }
 }
 
-pub struct FileSegmentIter<'a> {
+fn temp_directory() -> PathBuf {
+    ::std::env::temp_dir().join(::uuid::Uuid::new_v4().hyphenated().to_string())
+}
+
+pub struct FileSegmentIter<'a, C: 'a> {
     range: ::std::ops::Range<u32>,
-    segment: &'a FileSegment,
+    segment: &'a FileSegment<C>,
 }
 
-impl<'a> Iterator for FileSegmentIter<'a> {
-    type Item = (u32, Message);
+impl<'a, C: Codec> Iterator for FileSegmentIter<'a, C> {
+    type Item = (u32, OwnedMessage);
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(index) = self.range.next() {
-            let result = self.segment.read(index);
-            return match result {
-                Some(message) => Some((index, message)),
-                None => None,
-            };
+            return self.segment.read(index).map(|message| (index, message));
         }
         None
     }
 
     fn nth(&mut self, n: usize) -> Option<Self::Item> {
         match self.range.nth(n) {
-            Some(index) => {
-                let result = self.segment.read(index);
-                match result {
-                    Some(message) => Some((index, message)),
-                    None => None,
-                }
-            }
+            Some(index) => self.segment.read(index).map(|message| (index, message)),
             None => None,
         }
     }
@@ -204,14 +234,10 @@ impl<'a> Iterator for FileSegmentIter<'a> {
     //    }
 }
 
-impl<'a> DoubleEndedIterator for FileSegmentIter<'a> {
+impl<'a, C: Codec> DoubleEndedIterator for FileSegmentIter<'a, C> {
     fn next_back(&mut self) -> Option<Self::Item> {
         if let Some(index) = self.range.next_back() {
-            let result = self.segment.read(index);
-            return match result {
-                Some(message) => Some((index, message)),
-                None => None,
-            };
+            return self.segment.read(index).map(|message| (index, message));
         }
         None
     }
@@ -220,7 +246,9 @@ impl<'a> DoubleEndedIterator for FileSegmentIter<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use message::Value;
+    use codec::compact::CompactMessageCodec;
+    use codec::compression::{Algorithm, Compression};
+    use message::message::{Key, MessageBuilder, Value};
 
     #[test]
     fn size_of_empty_segment() {
@@ -232,10 +260,10 @@ mod test {
     #[test]
     fn size_of_segment_with_messages() {
         let segment = FileSegment::with_temp_directory();
-        let message = Message::new().build();
-        &segment.write(&message);
+        let message = Message::new();
+        segment.write(&message);
         assert_eq!(segment.size(), 1);
-        &segment.write(&message);
+        segment.write(&message);
         assert_eq!(segment.size(), 2);
         segment.delete().unwrap();
     }
@@ -244,10 +272,10 @@ mod test {
     fn size_of_existng_segment() {
         let segment = FileSegment::with_temp_directory();
         let path = segment.directory().to_owned();
-        let message = Message::new().build();
-        &segment.write(&message);
+        let message = Message::new();
+        segment.write(&message);
         assert_eq!(segment.size(), 1);
-        &segment.write(&message);
+        segment.write(&message);
         assert_eq!(segment.size(), 2);
         drop(segment);
         let segment = FileSegment::with_directory(path);
@@ -259,7 +287,7 @@ mod test {
     fn write_single_message() {
         let segment = FileSegment::with_temp_directory();
         let path = segment.directory().to_owned();
-        let message = Message::new().with_body("Hello, World").build();
+        let message = MessageBuilder::new().with_body("Hello, World").build();
         segment.write(&message);
         drop(segment);
         let mut dat = OpenOptions::new()
@@ -267,7 +295,7 @@ mod test {
             .create(false)
             .open(path.join("segment.dat"))
             .unwrap();
-        let _ = dat.seek(SeekFrom::Current(0)).unwrap();
+        let _ = dat.stream_position().unwrap();
 
         let mut buffer = [0u8; 4];
         use std::io::Read;
@@ -279,12 +307,10 @@ mod test {
         let mut buf = vec![0u8; message_size as usize];
         dat.read_exact(&mut buf[..]).unwrap();
 
-        let mut bytes = buf.into_buf();
-
-        let output = ::codec::decode_message(&mut bytes);
+        let output = BinaryMessageCodec::new().decode(&buf).unwrap();
         assert_eq!(message, output);
         assert_eq!(message.body(), Some(&Value::from("Hello, World")));
-        assert_eq!(message.properties().len(), 0);
+        assert_eq!(message.headers().len(), 0);
 
         let segment = FileSegment::with_directory(path);
         segment.delete().unwrap();
@@ -299,7 +325,7 @@ mod test {
 
     #[test]
     fn read_multiple_from_first_offset() {
-        let input = Message::with_body("Hello").build();
+        let input = MessageBuilder::new().with_body("Hello").build();
         let segment = FileSegment::with_temp_directory();
         //        assert_eq!(segment.size(), 0);
         segment.write(&input);
@@ -314,10 +340,10 @@ mod test {
 
     #[test]
     fn read_multiple_from_second_offset() {
-        let message1 = Message::with_body("Hello").build();
+        let message1 = MessageBuilder::new().with_body("Hello").build();
         let segment = FileSegment::with_temp_directory();
         segment.write(&message1);
-        let message2 = Message::with_body("World").build();
+        let message2 = MessageBuilder::new().with_body("World").build();
         segment.write(&message2);
         let output1 = segment.read(0);
         let output2 = segment.read(1);
@@ -329,11 +355,48 @@ mod test {
         segment.delete().unwrap();
     }
 
+    #[test]
+    fn append_message_set() {
+        let segment = FileSegment::with_temp_directory();
+        segment.write(&MessageBuilder::new().with_body("first").build());
+        let messages = MessageSet::starting_at(0)
+            .append(MessageBuilder::new().with_body("Hello").build())
+            .append(MessageBuilder::new().with_header("iter", 1).build())
+            .build();
+        assert_eq!(segment.append(&messages), 1);
+        assert_eq!(segment.size(), 3);
+        let output: Vec<Message> = segment.iter().skip(1).map(|(_, message)| message).collect();
+        assert_eq!(output, messages.into_messages());
+        segment.delete().unwrap();
+    }
+
+    #[test]
+    fn segment_with_codec() {
+        let segment = FileSegment::with_codec(temp_directory(), CompactMessageCodec::new());
+        let message = MessageBuilder::new().with_header("iter", 7).with_body("Hello").build();
+        segment.write(&message);
+        segment.write(&message);
+        assert_eq!(segment.read(1), Some(message));
+        segment.delete().unwrap();
+    }
+
+    #[test]
+    fn segment_with_compression() {
+        let compression = Compression::new(Algorithm::Lz4).with_threshold(0);
+        let codec = BinaryMessageCodec::new().with_compression(compression);
+        let segment = FileSegment::with_codec(temp_directory(), codec);
+        let message = MessageBuilder::new().with_body("Hello ".repeat(100)).build();
+        segment.write(&message);
+        segment.write(&message);
+        assert_eq!(segment.read(1), Some(message));
+        segment.delete().unwrap();
+    }
+
     #[test]
     fn with_temp_directory() {
         let segment = FileSegment::with_temp_directory();
         eprintln!("segment.directory() = {:?}", segment.directory());
-        let message = Message::with_body("Test").build();
+        let message = MessageBuilder::new().with_body("Test").build();
         segment.write(&message);
         segment.delete().unwrap();
     }
@@ -342,7 +405,7 @@ mod test {
     fn iterate_file_segment() {
         let segment = FileSegment::with_temp_directory();
         for i in 0..100 {
-            let message = Message::with_body("Hello").with_property("iter", i).build();
+            let message = MessageBuilder::new().with_body("Hello").with_header("iter", i).build();
             segment.write(&message);
         }
 
@@ -352,7 +415,7 @@ mod test {
             eprintln!(
                 "offset: {}, iter = {:?}",
                 offset,
-                message.properties().get("iter").unwrap()
+                message.headers().get(&Key::from("iter")).unwrap()
             );
             counter += 1;
         }
@@ -370,7 +433,7 @@ mod test {
             eprintln!(
                 "offset: {}, iter = {:?}",
                 offset,
-                message.properties().get("iter").unwrap()
+                message.headers().get(&Key::from("iter")).unwrap()
             );
             counter -= 1;
         }
@@ -417,7 +480,7 @@ mod test {
     fn example_segment() -> FileSegment {
         let segment = FileSegment::with_temp_directory();
         for i in 0..100 {
-            let message = Message::with_body("Hello").with_property("iter", i).build();
+            let message = MessageBuilder::new().with_body("Hello").with_header("iter", i).build();
             segment.write(&message);
         }
         segment
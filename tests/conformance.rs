@@ -0,0 +1,308 @@
+//! Conformance corpus for the binary message format.
+//!
+//! Every case below has a golden file in `tests/golden/binary` holding its encoding.  Each
+//! golden file must decode to the case's message and re-encode to the same bytes, so a change
+//! to the wire format fails here instead of silently breaking stored segments and other
+//! implementations.  Compressed cases are only decoded: the output of a compressor may change
+//! from one version of it to the next, and all that has to hold is that it stays readable.
+//!
+//! Golden files are never rewritten.  Running with `HYDRAMQ_BLESS=1` writes the files of new
+//! cases only; a case whose encoding is meant to change needs a new name, and the old file
+//! should stay for as long as the decoder has to read it.
+
+extern crate bytes;
+extern crate chrono;
+extern crate hydramq;
+extern crate uuid;
+
+use bytes::BytesMut;
+use chrono::{TimeZone, UTC};
+use hydramq::codec::compression::{Algorithm, Compression};
+use hydramq::codec::limits::DecodeLimits;
+use hydramq::codec::message_codec::BinaryMessageCodec;
+use hydramq::codec::util::Flags;
+use hydramq::codec::Codec;
+use hydramq::message::message::{
+    Decimal, ListBuilder, Map, MapBuilder, Message, MessageBuilder, Value,
+};
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+struct Case {
+    name: String,
+    message: Message<'static>,
+    codec: BinaryMessageCodec,
+    /// Whether the message holds a NaN, which never compares equal to itself, so only the
+    /// re-encoded bytes are compared.
+    has_nan: bool,
+}
+
+impl Case {
+    fn new<S: Into<String>>(name: S, message: Message<'static>) -> Case {
+        Case {
+            name: name.into(),
+            message,
+            codec: BinaryMessageCodec::new(),
+            has_nan: false,
+        }
+    }
+
+    fn body<S: Into<String>, V: Into<Value<'static>>>(name: S, body: V) -> Case {
+        Case::new(name, MessageBuilder::new().with_body(body).build())
+    }
+
+    fn with_codec(mut self, codec: BinaryMessageCodec) -> Case {
+        self.codec = codec;
+        self
+    }
+
+    fn with_nan(mut self) -> Case {
+        self.has_nan = true;
+        self
+    }
+
+    fn path(&self) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/golden/binary");
+        path.push(format!("{}.bin", self.name));
+        path
+    }
+
+    fn encode(&self, message: &Message) -> Vec<u8> {
+        let mut buffer = BytesMut::new();
+        self.codec.encode(message, &mut buffer);
+        buffer.to_vec()
+    }
+
+    fn check(&self) {
+        let path = self.path();
+        if !path.exists() && env::var_os("HYDRAMQ_BLESS").is_some() {
+            fs::write(&path, self.encode(&self.message)).expect("Error writing golden file");
+        }
+        let golden = fs::read(&path)
+            .unwrap_or_else(|e| panic!("Missing golden file {}: {}", path.display(), e));
+        let compressed = self.codec.compression().is_some();
+
+        if !compressed {
+            assert_eq!(self.encode(&self.message), golden, "encoding of {}", self.name);
+        }
+
+        let decoded = self.codec.decode(&golden).unwrap_or_else(|e| {
+            panic!("Error decoding {}: {}", self.name, e)
+        });
+        if !compressed {
+            assert_eq!(self.encode(&decoded), golden, "re-encoding of {}", self.name);
+        }
+        if !self.has_nan {
+            assert_eq!(decoded, self.message, "decoding of {}", self.name);
+        }
+
+        let borrowed = self.codec.decode_borrowed(&golden).unwrap_or_else(|e| {
+            panic!("Error decoding {} borrowed: {}", self.name, e)
+        });
+        let expected = if compressed { self.encode(&decoded) } else { golden.clone() };
+        assert_eq!(self.encode(&borrowed), expected, "re-encoding of borrowed {}", self.name);
+    }
+}
+
+fn value_cases() -> Vec<Case> {
+    vec![
+        Case::body("value-null", Value::Null),
+        Case::body("value-str", "hello"),
+        Case::body("value-i32", -123_456),
+        Case::body("value-i64", 1i64 << 40),
+        Case::body("value-f32", 1.5f32),
+        Case::body("value-f64", -2.25f64),
+        Case::body("value-bool-true", true),
+        Case::body("value-bool-false", false),
+        Case::body("value-bytes", Value::Bytes(Cow::Borrowed(&[0u8, 1, 2, 0xff][..]))),
+        Case::body(
+            "value-list",
+            ListBuilder::new().push(1).push("two").push(Value::Null).build(),
+        ),
+        Case::body("value-map", MapBuilder::new().insert("a", 1).insert(2, "b").build()),
+        Case::body("value-uuid", Value::Uuid(uuid())),
+        Case::body("value-timestamp", UTC.timestamp(1_500_000_000, 123_456_789)),
+        Case::body("value-decimal", Decimal::new(-12_345, 2)),
+        Case::body("value-duration", Duration::new(90, 500)),
+        Case::body("value-i64-array", vec![1i64, -2, i64::MAX]),
+        Case::body("value-f64-array", vec![0.5f64, -1.0, 1e300]),
+    ]
+}
+
+fn key_cases() -> Vec<Case> {
+    vec![
+        Case::new("key-str", MessageBuilder::new().with_header("key", "value").build()),
+        Case::new("key-str-empty", MessageBuilder::new().with_header("", "value").build()),
+        Case::new("key-i32", MessageBuilder::new().with_header(7, "value").build()),
+        Case::new(
+            "key-i32-min",
+            MessageBuilder::new().with_header(i32::MIN, "value").build(),
+        ),
+        Case::new(
+            "key-order",
+            MessageBuilder::new()
+                .with_header("z", 1)
+                .with_header(3, 2)
+                .with_header("a", 3)
+                .with_header(-1, 4)
+                .build(),
+        ),
+    ]
+}
+
+/// One case for every consistent combination of flags.  Compressed sections are written with
+/// LZ4.
+fn flag_cases() -> Vec<(Flags, Case)> {
+    (0..=Flags::all().bits())
+        .filter_map(Flags::from_bits)
+        .filter(|flags| flags.is_consistent())
+        .map(|flags| {
+            let mut message = Message::new();
+            if flags.contains(Flags::HAS_TIMESTAMP) {
+                message.set_timestamp(Some(UTC.timestamp(1_500_000_000, 123_456_789)));
+            }
+            if flags.contains(Flags::HAS_EXPIRATION) {
+                message.set_expiration(Some(UTC.timestamp(1_500_000_060, 0)));
+            }
+            if flags.contains(Flags::HAS_CORRELATION_ID) {
+                message.set_correlation_id(Some(uuid()));
+            }
+            if flags.contains(Flags::HEADERS_COMPRESSED) {
+                message.headers_mut().insert("text", "header ".repeat(20));
+            } else if flags.contains(Flags::HAS_HEADERS) {
+                message.headers_mut().insert("key", "value");
+            }
+            if flags.contains(Flags::BODY_COMPRESSED) {
+                message.set_body(Some("body ".repeat(20)));
+            } else if flags.contains(Flags::HAS_BODY) {
+                message.set_body(Some(42));
+            }
+
+            let mut case = Case::new(format!("flags-{:02x}", flags.bits()), message);
+            if flags.intersects(Flags::HEADERS_COMPRESSED | Flags::BODY_COMPRESSED) {
+                let compression = Compression::new(Algorithm::Lz4)
+                    .with_threshold(0)
+                    .with_headers(flags.contains(Flags::HEADERS_COMPRESSED));
+                case = case.with_codec(BinaryMessageCodec::new().with_compression(compression));
+            }
+            (flags, case)
+        })
+        .collect()
+}
+
+fn nested_lists(depth: usize) -> Value<'static> {
+    (0..depth).fold(Value::Null, |value, _| ListBuilder::new().push(value).build().into())
+}
+
+fn nested_maps(depth: usize) -> Value<'static> {
+    (0..depth).fold(Value::Null, |value, _| MapBuilder::new().insert("m", value).build().into())
+}
+
+fn edge_cases() -> Vec<Case> {
+    let max_len = 4096;
+    vec![
+        Case::body("empty-map", Map::new()),
+        Case::body("empty-list", ListBuilder::new().build()),
+        Case::body("empty-str", ""),
+        Case::body("empty-bytes", Value::Bytes(Cow::Borrowed(&[][..]))),
+        Case::body("empty-i64-array", Vec::<i64>::new()),
+        Case::body("empty-f64-array", Vec::<f64>::new()),
+        Case::body("nested-lists", nested_lists(64)),
+        Case::body("nested-maps", nested_maps(64)),
+        Case::new(
+            "nested-headers",
+            MessageBuilder::new()
+                .with_header("list", nested_lists(63))
+                .with_header("map", nested_maps(63))
+                .build(),
+        ),
+        Case::body("str-unicode", "h\u{e9}llo w\u{f6}rld \u{1f980}"),
+        Case::body("str-max-length", "x".repeat(max_len))
+            .with_codec(BinaryMessageCodec::with_limits(
+                DecodeLimits::new().with_max_bytes_len(max_len),
+            )),
+        Case::body("f32-nan", f32::from_bits(0x7fc0_0000)).with_nan(),
+        Case::body("f32-nan-payload", f32::from_bits(0xffc0_0001)).with_nan(),
+        Case::body("f64-nan", f64::from_bits(0x7ff8_0000_0000_0000)).with_nan(),
+        Case::body("f64-nan-payload", f64::from_bits(0xfff8_0000_0000_0001)).with_nan(),
+        Case::body("f64-array-nan", vec![0.0f64, f64::NAN]).with_nan(),
+        Case::body("f32-negative-zero", -0.0f32),
+        Case::body("f64-negative-zero", -0.0f64),
+        Case::body("f32-infinity", f32::NEG_INFINITY),
+        Case::body("f64-infinity", f64::INFINITY),
+        Case::body("f64-subnormal", f64::from_bits(1)),
+        Case::body("i32-min", i32::MIN),
+        Case::body("i32-max", i32::MAX),
+        Case::body("i64-min", i64::MIN),
+        Case::body("i64-max", i64::MAX),
+        Case::body("timestamp-epoch", UTC.timestamp(0, 0)),
+        Case::body("timestamp-negative", UTC.timestamp(-1, 500_000_000)),
+        Case::body("timestamp-far-past", UTC.timestamp(-62_135_596_800, 0)),
+        Case::body("timestamp-far-future", UTC.timestamp(253_402_300_799, 999_999_999)),
+        Case::body("decimal-max", Decimal::new(i128::MAX, 38)),
+        Case::body("decimal-min", Decimal::new(i128::MIN, 255)),
+        Case::body("duration-max", Duration::new(u64::MAX, 999_999_999)),
+        Case::new(
+            "all-fields",
+            MessageBuilder::new()
+                .with_timestamp(UTC.timestamp(1_500_000_000, 0))
+                .with_expiration(UTC.timestamp(1_500_000_060, 0))
+                .with_correlation_id(uuid())
+                .with_header("key", "value")
+                .with_header(-7, i64::MIN)
+                .with_body(
+                    MapBuilder::new()
+                        .insert("nested", ListBuilder::new().push(1.5).build())
+                        .build(),
+                )
+                .build(),
+        ),
+        Case::body("checksum", "checked")
+            .with_codec(BinaryMessageCodec::new().with_checksum(true)),
+        Case::body("deflate", "body ".repeat(20)).with_codec(
+            BinaryMessageCodec::new()
+                .with_compression(Compression::new(Algorithm::Deflate).with_threshold(0)),
+        ),
+    ]
+}
+
+fn uuid() -> Uuid {
+    Uuid::parse_str("6ba7b810-9dad-11d1-80b4-00c04fd430c8").unwrap()
+}
+
+#[test]
+fn value_tags() {
+    for case in value_cases() {
+        case.check();
+    }
+}
+
+#[test]
+fn key_types() {
+    for case in key_cases() {
+        case.check();
+    }
+}
+
+#[test]
+fn flag_combinations() {
+    let cases = flag_cases();
+    assert_eq!(cases.len(), 72);
+    for (flags, case) in cases {
+        case.check();
+        let golden = fs::read(case.path()).unwrap();
+        assert_eq!(&golden[..4], &flags.bits().to_be_bytes(), "{}", case.name);
+    }
+}
+
+#[test]
+fn edge_values() {
+    for case in edge_cases() {
+        case.check();
+    }
+}